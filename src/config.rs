@@ -0,0 +1,42 @@
+use std::{ fs, path::{ Path, PathBuf } };
+use directories::ProjectDirs;
+use serde::{ Deserialize, Serialize };
+
+use crate::MyError;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub last_volumes: Vec<f32>,
+    pub last_output_dir: Option<PathBuf>,
+    // Turned off with the checkbox below the job buttons
+    #[serde(default)]
+    pub disable_notifications: bool,
+}
+
+pub fn default_config_path() -> PathBuf {
+    match ProjectDirs::from("", "", "video_editor") {
+        Some(dirs) => dirs.config_dir().join("config.toml"),
+        None => PathBuf::from("video_editor.toml"),
+    }
+}
+
+impl Config {
+    // A missing or unreadable config just means we start from the defaults
+    pub fn load(path: &Path) -> Config {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MyError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let contents = toml::to_string(self).map_err(|e| MyError::Other(e.to_string()))?;
+        fs::write(path, contents)?;
+
+        Ok(())
+    }
+}
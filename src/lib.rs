@@ -0,0 +1,6316 @@
+// The combine pipeline behind the video_editor CLI and GUI, usable on its own through
+// `combine_videos` or step by step. Needs ffmpeg and ffprobe on the PATH.
+
+use std::cell::Cell;
+use std::collections::{ hash_map::DefaultHasher, VecDeque };
+use std::hash::{ Hash, Hasher };
+use std::fmt;
+use std::{
+    error::Error,
+    ffi::OsString,
+    ops::Range,
+    path::{ Path, PathBuf },
+    process::{ Child, Command, ExitStatus, Stdio },
+    io::BufWriter,
+};
+use std::io::{ BufRead, BufReader, Read, Write };
+use std::sync::{ atomic::{ AtomicBool, AtomicU8, AtomicUsize, Ordering }, Mutex, OnceLock };
+use std::thread;
+use std::time::{ Duration, Instant, SystemTime };
+use serde::{ Deserialize, Serialize };
+
+// Set by --json, keeps stdout free for the final JSON object
+pub(crate) static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+// Set when the combined video is written to stdout, messages then go to stderr
+pub(crate) static PIPE_OUTPUT: AtomicBool = AtomicBool::new(false);
+pub(crate) static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Normal as u8);
+// Every ffmpeg process that is running, so a cancelled job can kill them
+static RUNNING_CHILDREN: Mutex<Vec<Child>> = Mutex::new(Vec::new());
+pub(crate) static CANCEL_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+// Moves every message to stderr so stdout only holds the final JSON object
+pub fn set_json_output(json: bool) {
+    JSON_OUTPUT.store(json, Ordering::Relaxed);
+}
+
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+// Moves every message to stderr so stdout only holds the output file
+pub fn set_pipe_output(pipe: bool) {
+    PIPE_OUTPUT.store(pipe, Ordering::Relaxed);
+}
+
+pub fn pipe_output() -> bool {
+    PIPE_OUTPUT.load(Ordering::Relaxed)
+}
+
+// Lets the next job run after an earlier one was cancelled
+pub fn clear_cancel_request() {
+    CANCEL_REQUESTED.store(false, Ordering::SeqCst);
+}
+// Set by --ffmpeg-bin and --ffprobe-bin, they win over FFMPEG_PATH and FFPROBE_PATH
+static FFMPEG_BIN: Mutex<Option<PathBuf>> = Mutex::new(None);
+static FFPROBE_BIN: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Quiet,
+    Normal,
+    Verbose,
+    Debug,
+}
+
+impl LogLevel {
+    pub fn from_flags(verbose: u8, quiet: bool) -> LogLevel {
+        match (quiet, verbose) {
+            (true, _) => LogLevel::Quiet,
+            (false, 0) => LogLevel::Normal,
+            (false, 1) => LogLevel::Verbose,
+            (false, _) => LogLevel::Debug,
+        }
+    }
+
+    pub fn current() -> LogLevel {
+        match LOG_LEVEL.load(Ordering::Relaxed) {
+            0 => LogLevel::Quiet,
+            1 => LogLevel::Normal,
+            2 => LogLevel::Verbose,
+            _ => LogLevel::Debug,
+        }
+    }
+
+    fn ffmpeg_loglevel(&self) -> &'static str {
+        match self {
+            LogLevel::Quiet => "error",
+            LogLevel::Normal => "warning",
+            LogLevel::Verbose => "info",
+            LogLevel::Debug => "debug",
+        }
+    }
+}
+
+// What the logging macros expand to, public only so the binary can log the same way
+#[doc(hidden)]
+pub mod logging {
+    // println! for messages, moved to stderr in JSON or pipe mode
+    pub fn print_line(message: std::fmt::Arguments) {
+        if crate::json_output() || crate::pipe_output() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+// Messages at the given level, not part of the API of the library
+#[doc(hidden)]
+#[macro_export]
+macro_rules! log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::LogLevel::current() >= $level {
+            $crate::logging::print_line(format_args!($($arg)*));
+        }
+    };
+}
+
+// Progress and results the user asked for, silenced by --quiet
+#[doc(hidden)]
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::LogLevel::Normal, $($arg)*)
+    };
+}
+
+// Chatter that only helps when debugging, shown with --verbose
+#[doc(hidden)]
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::log_at!($crate::LogLevel::Verbose, $($arg)*)
+    };
+}
+
+// Declared after the macros so they can log with them
+pub mod progress_log;
+pub mod watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct TrimRange {
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+}
+
+impl TrimRange {
+    fn duration_us(&self, source_us: u64) -> u64 {
+        let source_seconds = (source_us as f64) / 1_000_000.0;
+        let start = self.start.unwrap_or(0.0).clamp(0.0, source_seconds);
+        let end = self.end.unwrap_or(source_seconds).min(source_seconds);
+
+        ((end - start).max(0.0) * 1_000_000.0) as u64
+    }
+}
+
+// Anything louder than this clips badly, negative volumes make no sense
+pub const MIN_VOLUME: f32 = 0.0;
+pub const MAX_VOLUME: f32 = 4.0;
+
+pub fn format_trim_bound(bound: Option<f64>) -> String {
+    bound.map(|b| b.to_string()).unwrap_or_default()
+}
+
+impl fmt::Display for TrimRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", format_trim_bound(self.start), format_trim_bound(self.end))
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EncodeOptions {
+    pub volumes: Vec<f32>,
+    // Measures the tracks that have no volume in `volumes`
+    pub auto_gain: bool,
+    // Picks the two tracks that are used, `volumes` then follows this order instead
+    pub track_roles: Option<TrackRoles>,
+    // Leaves out the background track (the first one, or the one picked by `track_roles`)
+    pub drop_background: bool,
+    // Parallel to the input files, None keeps the whole clip
+    pub trims: Vec<Option<TrimRange>>,
+    // Parallel to the input files as well, multiplies the track volumes of that clip.
+    // Missing entries are 1.0.
+    pub clip_volumes: Vec<f32>,
+    pub intermediate_container: IntermediateContainer,
+    pub intermediate_audio: IntermediateAudio,
+    // Vorbis -q:a of the intermediate audio, None leaves the ffmpeg default
+    pub intermediate_quality: Option<f32>,
+    pub audio_codec: AudioCodec,
+    // Bitrate of the output audio in bits per second, None for the encoder's default
+    pub audio_bitrate: Option<u32>,
+    pub aac_profile: Option<AacProfile>,
+    pub mix_mode: MixMode,
+    // What the final audio is resampled to before the mux, None for DEFAULT_SAMPLE_RATE and
+    // stereo. Merge keeps the channels of the tracks unless a count is given.
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+    // None keeps the fast stream copy concat at the native resolution
+    pub target_resolution: Option<(u32, u32)>,
+    // None keeps the native frame rate, clips that differ then need the concat filter
+    pub target_fps: Option<f64>,
+    pub encoder: Encoder,
+    pub video_quality: Option<VideoQuality>,
+    pub preset: Preset,
+    // Size in bytes the output should end up at, encoded in two passes instead of by quality
+    pub target_size: Option<u64>,
+    pub concat_strategy: ConcatStrategy,
+    pub subtitles: SubtitleMode,
+    // Adds a chapter at the start of every clip
+    pub chapters: bool,
+    // Burns the file name of every clip into its corner, which needs re-encoding
+    pub label_clips: bool,
+    // Adds the running time of the output below the file name
+    pub label_time: bool,
+    // Skips every video step and writes the final audio as the output
+    pub audio_only: bool,
+    // Seconds consecutive clips fade into each other, None for hard cuts
+    pub crossfade: Option<f64>,
+    // Target integrated loudness in LUFS when normalizing the final audio
+    pub loudness_target: Option<f32>,
+    // Seconds the final audio fades in at the start and out at the end
+    pub fade_in: Option<f64>,
+    pub fade_out: Option<f64>,
+    pub keep_temp: bool,
+    // Keeps the finished steps of a failed job and reuses them when the same job runs again
+    pub resume: bool,
+    // Skips fitting the audio of every clip to its video, faster, but the audio can drift from
+    // the video over long jobs
+    pub no_pad: bool,
+    // Threads for every encoding ffmpeg call, 0 lets ffmpeg decide
+    pub threads: u32,
+    // Whether an existing output file may be replaced, the job fails before starting otherwise
+    pub overwrite: bool,
+    // Only used when the output goes to stdout
+    pub pipe_format: PipeFormat,
+    // Passed as they are to the ffmpeg call that writes the output, e.g. `-metadata title=x`
+    pub extra_ffmpeg_args: Vec<String>,
+    pub metadata: OutputMetadata,
+    // Most clips a filter that opens all of them at once gets, 0 for DEFAULT_BATCH_SIZE
+    pub batch_size: usize,
+    // Width of a GIF or WebP output when no resolution is given, None for the default
+    pub animation_width: Option<u32>,
+    // Plays the only input this many times in a row, 0 and 1 play it once
+    pub repeat: usize,
+    // Converts HDR clips to SDR instead of keeping their color tags
+    pub tonemap: bool,
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackRoles {
+    pub background: usize,
+    pub voiceover: usize,
+}
+
+impl TrackRoles {
+    pub fn new(background: usize, voiceover: usize) -> Result<TrackRoles, MyError> {
+        if background == voiceover {
+            return Err(
+                MyError::InvalidInput(
+                    format!("The background and voiceover track can't both be track {}", background)
+                )
+            );
+        }
+        Ok(TrackRoles { background, voiceover })
+    }
+
+    // The tracks to extract, background first
+    fn tracks(&self, input: &Path, track_count: usize) -> Result<Vec<usize>, MyError> {
+        for track in [self.background, self.voiceover] {
+            if track >= track_count {
+                return Err(
+                    MyError::InvalidInput(
+                        format!(
+                            "{} has {} audio tracks, there is no track {}",
+                            input.display(),
+                            track_count,
+                            track
+                        )
+                    )
+                );
+            }
+        }
+        Ok(vec![self.background, self.voiceover])
+    }
+}
+
+// What a finished job produced, printed as is by --json
+#[derive(Debug, Default, Serialize)]
+pub struct CombineSummary {
+    pub output: PathBuf,
+    // Every input once, even when it was repeated
+    pub inputs: Vec<PathBuf>,
+    // Times the inputs play in a row, 1 unless --repeat was given
+    pub repeat: usize,
+    pub duration_seconds: f64,
+    pub temp_files_removed: Vec<PathBuf>,
+    // How long the whole job took and where that time went
+    pub elapsed_seconds: f64,
+    pub stages: Vec<StageTime>,
+}
+
+// Time spent in one pipeline stage, added up over every clip it ran for
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StageTime {
+    pub stage: String,
+    pub runs: usize,
+    pub seconds: f64,
+}
+
+thread_local! {
+    // Time the steps nested in the one running on this thread took, so it can leave it out
+    static NESTED_STAGE_TIME: Cell<Duration> = const { Cell::new(Duration::ZERO) };
+}
+
+// Collects the stage times of one job, shared by the threads that extract the audio
+#[derive(Default)]
+struct StageTimes {
+    times: Mutex<Vec<StageTime>>,
+}
+
+impl StageTimes {
+    // progress_log::step that also adds the time it took to its stage. A step inside another
+    // one (merge within extract) only counts for the inner stage.
+    fn step<T>(
+        &self,
+        stage: &str,
+        input: Option<&Path>,
+        run: impl FnOnce() -> Result<T, MyError>
+    ) -> Result<T, MyError> {
+        let started = Instant::now();
+        let outer_nested = NESTED_STAGE_TIME.with(|nested| nested.replace(Duration::ZERO));
+        let result = progress_log::step(stage, input, run);
+        let elapsed = started.elapsed();
+        let nested = NESTED_STAGE_TIME.with(|nested| nested.replace(outer_nested + elapsed));
+
+        let seconds = elapsed.saturating_sub(nested).as_secs_f64();
+        let mut times = self.times.lock().unwrap();
+        match times.iter_mut().find(|time| time.stage == stage) {
+            Some(time) => {
+                time.runs += 1;
+                time.seconds += seconds;
+            }
+            None => times.push(StageTime { stage: stage.to_string(), runs: 1, seconds }),
+        }
+        result
+    }
+
+    fn into_times(self) -> Vec<StageTime> {
+        self.times.into_inner().unwrap()
+    }
+}
+
+// The table printed after a job. Clips are worked on in parallel, so the stages can add up to
+// more than the total.
+pub fn format_stage_times(stages: &[StageTime], elapsed_seconds: f64) -> String {
+    let width = stages
+        .iter()
+        .map(|time| time.stage.len())
+        .chain(["total".len()])
+        .max()
+        .unwrap_or(0);
+    let mut table = String::from("Time per stage:");
+    for time in stages {
+        table.push_str(&format!("\n  {:<width$} {:>8.1}s", time.stage, time.seconds));
+        if time.runs > 1 {
+            table.push_str(&format!(" ({} runs)", time.runs));
+        }
+    }
+    table.push_str(&format!("\n  {:<width$} {:>8.1}s", "total", elapsed_seconds));
+    table
+}
+
+// Measurements printed by the first loudnorm pass, ffmpeg reports them as strings
+#[derive(Debug, Deserialize)]
+struct LoudnormStats {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum IntermediateContainer {
+    Mkv,
+    Mp4,
+    // MPEG-TS is the safest choice for the concat demuxer with stream copy
+    #[default]
+    Ts,
+}
+
+impl IntermediateContainer {
+    fn extension(&self) -> &'static str {
+        match self {
+            IntermediateContainer::Mkv => "mkv",
+            IntermediateContainer::Mp4 => "mp4",
+            IntermediateContainer::Ts => "ts",
+        }
+    }
+}
+
+// Format of the per clip audio files, a lossless one keeps the final encode the only lossy step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum IntermediateAudio {
+    #[default]
+    Vorbis,
+    Wav,
+    Flac,
+}
+
+impl IntermediateAudio {
+    fn extension(&self) -> &'static str {
+        match self {
+            IntermediateAudio::Vorbis => "ogg",
+            IntermediateAudio::Wav => "wav",
+            IntermediateAudio::Flac => "flac",
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            IntermediateAudio::Vorbis => "libvorbis",
+            IntermediateAudio::Wav => "pcm_s16le",
+            IntermediateAudio::Flac => "flac",
+        }
+    }
+
+    fn codec_name(&self) -> &'static str {
+        match self {
+            IntermediateAudio::Vorbis => "vorbis",
+            IntermediateAudio::Wav => "pcm_s16le",
+            IntermediateAudio::Flac => "flac",
+        }
+    }
+}
+
+// How every intermediate audio file is encoded, the audio counterpart of VideoEncoding
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AudioEncoding {
+    pub format: IntermediateAudio,
+    // Vorbis -q:a, the lossless formats have no quality to pick
+    pub quality: Option<f32>,
+}
+
+impl AudioEncoding {
+    pub fn codec_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:a".to_string(), self.format.encoder().to_string()];
+        if let (IntermediateAudio::Vorbis, Some(quality)) = (self.format, self.quality) {
+            args.push("-q:a".to_string());
+            args.push(quality.to_string());
+        }
+        args
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum AudioCodec {
+    #[default]
+    Aac,
+    Opus,
+    Copy,
+    Mp3,
+}
+
+impl AudioCodec {
+    pub fn label(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "AAC",
+            AudioCodec::Opus => "Opus",
+            AudioCodec::Copy => "Copy",
+            AudioCodec::Mp3 => "MP3",
+        }
+    }
+
+    fn encoder(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Copy => "copy",
+            AudioCodec::Mp3 => "libmp3lame",
+        }
+    }
+
+    // The codec that ends up in the output, copy keeps the one of the intermediate audio
+    fn codec_name(&self, intermediate_audio: IntermediateAudio) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Copy => intermediate_audio.codec_name(),
+            AudioCodec::Mp3 => "mp3",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum AacProfile {
+    Low,
+    Main,
+    Ltp,
+    Mpeg2Low,
+}
+
+impl AacProfile {
+    fn name(&self) -> &'static str {
+        match self {
+            AacProfile::Low => "aac_low",
+            AacProfile::Main => "aac_main",
+            AacProfile::Ltp => "aac_ltp",
+            AacProfile::Mpeg2Low => "mpeg2_aac_low",
+        }
+    }
+}
+
+// Tags written to the output file
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct OutputMetadata {
+    // Drops the tags the clips came with, e.g. the recording device, creation time or location
+    pub strip: bool,
+    pub title: Option<String>,
+    // Written as the artist, the tag players show
+    pub author: Option<String>,
+}
+
+impl OutputMetadata {
+    fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        if self.strip {
+            // Global tags and the ones of every stream are copied separately
+            args.extend(["-map_metadata", "-1", "-map_metadata:s", "-1"].map(str::to_string));
+        }
+        if let Some(title) = &self.title {
+            args.extend(["-metadata".to_string(), format!("title={}", title)]);
+        }
+        if let Some(author) = &self.author {
+            args.extend(["-metadata".to_string(), format!("artist={}", author)]);
+        }
+        args
+    }
+}
+
+// How the audio of the output is encoded, the only lossy audio step by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OutputAudio {
+    pub codec: AudioCodec,
+    // Bits per second, None leaves it to the encoder
+    pub bitrate: Option<u32>,
+    pub aac_profile: Option<AacProfile>,
+}
+
+impl OutputAudio {
+    fn from_options(options: &EncodeOptions) -> OutputAudio {
+        OutputAudio {
+            codec: options.audio_codec,
+            bitrate: options.audio_bitrate,
+            aac_profile: options.aac_profile,
+        }
+    }
+
+    fn codec_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:a".to_string(), self.codec.encoder().to_string()];
+        if self.codec == AudioCodec::Copy {
+            return args;
+        }
+        if let Some(bitrate) = self.bitrate {
+            args.extend(["-b:a".to_string(), bitrate.to_string()]);
+        }
+        if let Some(aac_profile) = self.aac_profile {
+            args.extend(["-profile:a".to_string(), aac_profile.name().to_string()]);
+        }
+        args.extend(["-strict".to_string(), "experimental".to_string()]);
+        args
+    }
+
+    // A copied stream keeps its bitrate, and only aac has profiles
+    fn validate(&self) -> Result<(), MyError> {
+        if self.codec == AudioCodec::Copy && self.bitrate.is_some() {
+            return Err(
+                MyError::InvalidInput(
+                    "--audio-bitrate needs the audio to be encoded, not copied".to_string()
+                )
+            );
+        }
+        if self.codec != AudioCodec::Aac && self.aac_profile.is_some() {
+            return Err(
+                MyError::InvalidInput(
+                    format!("--aac-profile only works with aac, not {}", self.codec.label())
+                )
+            );
+        }
+        Ok(())
+    }
+}
+
+// How the audio tracks of a clip are combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum MixMode {
+    // Overlay every track and downmix to stereo
+    #[default]
+    Mix,
+    // Keep the channels of every track, a stereo and a mono track give 3 channels
+    Merge,
+}
+
+impl MixMode {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MixMode::Mix => "Mix (stereo)",
+            MixMode::Merge => "Merge channels",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum PipeFormat {
+    #[default]
+    Matroska,
+    Mpegts,
+    Webm,
+    // Only listed to explain why it doesn't work, mp4 needs to seek back to write its index
+    Mp4,
+}
+
+impl PipeFormat {
+    fn muxer(&self) -> &'static str {
+        match self {
+            PipeFormat::Matroska => "matroska",
+            PipeFormat::Mpegts => "mpegts",
+            PipeFormat::Webm => "webm",
+            PipeFormat::Mp4 => "mp4",
+        }
+    }
+
+    // Matches OUTPUT_CONTAINERS, so piped output is checked like a file
+    fn extension(&self) -> &'static str {
+        match self {
+            PipeFormat::Matroska => "mkv",
+            PipeFormat::Mpegts => "ts",
+            PipeFormat::Webm => "webm",
+            PipeFormat::Mp4 => "mp4",
+        }
+    }
+}
+
+pub fn is_stdout_output(output: &Path) -> bool {
+    output == Path::new("-") || output == Path::new("pipe:1")
+}
+
+pub fn validate_pipe_format(pipe_format: PipeFormat) -> Result<(), MyError> {
+    if pipe_format == PipeFormat::Mp4 {
+        return Err(
+            MyError::InvalidInput(
+                "mp4 can't be written to a pipe, use matroska, mpegts or webm instead".to_string()
+            )
+        );
+    }
+    Ok(())
+}
+
+// What an output container can hold, the codecs are ffmpeg codec names
+struct OutputContainer {
+    extension: &'static str,
+    // Audio only containers can't hold the video
+    video: bool,
+    // Empty when the container takes any codec
+    video_codecs: &'static [&'static str],
+    audio_codecs: &'static [&'static str],
+}
+
+const MP4_AUDIO: &[&str] = &["aac", "mp3", "opus", "flac"];
+
+const OUTPUT_CONTAINERS: [OutputContainer; 14] = [
+    OutputContainer { extension: "mp4", video: true, video_codecs: &[], audio_codecs: MP4_AUDIO },
+    OutputContainer { extension: "m4v", video: true, video_codecs: &[], audio_codecs: MP4_AUDIO },
+    OutputContainer { extension: "mkv", video: true, video_codecs: &[], audio_codecs: &[] },
+    OutputContainer {
+        extension: "mov",
+        video: true,
+        video_codecs: &[],
+        audio_codecs: &["aac", "mp3", "pcm_s16le"],
+    },
+    OutputContainer {
+        extension: "webm",
+        video: true,
+        video_codecs: &["vp8", "vp9", "av1"],
+        audio_codecs: &["opus", "vorbis"],
+    },
+    OutputContainer {
+        extension: "avi",
+        video: true,
+        video_codecs: &[],
+        audio_codecs: &["mp3", "aac", "pcm_s16le"],
+    },
+    OutputContainer {
+        extension: "ts",
+        video: true,
+        video_codecs: &[],
+        audio_codecs: &["aac", "mp3", "opus"],
+    },
+    OutputContainer { extension: "m4a", video: false, video_codecs: &[], audio_codecs: MP4_AUDIO },
+    OutputContainer { extension: "mka", video: false, video_codecs: &[], audio_codecs: &[] },
+    OutputContainer {
+        extension: "ogg",
+        video: false,
+        video_codecs: &[],
+        audio_codecs: &["vorbis", "opus", "flac"],
+    },
+    OutputContainer { extension: "opus", video: false, video_codecs: &[], audio_codecs: &["opus"] },
+    OutputContainer { extension: "mp3", video: false, video_codecs: &[], audio_codecs: &["mp3"] },
+    OutputContainer { extension: "flac", video: false, video_codecs: &[], audio_codecs: &["flac"] },
+    OutputContainer {
+        extension: "wav",
+        video: false,
+        video_codecs: &[],
+        audio_codecs: &["pcm_s16le"],
+    },
+];
+
+// ffmpeg guesses the muxer from the extension, an unknown one only fails once the output is
+// written. `video_codec` is None when no video is written.
+fn validate_output_container(
+    output_file: &Path,
+    pipe_format: PipeFormat,
+    video_codec: Option<&str>,
+    audio_codec: &str
+) -> Result<(), MyError> {
+    let extension = if is_stdout_output(output_file) {
+        pipe_format.extension().to_string()
+    } else {
+        output_file
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    };
+    let Some(container) = OUTPUT_CONTAINERS.iter().find(|c| c.extension == extension) else {
+        let known: Vec<&str> = OUTPUT_CONTAINERS.iter()
+            .map(|container| container.extension)
+            .collect();
+        return Err(
+            MyError::InvalidInput(
+                format!(
+                    "Can't tell the output container of {}, use one of: {}",
+                    output_file.display(),
+                    known.join(", ")
+                )
+            )
+        );
+    };
+
+    if let Some(video_codec) = video_codec {
+        if !container.video {
+            return Err(
+                MyError::InvalidInput(
+                    format!(
+                        "{} only holds audio, write just the audio or use a video container",
+                        extension
+                    )
+                )
+            );
+        }
+        if !container.video_codecs.is_empty() && !container.video_codecs.contains(&video_codec) {
+            return Err(
+                MyError::InvalidInput(
+                    format!(
+                        "{} can't hold {} video, only {}",
+                        extension,
+                        video_codec,
+                        container.video_codecs.join(", ")
+                    )
+                )
+            );
+        }
+    }
+    if !container.audio_codecs.is_empty() && !container.audio_codecs.contains(&audio_codec) {
+        return Err(
+            MyError::InvalidInput(
+                format!(
+                    "{} can't hold {} audio, only {}",
+                    extension,
+                    audio_codec,
+                    container.audio_codecs.join(", ")
+                )
+            )
+        );
+    }
+    Ok(())
+}
+
+// A looping animation instead of a video, picked by the output extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    // Two passes, the first one picks the 256 colors that fit the clips best
+    Gif,
+    // libwebp_anim, full color and usually much smaller than a GIF
+    Webp,
+}
+
+impl AnimationFormat {
+    pub fn from_output(output_file: &Path) -> Option<AnimationFormat> {
+        let extension = output_file.extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "webp" => Some(AnimationFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+// Animations get big quickly, so unless asked otherwise they are smaller and slower than video
+pub const DEFAULT_ANIMATION_FPS: f64 = 15.0;
+pub const DEFAULT_ANIMATION_WIDTH: u32 = 480;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum SubtitleMode {
+    // Keep the subtitle streams of the first clip
+    Copy,
+    #[default]
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConcatStrategy {
+    // Concat demuxer with stream copy, only correct when every clip matches
+    #[default]
+    StreamCopy,
+    // Concat filter, decodes every clip and encodes the result once
+    ReEncode,
+}
+
+// What has to match between clips for the concat demuxer to work with stream copy
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct VideoStreamInfo {
+    codec: String,
+    width: u32,
+    height: u32,
+    frame_rate: String,
+    // Clockwise degrees players turn the picture, phones record portrait video this way
+    rotation: u32,
+}
+
+impl fmt::Display for VideoStreamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}x{} @ {} fps", self.codec, self.width, self.height, self.frame_rate)?;
+        if self.rotation != 0 {
+            write!(f, " rotated {}°", self.rotation)?;
+        }
+        Ok(())
+    }
+}
+
+// The format of the first audio stream, tracks have to match to be merged as they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct AudioStreamInfo {
+    sample_rate: u32,
+    channels: u32,
+}
+
+impl fmt::Display for AudioStreamInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} Hz {} channels", self.sample_rate, self.channels)
+    }
+}
+
+// ffmpeg only knows how to up or downmix between named layouts
+fn channel_layout(channels: u32) -> String {
+    match channels {
+        1 => "mono".to_string(),
+        2 => "stereo".to_string(),
+        6 => "5.1".to_string(),
+        8 => "7.1".to_string(),
+        _ => format!("{}c", channels),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Encoder {
+    #[default]
+    Software,
+    Nvenc,
+    Qsv,
+    Vaapi,
+}
+
+impl Encoder {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Encoder::Software => "Software (x264)",
+            Encoder::Nvenc => "NVIDIA NVENC",
+            Encoder::Qsv => "Intel Quick Sync",
+            Encoder::Vaapi => "VAAPI",
+        }
+    }
+
+    fn video_codec(&self) -> &'static str {
+        match self {
+            Encoder::Software => "libx264",
+            Encoder::Nvenc => "h264_nvenc",
+            Encoder::Qsv => "h264_qsv",
+            Encoder::Vaapi => "h264_vaapi",
+        }
+    }
+
+    // Arguments that have to come before the input, VAAPI needs a device to upload frames to
+    fn input_args(&self) -> Vec<&'static str> {
+        match self {
+            Encoder::Vaapi => vec!["-vaapi_device", "/dev/dri/renderD128"],
+            _ => Vec::new(),
+        }
+    }
+
+    fn filter_suffix(&self) -> &'static str {
+        match self {
+            Encoder::Vaapi => ",format=nv12,hwupload",
+            _ => "",
+        }
+    }
+}
+
+// x264 speed against size, a slower preset gives a smaller file at the same quality.
+// Hardware encoders have presets of their own and ignore it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Preset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    #[default]
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+impl Preset {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::Ultrafast => "ultrafast",
+            Preset::Superfast => "superfast",
+            Preset::Veryfast => "veryfast",
+            Preset::Faster => "faster",
+            Preset::Fast => "fast",
+            Preset::Medium => "medium",
+            Preset::Slow => "slow",
+            Preset::Slower => "slower",
+            Preset::Veryslow => "veryslow",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VideoQuality {
+    // Constant quality where lower is better, 23 is the x264 default
+    Crf(u8),
+    // Target bitrate in ffmpeg notation, e.g. `8M`
+    Bitrate(String),
+}
+
+// Everything needed to encode a video stream, grouped since every re-encode needs both
+#[derive(Debug, Clone, PartialEq, Default)]
+struct VideoEncoding {
+    encoder: Encoder,
+    // None leaves the encoder defaults
+    quality: Option<VideoQuality>,
+    preset: Preset,
+    // 0 lets ffmpeg pick
+    threads: u32,
+    // Encodes in two passes with the first pass stats written under this prefix
+    passlog: Option<PathBuf>,
+    // Color tags written to the output, None leaves them to ffmpeg
+    color: Option<ColorInfo>,
+}
+
+impl VideoEncoding {
+    pub fn codec_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), self.encoder.video_codec().to_string()];
+        match &self.quality {
+            Some(VideoQuality::Crf(crf)) => {
+                // Hardware encoders have no -crf, these are their constant quality counterparts
+                let flag = match self.encoder {
+                    Encoder::Software => "-crf",
+                    Encoder::Nvenc => "-cq",
+                    Encoder::Qsv => "-global_quality",
+                    Encoder::Vaapi => "-qp",
+                };
+                args.push(flag.to_string());
+                args.push(crf.to_string());
+            }
+            Some(VideoQuality::Bitrate(bitrate)) => {
+                args.push("-b:v".to_string());
+                args.push(bitrate.clone());
+            }
+            None => {}
+        }
+        if self.encoder == Encoder::Software {
+            args.push("-preset".to_string());
+            args.push(self.preset.name().to_string());
+        }
+        if self.threads > 0 {
+            args.push("-threads".to_string());
+            args.push(self.threads.to_string());
+        }
+        if let Some(color) = &self.color {
+            args.extend(color.output_args());
+        }
+        args
+    }
+
+    // HDR needs 10 bits per channel, 8 bit output bands the gradients
+    fn pixel_format(&self) -> &'static str {
+        let hdr = self.color.as_ref().is_some_and(ColorInfo::is_hdr);
+        if hdr && self.encoder == Encoder::Software { "yuv420p10le" } else { "yuv420p" }
+    }
+}
+
+// Color tags of a video stream as ffprobe names them, None where they are unknown
+#[derive(Debug, Clone, PartialEq, Default)]
+struct ColorInfo {
+    primaries: Option<String>,
+    transfer: Option<String>,
+    space: Option<String>,
+}
+
+impl ColorInfo {
+    fn bt709() -> Self {
+        ColorInfo {
+            primaries: Some("bt709".to_string()),
+            transfer: Some("bt709".to_string()),
+            space: Some("bt709".to_string()),
+        }
+    }
+
+    // PQ and HLG are the two HDR transfer curves
+    fn is_hdr(&self) -> bool {
+        matches!(self.transfer.as_deref(), Some("smpte2084" | "arib-std-b67"))
+    }
+
+    fn output_args(&self) -> Vec<String> {
+        let tags = [
+            ("-color_primaries", &self.primaries),
+            ("-color_trc", &self.transfer),
+            ("-colorspace", &self.space),
+        ];
+        tags.into_iter()
+            .filter_map(|(flag, value)| Some([flag.to_string(), value.clone()?]))
+            .flatten()
+            .collect()
+    }
+}
+
+// HDR to SDR through linear light, hable keeps the highlights from clipping. Needs an ffmpeg
+// built with zimg for zscale.
+const TONEMAP_FILTER: &str =
+    "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+
+// SDR clips would end up tagged as HDR without being converted, so HDR and SDR clips are only
+// joined when tonemapping turns them all into SDR
+fn check_color_mix(
+    input_files: &[PathBuf],
+    colors: &[ColorInfo],
+    tonemap: bool
+) -> Result<(), MyError> {
+    let hdr = colors.iter().position(ColorInfo::is_hdr);
+    let sdr = colors.iter().position(|color| !color.is_hdr());
+    match (hdr, sdr) {
+        (Some(hdr), Some(sdr)) if !tonemap => {
+            Err(
+                MyError::InvalidInput(
+                    format!(
+                        "{} is HDR but {} is SDR, pass --tonemap to convert them all to SDR",
+                        input_files[hdr].display(),
+                        input_files[sdr].display()
+                    )
+                )
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+// The tags the output is encoded with. Tonemapped clips end up as BT.709, otherwise the first
+// HDR clip decides, so HDR stays HDR. SDR only inputs keep what ffmpeg does by default.
+fn output_color(colors: &[ColorInfo], tonemap: bool) -> Option<ColorInfo> {
+    let first_hdr = colors.iter().find(|color| color.is_hdr())?;
+    if tonemap { Some(ColorInfo::bt709()) } else { Some(first_hdr.clone()) }
+}
+
+#[derive(Debug)]
+pub enum MyError {
+    ToolNotFound(String),
+    FfmpegFailed {
+        stage: String,
+        code: Option<i32>,
+        // Last lines ffmpeg printed to stderr before failing
+        stderr: String,
+    },
+    Io(std::io::Error),
+    InvalidInput(String),
+    // The user stopped the job, not really a failure
+    Cancelled,
+    Other(String),
+}
+
+impl MyError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MyError::Other(_) => 1,
+            MyError::InvalidInput(_) => 2,
+            MyError::ToolNotFound(_) => 3,
+            MyError::FfmpegFailed { .. } => 4,
+            MyError::Io(_) => 5,
+            MyError::Cancelled => 6,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            MyError::ToolNotFound(_) => "ToolNotFound",
+            MyError::FfmpegFailed { .. } => "FfmpegFailed",
+            MyError::Io(_) => "Io",
+            MyError::InvalidInput(_) => "InvalidInput",
+            MyError::Cancelled => "Cancelled",
+            MyError::Other(_) => "Other",
+        }
+    }
+}
+
+impl fmt::Display for MyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MyError::ToolNotFound(tool) =>
+                write!(f, "{} not found on PATH; install it from https://ffmpeg.org", tool),
+            MyError::FfmpegFailed { stage, code, stderr } => {
+                match code {
+                    Some(code) => write!(f, "{} failed with exit code {}", stage, code)?,
+                    None => write!(f, "{} failed", stage)?,
+                }
+                if !stderr.is_empty() {
+                    write!(f, "\n{}", stderr)?;
+                }
+                Ok(())
+            }
+            MyError::Io(error) => write!(f, "{}", error),
+            MyError::Cancelled => write!(f, "Cancelled"),
+            MyError::InvalidInput(message) | MyError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for MyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MyError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for MyError {
+    fn from(error: std::io::Error) -> Self {
+        MyError::Io(error)
+    }
+}
+
+impl From<Box<dyn Error>> for MyError {
+    fn from(error: Box<dyn Error>) -> Self {
+        MyError::Other(error.to_string())
+    }
+}
+
+// Containers that can be imported, every place that picks videos by extension uses this list
+pub const SUPPORTED_EXTENSIONS: [&str; 12] = [
+    "mp4",
+    "mkv",
+    "mov",
+    "webm",
+    "avi",
+    "ts",
+    "m2ts",
+    "m4v",
+    "flv",
+    "wmv",
+    "mpg",
+    "mpeg",
+];
+
+pub fn is_video_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext.as_str()))
+}
+
+pub fn check_video_file(path: &Path) -> Result<(), MyError> {
+    if is_video_file(path) {
+        return Ok(());
+    }
+    Err(
+        MyError::InvalidInput(
+            format!(
+                "{} is not a supported video file, expected one of: {}",
+                path.display(),
+                SUPPORTED_EXTENSIONS.join(", ")
+            )
+        )
+    )
+}
+
+// Orders embedded numbers by value, so `clip2` comes before `clip10`
+fn natural_cmp(a: &str, b: &str) -> std::cmp::Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => {
+                return std::cmp::Ordering::Equal;
+            }
+            (None, Some(_)) => {
+                return std::cmp::Ordering::Less;
+            }
+            (Some(_), None) => {
+                return std::cmp::Ordering::Greater;
+            }
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut a_digits = String::new();
+                while let Some(c) = a_chars.next_if(|c| c.is_ascii_digit()) {
+                    a_digits.push(c);
+                }
+                let mut b_digits = String::new();
+                while let Some(c) = b_chars.next_if(|c| c.is_ascii_digit()) {
+                    b_digits.push(c);
+                }
+                let a_number = a_digits.trim_start_matches('0');
+                let b_number = b_digits.trim_start_matches('0');
+                let ordering = a_number
+                    .len()
+                    .cmp(&b_number.len())
+                    .then_with(|| a_number.cmp(b_number));
+                if ordering != std::cmp::Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                if x != y {
+                    return x.cmp(&y);
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+pub fn natural_sort(files: &mut [PathBuf]) {
+    files.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()));
+}
+
+fn collect_directory_videos(
+    dir: &Path,
+    recursive: bool,
+    files: &mut Vec<PathBuf>
+) -> Result<(), MyError> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if recursive {
+                collect_directory_videos(&path, recursive, files)?;
+            }
+        } else if is_video_file(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Turns the --input values into files. Directories and glob patterns pull in the supported videos
+// they contain, sorted naturally (plain name order without `sort`), plain paths are kept as given.
+pub fn expand_inputs(
+    patterns: &[OsString],
+    recursive: bool,
+    sort: bool
+) -> Result<Vec<PathBuf>, MyError> {
+    let mut input_files: Vec<PathBuf> = Vec::new();
+    for pattern in patterns {
+        let path = PathBuf::from(pattern);
+        let mut matches: Vec<PathBuf> = Vec::new();
+        // glob only takes UTF-8, other names can only be plain paths
+        let glob_pattern = pattern.to_str().filter(|text| text.contains(['*', '?', '[']));
+        if path.is_dir() {
+            collect_directory_videos(&path, recursive, &mut matches)?;
+            if matches.is_empty() {
+                return Err(
+                    MyError::InvalidInput(format!("No videos found in {}", path.display()))
+                );
+            }
+        } else if let Some(pattern) = glob_pattern {
+            let paths = glob::glob(pattern).map_err(|e| {
+                MyError::InvalidInput(format!("Invalid pattern {}: {}", pattern, e))
+            })?;
+            for entry in paths {
+                let entry = entry.map_err(|e| MyError::Io(e.into()))?;
+                if entry.is_file() && is_video_file(&entry) {
+                    matches.push(entry);
+                }
+            }
+            if matches.is_empty() {
+                return Err(MyError::InvalidInput(format!("No videos match {}", pattern)));
+            }
+        } else {
+            check_video_file(&path)?;
+            input_files.push(path);
+            continue;
+        }
+        if sort {
+            natural_sort(&mut matches);
+        } else {
+            // Directories list their files in no particular order
+            matches.sort();
+        }
+        input_files.extend(matches);
+    }
+    Ok(input_files)
+}
+
+// Fills in `{first_stem}` (the first input without its extension), `{count}` (the number of
+// inputs) and `{date}` (today in UTC, e.g. `2024-01-07`), other text is kept as it is
+pub fn expand_template(template: &str, inputs: &[PathBuf]) -> OsString {
+    let first_stem = inputs
+        .first()
+        .and_then(|input| input.file_stem())
+        .unwrap_or_default();
+    let timestamp = progress_log::timestamp(SystemTime::now());
+    let text = template
+        .replace("{count}", &inputs.len().to_string())
+        .replace("{date}", &timestamp[..10]);
+    // The stem is put in as it is, it doesn't have to be UTF-8
+    let mut expanded = OsString::new();
+    for (index, part) in text.split("{first_stem}").enumerate() {
+        if index > 0 {
+            expanded.push(first_stem);
+        }
+        expanded.push(part);
+    }
+    expanded
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    Ffmpeg,
+    Ffprobe,
+}
+
+impl Tool {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tool::Ffmpeg => "ffmpeg",
+            Tool::Ffprobe => "ffprobe",
+        }
+    }
+
+    pub fn env_var(&self) -> &'static str {
+        match self {
+            Tool::Ffmpeg => "FFMPEG_PATH",
+            Tool::Ffprobe => "FFPROBE_PATH",
+        }
+    }
+
+    fn bin_override(&self) -> &'static Mutex<Option<PathBuf>> {
+        match self {
+            Tool::Ffmpeg => &FFMPEG_BIN,
+            Tool::Ffprobe => &FFPROBE_BIN,
+        }
+    }
+
+    // The binary the tool was pointed at and the flag or variable that did it
+    fn configured_path(&self) -> Option<(PathBuf, String)> {
+        if let Some(path) = self.bin_override().lock().unwrap().clone() {
+            return Some((path, format!("--{}-bin", self.name())));
+        }
+        std::env::var_os(self.env_var())
+            .filter(|path| !path.is_empty())
+            .map(|path| (PathBuf::from(path), self.env_var().to_string()))
+    }
+
+    // Falls back to looking the name up on PATH
+    pub fn path(&self) -> PathBuf {
+        self.configured_path()
+            .map(|(path, _)| path)
+            .unwrap_or_else(|| PathBuf::from(self.name()))
+    }
+
+    // Every ffmpeg and ffprobe call starts here, so they all run the same binary
+    fn command(&self) -> Command {
+        Command::new(self.path())
+    }
+}
+
+pub fn set_tool_path(tool: Tool, path: PathBuf) {
+    *tool.bin_override().lock().unwrap() = Some(path);
+}
+
+pub fn ensure_tools_available() -> Result<(), MyError> {
+    for tool in [Tool::Ffmpeg, Tool::Ffprobe] {
+        let status = tool
+            .command()
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+
+        match status {
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(match tool.configured_path() {
+                    Some((path, source)) =>
+                        MyError::InvalidInput(
+                            format!(
+                                "{} points to {}, which doesn't exist",
+                                source,
+                                path.display()
+                            )
+                        ),
+                    None => MyError::ToolNotFound(tool.name().to_string()),
+                });
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn probe_available_encoders() -> Vec<Encoder> {
+    let output = match Tool::Ffmpeg.command().arg("-hide_banner").arg("-encoders").output() {
+        Ok(output) => output,
+        Err(_) => {
+            return vec![Encoder::Software];
+        }
+    };
+    let encoders = String::from_utf8_lossy(&output.stdout);
+
+    [Encoder::Software, Encoder::Nvenc, Encoder::Qsv, Encoder::Vaapi]
+        .into_iter()
+        .filter(|encoder| {
+            *encoder == Encoder::Software ||
+                encoders
+                    .lines()
+                    .any(|line| line.split_whitespace().nth(1) == Some(encoder.video_codec()))
+        })
+        .collect()
+}
+
+fn resolve_encoder(requested: Encoder) -> Encoder {
+    if requested == Encoder::Software || probe_available_encoders().contains(&requested) {
+        return requested;
+    }
+
+    status!(
+        "Warning: {} encoder ({}) is not available in this ffmpeg build, falling back to software",
+        requested.label(),
+        requested.video_codec()
+    );
+    Encoder::Software
+}
+
+pub fn validate_inputs(files: &[PathBuf]) -> Result<(), MyError> {
+    let missing_files: Vec<String> = files
+        .iter()
+        .filter(|file| !file.exists())
+        .map(|file| file.display().to_string())
+        .collect();
+
+    if !missing_files.is_empty() {
+        return Err(
+            MyError::InvalidInput(format!("Input files not found: {}", missing_files.join(", ")))
+        );
+    }
+
+    Ok(())
+}
+
+// Canonicalizes a file that may not exist yet, then only its folder has to
+fn canonical_output(output_file: &Path) -> Option<PathBuf> {
+    if let Ok(output_file) = output_file.canonicalize() {
+        return Some(output_file);
+    }
+    let file_name = output_file.file_name()?;
+    let dir = match output_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some(dir.canonicalize().ok()?.join(file_name))
+}
+
+// ffmpeg would overwrite an input while it is still reading it, so this is refused before
+// anything runs. Symlinks and relative paths count as the file they point to.
+pub fn check_output_not_an_input(
+    input_files: &[PathBuf],
+    output_file: &Path
+) -> Result<(), MyError> {
+    if is_stdout_output(output_file) {
+        return Ok(());
+    }
+    let Some(output) = canonical_output(output_file) else {
+        return Ok(());
+    };
+    let same_file = input_files
+        .iter()
+        .find(|input| input.canonicalize().is_ok_and(|input| input == output));
+    match same_file {
+        Some(input) =>
+            Err(
+                MyError::InvalidInput(
+                    format!(
+                        "The output {} is the input {}, writing it would destroy the input",
+                        output_file.display(),
+                        input.display()
+                    )
+                )
+            ),
+        None => Ok(()),
+    }
+}
+
+// ffmpeg with the shared global options, its log level follows --verbose/--quiet
+fn ffmpeg_command() -> Command {
+    let mut command = Tool::Ffmpeg.command();
+    command.arg("-hide_banner").arg("-loglevel").arg(LogLevel::current().ffmpeg_loglevel());
+    command
+}
+
+fn shell_escape(arg: &str) -> String {
+    let is_safe = !arg.is_empty() &&
+        arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@%".contains(c));
+    if is_safe {
+        return arg.to_string();
+    }
+
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+fn format_command(command: &Command) -> String {
+    let mut parts = vec![shell_escape(&command.get_program().to_string_lossy())];
+    for arg in command.get_args() {
+        parts.push(shell_escape(&arg.to_string_lossy()));
+    }
+
+    parts.join(" ")
+}
+
+const STDERR_TAIL_LINES: usize = 20;
+
+fn tail_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text
+        .split(['\n', '\r'])
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
+
+// Passes stderr through to the terminal while keeping the last lines for error reports
+fn read_stderr_tail(mut stderr: impl Read) -> String {
+    let mut tail: VecDeque<String> = VecDeque::new();
+    let mut current_line: Vec<u8> = Vec::new();
+    let mut buffer = [0u8; 4096];
+    let mut terminal = std::io::stderr();
+
+    loop {
+        let read = match stderr.read(&mut buffer) {
+            Ok(0) | Err(_) => {
+                break;
+            }
+            Ok(read) => read,
+        };
+        terminal.write_all(&buffer[..read]).ok();
+
+        for &byte in &buffer[..read] {
+            if byte != b'\n' && byte != b'\r' {
+                current_line.push(byte);
+                continue;
+            }
+            if !current_line.is_empty() {
+                tail.push_back(String::from_utf8_lossy(&current_line).into_owned());
+                current_line.clear();
+                if tail.len() > STDERR_TAIL_LINES {
+                    tail.pop_front();
+                }
+            }
+        }
+    }
+    if !current_line.is_empty() {
+        tail.push_back(String::from_utf8_lossy(&current_line).into_owned());
+    }
+
+    Vec::from(tail).join("\n")
+}
+
+fn check_status(status: ExitStatus, stage: &str, stderr: &str) -> Result<(), MyError> {
+    if !status.success() {
+        return Err(MyError::FfmpegFailed {
+            stage: stage.to_string(),
+            code: status.code(),
+            stderr: tail_lines(stderr, STDERR_TAIL_LINES),
+        });
+    }
+
+    Ok(())
+}
+
+// Hands the child over to RUNNING_CHILDREN, wait for it with wait_for_child
+fn register_child(child: Child) -> u32 {
+    let id = child.id();
+    RUNNING_CHILDREN.lock().unwrap().push(child);
+    id
+}
+
+// Polls instead of blocking in wait() so the lock stays free for cancel_running_jobs
+fn wait_for_child(id: u32) -> Result<ExitStatus, MyError> {
+    loop {
+        {
+            let mut children = RUNNING_CHILDREN.lock().unwrap();
+            let Some(position) = children.iter().position(|child| child.id() == id) else {
+                return Err(MyError::Other(format!("Lost track of process {}", id)));
+            };
+            if children[position].try_wait()?.is_some() {
+                // Already exited, so wait() returns the status right away
+                let status = children.remove(position).wait()?;
+                if CANCEL_REQUESTED.load(Ordering::SeqCst) {
+                    return Err(MyError::Cancelled);
+                }
+                return Ok(status);
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Kills every running ffmpeg and keeps new steps from starting until the flag is reset
+pub fn cancel_running_jobs() {
+    CANCEL_REQUESTED.store(true, Ordering::SeqCst);
+    for child in RUNNING_CHILDREN.lock().unwrap().iter_mut() {
+        child.kill().ok();
+    }
+}
+
+// Prints the command instead of spawning it when dry_run is set
+fn run_command(command: &mut Command, dry_run: bool, stage: &str) -> Result<(), MyError> {
+    if dry_run {
+        status!("{}", format_command(command));
+        return Ok(());
+    }
+    if CANCEL_REQUESTED.load(Ordering::SeqCst) {
+        return Err(MyError::Cancelled);
+    }
+
+    let mut child = command.stderr(Stdio::piped()).spawn()?;
+    let stderr_pipe = child.stderr.take();
+    let id = register_child(child);
+    let stderr = stderr_pipe.map(read_stderr_tail).unwrap_or_default();
+    let status = wait_for_child(id)?;
+    check_status(status, stage, &stderr)
+}
+
+// run_command for passes that only measure, the stats they print are read from the whole
+// stderr it returns
+fn run_analysis(command: &mut Command, stage: &str) -> Result<String, MyError> {
+    if CANCEL_REQUESTED.load(Ordering::SeqCst) {
+        return Err(MyError::Cancelled);
+    }
+
+    let mut child = command.stdout(Stdio::null()).stderr(Stdio::piped()).spawn()?;
+    let stderr_pipe = child.stderr.take();
+    let id = register_child(child);
+    let mut stderr: Vec<u8> = Vec::new();
+    // A failed read still waits below, so the child doesn't stay registered
+    let read = stderr_pipe.map_or(Ok(0), |mut pipe| pipe.read_to_end(&mut stderr));
+    let status = wait_for_child(id)?;
+    read?;
+    let stderr = String::from_utf8_lossy(&stderr).into_owned();
+    check_status(status, stage, &stderr)?;
+    Ok(stderr)
+}
+
+// Expects the command to already contain `-progress pipe:1` before the output file
+fn run_ffmpeg_with_progress(
+    mut cmd: Command,
+    total_us: u64,
+    mut on_progress: impl FnMut(f32)
+) -> Result<(ExitStatus, String), MyError> {
+    if CANCEL_REQUESTED.load(Ordering::SeqCst) {
+        return Err(MyError::Cancelled);
+    }
+
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+    // stderr is drained on its own thread so neither pipe can fill up and block ffmpeg
+    let stderr_reader = child.stderr
+        .take()
+        .map(|stderr| thread::spawn(move || read_stderr_tail(stderr)));
+    let stdout = child.stdout.take();
+    let id = register_child(child);
+
+    if let Some(stdout) = stdout {
+        for line in BufReader::new(stdout).lines() {
+            let line = line?;
+            // Despite the name, out_time_ms is reported in microseconds
+            if let Some(value) = line.strip_prefix("out_time_ms=") {
+                if let Ok(out_time_us) = value.trim().parse::<u64>() {
+                    if total_us > 0 {
+                        on_progress(((out_time_us as f64) / (total_us as f64)).min(1.0) as f32);
+                    }
+                }
+            } else if line == "progress=end" {
+                on_progress(1.0);
+            }
+        }
+    }
+
+    let status = wait_for_child(id)?;
+    let stderr = stderr_reader
+        .and_then(|reader| reader.join().ok())
+        .unwrap_or_default();
+    Ok((status, stderr))
+}
+
+// The concat demuxer has no escape inside quotes, so a quote closes the string,
+// is escaped on its own and the string is reopened.
+// Written as the bytes of the path, ffmpeg opens the name as it is written and a file name
+// doesn't have to be UTF-8.
+fn concat_list_line(file: &Path) -> Vec<u8> {
+    let mut line = b"file '".to_vec();
+    for byte in file.as_os_str().as_encoded_bytes() {
+        if *byte == b'\'' {
+            line.extend_from_slice(b"'\\''");
+        } else {
+            line.push(*byte);
+        }
+    }
+    line.push(b'\'');
+    line
+}
+
+fn write_concat_list(
+    files: &[PathBuf],
+    dry_run: bool
+) -> Result<Option<tempfile::NamedTempFile>, MyError> {
+    if dry_run {
+        for file in files {
+            status!("# {}", String::from_utf8_lossy(&concat_list_line(file)));
+        }
+        return Ok(None);
+    }
+
+    let temp_file = tempfile::NamedTempFile::new()?;
+    let mut writer = BufWriter::new(temp_file.reopen()?);
+
+    for file in files {
+        writer.write_all(&concat_list_line(file))?;
+        writer.write_all(b"\n")?;
+    }
+
+    writer.flush()?;
+
+    Ok(Some(temp_file))
+}
+
+fn concat_list_path(list_file: &Option<tempfile::NamedTempFile>) -> PathBuf {
+    match list_file {
+        Some(file) => file.path().to_path_buf(),
+        None => PathBuf::from("concat_list.txt"),
+    }
+}
+
+pub fn probe_audio_tracks(input_file: &PathBuf) -> Result<usize, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a")
+        .arg("-show_entries")
+        .arg("stream=index")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input_file)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing audio tracks of {}", input_file.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let track_count = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .count();
+
+    Ok(track_count)
+}
+
+fn probe_video_stream(input_file: &PathBuf) -> Result<VideoStreamInfo, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=codec_name,width,height,r_frame_rate")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input_file)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing video stream of {}", input_file.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let invalid = || {
+        MyError::Other(format!("Invalid video stream info for {}", input_file.display()))
+    };
+    let fields: Vec<&str> = stdout.trim().split(',').collect();
+    match fields.as_slice() {
+        [codec, width, height, frame_rate] =>
+            Ok(VideoStreamInfo {
+                codec: codec.to_string(),
+                width: width.parse().map_err(|_| invalid())?,
+                height: height.parse().map_err(|_| invalid())?,
+                frame_rate: frame_rate.to_string(),
+                rotation: probe_rotation(input_file)?,
+            }),
+        _ => Err(invalid()),
+    }
+}
+
+// The color tags of the first video stream, ffprobe calls the ones that aren't set `unknown`
+fn probe_color_info(input_file: &PathBuf) -> Result<ColorInfo, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=color_primaries,color_transfer,color_space")
+        .arg("-of")
+        .arg("json")
+        .arg(input_file)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing colors of {}", input_file.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|_| {
+        MyError::Other(format!("Invalid color info for {}", input_file.display()))
+    })?;
+    let stream = &info["streams"][0];
+    let tag = |key: &str| {
+        stream[key]
+            .as_str()
+            .filter(|value| *value != "unknown")
+            .map(str::to_string)
+    };
+    Ok(ColorInfo {
+        primaries: tag("color_primaries"),
+        transfer: tag("color_transfer"),
+        space: tag("color_space"),
+    })
+}
+
+// Newer ffprobe reports the display matrix, older ones the `rotate` tag. The matrix turns
+// counter-clockwise, the tag clockwise.
+fn probe_rotation(input_file: &PathBuf) -> Result<u32, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream_tags=rotate:stream_side_data=rotation")
+        .arg("-of")
+        .arg("json")
+        .arg(input_file)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing rotation of {}", input_file.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let invalid = || {
+        MyError::Other(format!("Invalid rotation info for {}", input_file.display()))
+    };
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).map_err(|_| invalid())?;
+    let stream = &info["streams"][0];
+    let degrees = stream["side_data_list"]
+        .as_array()
+        .and_then(|side_data| side_data.iter().find_map(|data| data["rotation"].as_f64()))
+        .map(|rotation| -rotation)
+        .or_else(|| stream["tags"]["rotate"].as_str().and_then(|rotate| rotate.parse().ok()))
+        .unwrap_or(0.0);
+
+    Ok(normalize_rotation(degrees))
+}
+
+fn normalize_rotation(degrees: f64) -> u32 {
+    (degrees.round() as i64).rem_euclid(360) as u32
+}
+
+// xfade is the newest filter the pipeline needs, older builds miss features silently
+const MIN_FFMPEG_VERSION: (u32, u32, u32) = (4, 3, 0);
+const LOUDNORM_FFMPEG_VERSION: (u32, u32, u32) = (3, 1, 0);
+const XFADE_FFMPEG_VERSION: (u32, u32, u32) = (4, 3, 0);
+
+fn format_version((major, minor, patch): (u32, u32, u32)) -> String {
+    format!("{}.{}.{}", major, minor, patch)
+}
+
+// Read once per run, so an old ffmpeg is only warned about once
+fn ffmpeg_version() -> Option<(u32, u32, u32)> {
+    static VERSION: OnceLock<Option<(u32, u32, u32)>> = OnceLock::new();
+    *VERSION.get_or_init(|| {
+        let version = Tool::Ffmpeg.command()
+            .arg("-version")
+            .output()
+            .ok()
+            .and_then(|output| parse_ffmpeg_version(&String::from_utf8_lossy(&output.stdout)));
+        if let Some(version) = version.filter(|version| *version < MIN_FFMPEG_VERSION) {
+            status!(
+                "Warning: ffmpeg {} is older than {}, some steps may fail",
+                format_version(version),
+                format_version(MIN_FFMPEG_VERSION)
+            );
+        }
+        version
+    })
+}
+
+// Git builds don't carry a version number and are assumed to be recent
+fn ffmpeg_is_at_least(minimum: (u32, u32, u32)) -> bool {
+    ffmpeg_version().is_none_or(|version| version >= minimum)
+}
+
+// ffmpeg 6.1 stopped writing the `rotate` tag and added -display_rotation instead
+fn ffmpeg_supports_display_rotation() -> bool {
+    ffmpeg_is_at_least((6, 1, 0))
+}
+
+// -vsync was renamed to -fps_mode in ffmpeg 5.1 and prints a deprecation warning since
+fn constant_frame_rate_args() -> [&'static str; 2] {
+    if ffmpeg_is_at_least((5, 1, 0)) { ["-fps_mode", "cfr"] } else { ["-vsync", "cfr"] }
+}
+
+// Filters without a fallback fail with a bare "No such filter", say what is missing instead
+fn check_ffmpeg_features(options: &EncodeOptions) -> Result<(), MyError> {
+    let mut required: Vec<(&str, (u32, u32, u32))> = Vec::new();
+    if options.loudness_target.is_some() {
+        required.push(("--normalize (loudnorm)", LOUDNORM_FFMPEG_VERSION));
+    }
+    if options.crossfade.is_some() && !options.audio_only {
+        required.push(("--crossfade (xfade)", XFADE_FFMPEG_VERSION));
+    }
+    let Some(version) = ffmpeg_version() else {
+        return Ok(());
+    };
+    for (feature, minimum) in required {
+        if version < minimum {
+            return Err(
+                MyError::InvalidInput(
+                    format!(
+                        "{} needs ffmpeg {} or newer, found {}",
+                        feature,
+                        format_version(minimum),
+                        format_version(version)
+                    )
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+// Another input would shift the stream indices the command maps, and whether the output may
+// be replaced is up to --overwrite
+const REJECTED_EXTRA_FFMPEG_ARGS: [&str; 3] = ["-i", "-y", "-n"];
+
+// Only catches what would break the output command, ffmpeg reports anything else it doesn't like
+pub fn validate_extra_ffmpeg_args(args: &[String]) -> Result<(), MyError> {
+    if let Some(first) = args.first() {
+        // A value on its own would be taken as a second output file
+        if !first.starts_with('-') {
+            return Err(
+                MyError::InvalidInput(
+                    format!("Extra ffmpeg arguments have to start with an option, got `{}`", first)
+                )
+            );
+        }
+    }
+    for arg in args {
+        if arg.trim().is_empty() {
+            return Err(MyError::InvalidInput("Extra ffmpeg arguments can't be empty".to_string()));
+        }
+        if REJECTED_EXTRA_FFMPEG_ARGS.contains(&arg.as_str()) {
+            return Err(
+                MyError::InvalidInput(
+                    format!("`{}` can't be passed as an extra ffmpeg argument", arg)
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+// Release builds print e.g. `ffmpeg version 6.1.1-3ubuntu5`, git builds a revision
+// (`N-112345-g...`) or a date (`2024-03-14-git-...`) instead
+pub fn parse_ffmpeg_version(version_output: &str) -> Option<(u32, u32, u32)> {
+    let version = version_output
+        .split_once("ffmpeg version ")?
+        .1.split_whitespace()
+        .next()?
+        .trim_start_matches('n');
+    let number_end = version
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(version.len());
+    let mut parts = version[..number_end].split('.');
+    let major: u32 = parts.next()?.parse().ok()?;
+    if major >= 1000 {
+        return None;
+    }
+    let mut next = || parts.next().and_then(|part| part.parse().ok()).unwrap_or(0);
+    let minor = next();
+    let patch = next();
+    Some((major, minor, patch))
+}
+
+fn probe_audio_stream(input_file: &PathBuf) -> Result<AudioStreamInfo, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("a:0")
+        .arg("-show_entries")
+        .arg("stream=sample_rate,channels")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input_file)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing audio stream of {}", input_file.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let invalid = || {
+        MyError::Other(format!("Invalid audio stream info for {}", input_file.display()))
+    };
+    let fields: Vec<&str> = stdout.trim().split(',').collect();
+    match fields.as_slice() {
+        [sample_rate, channels] =>
+            Ok(AudioStreamInfo {
+                sample_rate: sample_rate.parse().map_err(|_| invalid())?,
+                channels: channels.parse().map_err(|_| invalid())?,
+            }),
+        _ => Err(invalid()),
+    }
+}
+
+// ffprobe rates are fractions like `30000/1001`, `0/0` when unknown
+fn parse_frame_rate(rate: &str) -> Option<f64> {
+    let (numerator, denominator) = rate.split_once('/').unwrap_or((rate, "1"));
+    let rate = numerator.trim().parse::<f64>().ok()? / denominator.trim().parse::<f64>().ok()?;
+    (rate.is_finite() && rate > 0.0).then_some(rate)
+}
+
+// The highest and the average frame rate of the video stream
+fn probe_frame_rates(input_file: &PathBuf) -> Result<(String, String), MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=r_frame_rate,avg_frame_rate")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input_file)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing frame rate of {}", input_file.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.trim().split_once(',') {
+        Some((r_frame_rate, avg_frame_rate)) =>
+            Ok((r_frame_rate.to_string(), avg_frame_rate.to_string())),
+        None =>
+            Err(MyError::Other(format!("Invalid frame rate info for {}", input_file.display()))),
+    }
+}
+
+// Screen recorders drop frames when nothing changes, which shows as an average frame rate
+// well below the stream's nominal one
+fn is_variable_frame_rate(r_frame_rate: &str, avg_frame_rate: &str) -> bool {
+    match (parse_frame_rate(r_frame_rate), parse_frame_rate(avg_frame_rate)) {
+        (Some(nominal), Some(average)) => (nominal - average).abs() / nominal > 0.02,
+        _ => false,
+    }
+}
+
+// Lists the clips with a variable frame rate, None when every clip is constant
+pub fn describe_variable_frame_rate(files: &[PathBuf]) -> Result<Option<String>, MyError> {
+    let mut clips: Vec<String> = Vec::new();
+    for file in files {
+        let (r_frame_rate, avg_frame_rate) = probe_frame_rates(file)?;
+        if is_variable_frame_rate(&r_frame_rate, &avg_frame_rate) {
+            let average = parse_frame_rate(&avg_frame_rate).unwrap_or(0.0);
+            clips.push(
+                format!("{} ({} fps, {:.2} on average)", file.display(), r_frame_rate, average)
+            );
+        }
+    }
+
+    if clips.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(format!("variable frame rate in {}", clips.join(", "))))
+    }
+}
+
+// Describes the first clip that can't be stream copied after the first one, None when all match
+pub fn describe_stream_mismatch(files: &[PathBuf]) -> Result<Option<String>, MyError> {
+    let Some((first_file, rest)) = files.split_first() else {
+        return Ok(None);
+    };
+    let first = probe_video_stream(first_file)?;
+    for file in rest {
+        let info = probe_video_stream(file)?;
+        if info != first {
+            return Ok(
+                Some(
+                    format!(
+                        "{} is {} but {} is {}",
+                        file.display(),
+                        info,
+                        first_file.display(),
+                        first
+                    )
+                )
+            );
+        }
+    }
+
+    Ok(None)
+}
+
+fn probe_duration(input: &PathBuf) -> Result<f64, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-show_entries")
+        .arg("format=duration")
+        .arg("-of")
+        .arg("csv=p=0")
+        .arg(input)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing duration of {}", input.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(|_| MyError::Other(format!("Invalid duration for {}", input.display())))
+}
+
+pub fn probe_duration_us(input_file: &PathBuf) -> Result<u64, MyError> {
+    Ok((probe_duration(input_file)? * 1_000_000.0) as u64)
+}
+
+// Why an input can't be used, None when ffprobe reads it fine. A truncated download or a
+// recording that was cut off usually has no duration or doesn't parse at all.
+fn unreadable_reason(input_file: &PathBuf) -> Option<String> {
+    match std::fs::metadata(input_file) {
+        Ok(metadata) if metadata.len() == 0 => {
+            return Some("the file is empty".to_string());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            return Some(e.to_string());
+        }
+    }
+    match probe_duration(input_file) {
+        Ok(seconds) if seconds > 0.0 => None,
+        // ffprobe explains itself on its last line, e.g. `moov atom not found`
+        Err(MyError::FfmpegFailed { stderr, .. }) => {
+            let reason = stderr.lines().rev().find(|line| !line.trim().is_empty());
+            Some(reason.unwrap_or("ffprobe can't read it").trim().to_string())
+        }
+        Err(MyError::Io(e)) => Some(e.to_string()),
+        _ => Some("it has no duration".to_string()),
+    }
+}
+
+// The inputs a job would fail on halfway through, with the reason for each, checked up front
+// so one broken clip doesn't cost the work done on the others
+pub fn find_unreadable_inputs(input_files: &[PathBuf]) -> Vec<(PathBuf, String)> {
+    input_files
+        .iter()
+        .filter_map(|input_file| {
+            unreadable_reason(input_file).map(|reason| (input_file.clone(), reason))
+        })
+        .collect()
+}
+
+// The container duration covers the longest stream, which may be the audio. Matroska only
+// keeps stream durations in a tag, files without either fall back to the container.
+fn probe_video_duration_us(input_file: &PathBuf) -> Result<u64, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-select_streams")
+        .arg("v:0")
+        .arg("-show_entries")
+        .arg("stream=duration:stream_tags=DURATION")
+        .arg("-of")
+        .arg("json")
+        .arg(input_file)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing video duration of {}", input_file.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap_or_default();
+    let stream = &info["streams"][0];
+    let seconds = stream["duration"]
+        .as_str()
+        .and_then(|duration| duration.parse::<f64>().ok())
+        .or_else(|| stream["tags"]["DURATION"].as_str().and_then(parse_tag_duration));
+    match seconds {
+        Some(seconds) => Ok((seconds * 1_000_000.0) as u64),
+        None => probe_duration_us(input_file),
+    }
+}
+
+// Matroska duration tags look like `00:01:02.500000000`
+fn parse_tag_duration(tag: &str) -> Option<f64> {
+    let mut seconds = 0.0;
+    for part in tag.trim().split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds)
+}
+
+// What the GUI shows about the selected clip, to spot clips that won't join cleanly
+#[derive(Debug, Clone, PartialEq)]
+pub struct MediaInfo {
+    pub container: String,
+    pub duration_seconds: Option<f64>,
+    // None for files without a video stream
+    pub video_codec: Option<String>,
+    pub resolution: Option<(u32, u32)>,
+    pub frame_rate: Option<f64>,
+    // Codec, sample rate and channel layout of every audio track, in track order
+    pub audio_tracks: Vec<String>,
+    // Every stream of the file, subtitles and attachments included
+    pub streams: Vec<StreamSummary>,
+}
+
+// One row of the table the `streams` subcommand prints
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StreamSummary {
+    pub index: u64,
+    pub kind: String,
+    pub codec: String,
+    // `1920x1080` for video and `48000 Hz stereo` for audio, empty for everything else
+    pub detail: String,
+    pub duration_seconds: Option<f64>,
+}
+
+pub fn format_stream_table(streams: &[StreamSummary]) -> String {
+    let row = |index: &str, kind: &str, codec: &str, detail: &str, duration: &str| {
+        format!("{:>3}  {:<10} {:<12} {:<18} {}", index, kind, codec, detail, duration)
+    };
+    let mut table = row("#", "type", "codec", "details", "duration");
+    for stream in streams {
+        let duration = stream.duration_seconds
+            .map(|seconds| format!("{:.2}s", seconds))
+            .unwrap_or_else(|| "-".to_string());
+        table.push('\n');
+        table.push_str(
+            &row(&stream.index.to_string(), &stream.kind, &stream.codec, &stream.detail, &duration)
+        );
+    }
+    table
+}
+
+impl fmt::Display for MediaInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Container: {}", self.container)?;
+        if let Some(duration) = self.duration_seconds {
+            let seconds = duration.round() as u64;
+            writeln!(
+                f,
+                "Duration: {}:{:02}:{:02}",
+                seconds / 3600,
+                (seconds / 60) % 60,
+                seconds % 60
+            )?;
+        }
+        match &self.video_codec {
+            Some(codec) => {
+                write!(f, "Video: {}", codec)?;
+                if let Some((width, height)) = self.resolution {
+                    write!(f, " {}x{}", width, height)?;
+                }
+                if let Some(frame_rate) = self.frame_rate {
+                    write!(f, " @ {} fps", (frame_rate * 100.0).round() / 100.0)?;
+                }
+                writeln!(f)?;
+            }
+            None => writeln!(f, "Video: none")?,
+        }
+        write!(f, "Audio tracks: {}", self.audio_tracks.len())?;
+        for (index, track) in self.audio_tracks.iter().enumerate() {
+            write!(f, "\n  Track {}: {}", index, track)?;
+        }
+        Ok(())
+    }
+}
+
+// Reads the output of `ffprobe -show_streams -show_format -of json`
+fn parse_media_info(info: &serde_json::Value) -> MediaInfo {
+    let streams = info["streams"].as_array().map(Vec::as_slice).unwrap_or_default();
+    let video = streams.iter().find(|stream| stream["codec_type"] == "video");
+    let audio_tracks = streams
+        .iter()
+        .filter(|stream| stream["codec_type"] == "audio")
+        .map(|stream| {
+            let codec = stream["codec_name"].as_str().unwrap_or("unknown");
+            let sample_rate = stream["sample_rate"].as_str().unwrap_or("?");
+            let channels = stream["channels"].as_u64().unwrap_or(0) as u32;
+            format!("{} {} Hz {}", codec, sample_rate, channel_layout(channels))
+        })
+        .collect();
+    let streams = streams
+        .iter()
+        .enumerate()
+        .map(|(position, stream)| {
+            let kind = stream["codec_type"].as_str().unwrap_or("unknown");
+            let detail = match kind {
+                "video" =>
+                    format!(
+                        "{}x{}",
+                        stream["width"].as_u64().unwrap_or(0),
+                        stream["height"].as_u64().unwrap_or(0)
+                    ),
+                "audio" =>
+                    format!(
+                        "{} Hz {}",
+                        stream["sample_rate"].as_str().unwrap_or("?"),
+                        channel_layout(stream["channels"].as_u64().unwrap_or(0) as u32)
+                    ),
+                _ => String::new(),
+            };
+            StreamSummary {
+                index: stream["index"].as_u64().unwrap_or(position as u64),
+                kind: kind.to_string(),
+                codec: stream["codec_name"].as_str().unwrap_or("unknown").to_string(),
+                detail,
+                duration_seconds: stream["duration"].as_str().and_then(|d| d.parse().ok()),
+            }
+        })
+        .collect();
+
+    MediaInfo {
+        container: info["format"]["format_name"].as_str().unwrap_or("unknown").to_string(),
+        duration_seconds: info["format"]["duration"].as_str().and_then(|d| d.parse().ok()),
+        video_codec: video.map(|stream| {
+            stream["codec_name"].as_str().unwrap_or("unknown").to_string()
+        }),
+        resolution: video.and_then(|stream| {
+            Some((stream["width"].as_u64()? as u32, stream["height"].as_u64()? as u32))
+        }),
+        frame_rate: video
+            .and_then(|stream| stream["r_frame_rate"].as_str())
+            .and_then(parse_frame_rate),
+        audio_tracks,
+        streams,
+    }
+}
+
+pub fn probe_media_info(input: &PathBuf) -> Result<MediaInfo, MyError> {
+    let output = Tool::Ffprobe.command()
+        .arg("-v")
+        .arg("error")
+        .arg("-show_streams")
+        .arg("-show_format")
+        .arg("-of")
+        .arg("json")
+        .arg(input)
+        .output()?;
+
+    check_status(
+        output.status,
+        &format!("Probing media info of {}", input.display()),
+        &String::from_utf8_lossy(&output.stderr)
+    )?;
+
+    let info: serde_json::Value = serde_json
+        ::from_slice(&output.stdout)
+        .map_err(|_| MyError::Other(format!("Invalid media info for {}", input.display())))?;
+    Ok(parse_media_info(&info))
+}
+
+// Rough numbers shown before a job starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OutputEstimate {
+    pub duration_seconds: f64,
+    pub size_bytes: u64,
+}
+
+impl fmt::Display for OutputEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let seconds = self.duration_seconds.round() as u64;
+        write!(
+            f,
+            "Total duration: {}:{:02}:{:02}; estimated size: ",
+            seconds / 3600,
+            (seconds / 60) % 60,
+            seconds % 60
+        )?;
+        let size = self.size_bytes as f64;
+        match self.size_bytes {
+            0..=999_999 => write!(f, "{:.0} KB", size / 1_000.0),
+            1_000_000..=999_999_999 => write!(f, "{:.1} MB", size / 1_000_000.0),
+            _ => write!(f, "{:.2} GB", size / 1_000_000_000.0),
+        }
+    }
+}
+
+// Assumes every clip keeps its average bitrate, so a clip trimmed to half its length
+// adds half its file size
+pub fn estimate_output(
+    files: &[PathBuf],
+    trims: &[Option<TrimRange>]
+) -> Result<OutputEstimate, MyError> {
+    let mut estimate = OutputEstimate { duration_seconds: 0.0, size_bytes: 0 };
+    for (index, file) in files.iter().enumerate() {
+        let source_seconds = probe_duration(file)?;
+        let clip_seconds = match trims.get(index).copied().flatten() {
+            Some(trim) => {
+                (trim.duration_us((source_seconds * 1_000_000.0) as u64) as f64) / 1_000_000.0
+            }
+            None => source_seconds,
+        };
+        let file_size = std::fs::metadata(file)?.len() as f64;
+        if source_seconds > 0.0 {
+            estimate.size_bytes += ((file_size * clip_seconds) / source_seconds) as u64;
+        }
+        estimate.duration_seconds += clip_seconds;
+    }
+    Ok(estimate)
+}
+
+pub fn track_volume(volumes: &[f32], track_index: usize) -> f32 {
+    volumes.get(track_index).copied().unwrap_or(1.0)
+}
+
+// Mean loudness --auto-gain brings every track to, quiet speech ends up around here
+const AUTO_GAIN_TARGET_DB: f32 = -20.0;
+// Peaks are kept this far below full scale so the gain never clips
+const AUTO_GAIN_HEADROOM_DB: f32 = -1.0;
+
+// Runs volumedetect on one track and returns the volume that brings it to
+// AUTO_GAIN_TARGET_DB, usable as the volume of extract_and_adjust_audio
+fn detect_volume(input: &PathBuf, track: usize, dry_run: bool) -> Result<f32, MyError> {
+    // The stats are only printed at the info log level
+    let mut command = Tool::Ffmpeg.command();
+    command
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:a:{}", track))
+        .arg("-af")
+        .arg("volumedetect")
+        .arg("-f")
+        .arg("null")
+        .arg("-");
+
+    if dry_run {
+        status!("{}", format_command(&command));
+        return Ok(1.0);
+    }
+
+    // Registered like every other step, so cancelling stops it
+    let stderr = run_analysis(&mut command, "Detecting volume")?;
+
+    let (mean_db, max_db) = parse_volumedetect(&stderr).ok_or_else(|| {
+        MyError::Other(format!("No volume stats for track {} of {}", track, input.display()))
+    })?;
+    let gain = auto_gain(mean_db, max_db);
+    verbose!(
+        "Track {} of {}: mean {} dB, max {} dB, volume {:.2}",
+        track,
+        input.display(),
+        mean_db,
+        max_db,
+        gain
+    );
+    Ok(gain)
+}
+
+// Reads the `mean_volume: -25.3 dB` and `max_volume: -5.1 dB` lines
+fn parse_volumedetect(stderr: &str) -> Option<(f32, f32)> {
+    let read_db = |key: &str| {
+        stderr.lines().find_map(|line| {
+            let (_, value) = line.split_once(key)?;
+            value.trim().trim_end_matches("dB").trim().parse::<f32>().ok()
+        })
+    };
+    Some((read_db("mean_volume:")?, read_db("max_volume:")?))
+}
+
+pub fn auto_gain(mean_db: f32, max_db: f32) -> f32 {
+    let gain_db = (AUTO_GAIN_TARGET_DB - mean_db).min(AUTO_GAIN_HEADROOM_DB - max_db);
+    (10f32).powf(gain_db / 20.0).clamp(MIN_VOLUME, MAX_VOLUME)
+}
+
+// Cuts a clip down to the trim range with stream copy so it can feed the concat demuxer
+// Stream copy starts at the keyframe before `trim.start`, with `video_encoding` the video is
+// re-encoded instead so the cut lands on the exact frame
+fn trim_clip(
+    input_file: &PathBuf,
+    trim: &TrimRange,
+    video_encoding: Option<&VideoEncoding>,
+    output_file: PathBuf,
+    overwrite: bool,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    let mut command = ffmpeg_command();
+    command.arg(if overwrite { "-y" } else { "-n" });
+    if let Some(start) = trim.start {
+        command.arg("-ss").arg(start.to_string());
+    }
+    if let Some(end) = trim.end {
+        command.arg("-to").arg(end.to_string());
+    }
+    command.arg("-i").arg(input_file).arg("-map").arg("0").arg("-c").arg("copy");
+    if let Some(video_encoding) = video_encoding {
+        command.args(video_encoding.codec_args());
+    }
+    command.arg(&output_file);
+
+    let stage = format!("Trimming {}", input_file.display());
+    if let Err(e) = run_command(&mut command, dry_run, &stage) {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(output_file)
+}
+
+// The parts a clip is split into at `points` (seconds), each one starts where the one before
+// ended and the last one runs to the end of the clip
+pub fn split_ranges(points: &[f64]) -> Vec<TrimRange> {
+    let mut ranges: Vec<TrimRange> = Vec::new();
+    let mut start = None;
+    for point in points {
+        ranges.push(TrimRange { start, end: Some(*point) });
+        start = Some(*point);
+    }
+    ranges.push(TrimRange { start, end: None });
+    ranges
+}
+
+// `parts.mkv` becomes `parts_1.mkv`, `parts_2.mkv`, ..., padded so they sort in order
+pub fn segment_path(output_file: &Path, index: usize, count: usize) -> PathBuf {
+    let mut name = output_file.file_stem().unwrap_or_default().to_os_string();
+    name.push(format!("_{:0width$}", index + 1, width = count.to_string().len()));
+    if let Some(extension) = output_file.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    output_file.with_file_name(name)
+}
+
+// The other way around from concatenating: cuts a clip at every point into numbered files
+// named after `output_file`, parts that already exist are only replaced with `overwrite`
+pub fn split_video(
+    input_file: &PathBuf,
+    points: &[f64],
+    output_file: &Path,
+    reencode: bool,
+    overwrite: bool,
+    dry_run: bool
+) -> Result<Vec<PathBuf>, MyError> {
+    if points.is_empty() {
+        return Err(MyError::InvalidInput("Give at least one time to split at".to_string()));
+    }
+    if let Some(pair) = points.windows(2).find(|pair| pair[0] >= pair[1]) {
+        return Err(
+            MyError::InvalidInput(
+                format!("Split times must go up, {}s comes after {}s", pair[1], pair[0])
+            )
+        );
+    }
+    let duration = probe_duration(input_file)?;
+    if let Some(point) = points.iter().find(|point| **point <= 0.0 || **point >= duration) {
+        return Err(
+            MyError::InvalidInput(
+                format!(
+                    "Can't split {} at {}s, it is {:.2}s long",
+                    input_file.display(),
+                    point,
+                    duration
+                )
+            )
+        );
+    }
+
+    let video_encoding = reencode.then_some(VideoEncoding {
+        encoder: Encoder::Software,
+        quality: None,
+        preset: Preset::default(),
+        threads: 0,
+        passlog: None,
+        color: None,
+    });
+    let ranges = split_ranges(points);
+    for index in 0..ranges.len() {
+        let segment = segment_path(output_file, index, ranges.len());
+        check_output_not_an_input(std::slice::from_ref(input_file), &segment)?;
+        if !overwrite && !dry_run && segment.exists() {
+            return Err(MyError::InvalidInput(format!("{} already exists", segment.display())));
+        }
+    }
+    let mut segments: Vec<PathBuf> = Vec::new();
+    for (index, range) in ranges.iter().enumerate() {
+        let segment = segment_path(output_file, index, ranges.len());
+        status!("Writing {} ({})", segment.display(), range);
+        let encoding = video_encoding.as_ref();
+        segments.push(trim_clip(input_file, range, encoding, segment, overwrite, dry_run)?);
+    }
+    Ok(segments)
+}
+
+// Re-encodes the video of a clip to a fixed resolution and/or frame rate, letterboxing to keep
+// the aspect ratio, so the clips can still be joined with stream copy afterwards
+fn conform_clip(
+    input_file: &PathBuf,
+    resolution: Option<(u32, u32)>,
+    fps: Option<f64>,
+    encoding: &VideoEncoding,
+    output_file: PathBuf,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    let mut filters: Vec<String> = Vec::new();
+    if let Some((width, height)) = resolution {
+        filters.push(
+            format!(
+                "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1",
+                w = width,
+                h = height
+            )
+        );
+    }
+    if let Some(fps) = fps {
+        filters.push(format!("fps={}", fps));
+    }
+
+    let mut command = ffmpeg_command();
+    command
+        .arg("-y")
+        .args(encoding.encoder.input_args())
+        .arg("-i")
+        .arg(input_file)
+        .arg("-vf")
+        .arg(format!("{}{}", filters.join(","), encoding.encoder.filter_suffix()))
+        .args(encoding.codec_args())
+        .arg("-an")
+        .arg(&output_file);
+
+    let stage = format!("Conforming {}", input_file.display());
+    if let Err(e) = run_command(&mut command, dry_run, &stage) {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(output_file)
+}
+
+// The rate of the output audio unless --sample-rate asks for another one. In mix mode every
+// clip is brought to the output rate whatever tracks it had, so clips with different tracks
+// can still be joined with stream copy.
+const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+
+fn generate_silence(
+    duration_us: u64,
+    output: &PathBuf,
+    sample_rate: u32,
+    encoding: AudioEncoding,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    let mut command = ffmpeg_command();
+    command
+        .arg("-y")
+        .arg("-f")
+        .arg("lavfi")
+        .arg("-i")
+        .arg(format!("anullsrc=r={}:cl=stereo", sample_rate))
+        .arg("-t")
+        .arg(format!("{}", (duration_us as f64) / 1_000_000.0))
+        .args(encoding.codec_args())
+        .arg(output);
+
+    if let Err(e) = run_command(&mut command, dry_run, "Generating silence") {
+        cleanup_temp_files(vec![output.clone()]);
+        return Err(e);
+    }
+
+    Ok(output.clone())
+}
+
+pub fn extract_and_adjust_audio(
+    input_file: &PathBuf,
+    track_index: usize,
+    volume: f32,
+    encoding: AudioEncoding,
+    output_file: PathBuf,
+    dry_run: bool
+) -> Result<(PathBuf, Vec<PathBuf>), MyError> {
+    let temp_files: Vec<PathBuf> = vec![output_file.clone()];
+
+    let mut command = ffmpeg_command();
+    command
+        .arg("-y")
+        .arg("-i")
+        .arg(input_file)
+        .args(&["-map", &format!("0:a:{}", track_index)])
+        .arg("-af")
+        .arg(&format!("volume={}", volume))
+        .args(encoding.codec_args())
+        .arg(&output_file);
+
+    if let Err(e) = run_command(&mut command, dry_run, "Extracting audio") {
+        cleanup_temp_files(temp_files);
+        return Err(e);
+    }
+
+    Ok((output_file, temp_files))
+}
+
+// Combines the tracks of one clip into a single audio file. Mix overlays them as stereo,
+// Merge keeps every channel of every track side by side.
+pub fn merge_audio_tracks(
+    audio_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    encoding: AudioEncoding,
+    mix_mode: MixMode,
+    mix_sample_rate: u32,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    // Create the FFmpeg command
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg.arg("-y");
+    for input_file in &audio_files {
+        ffmpeg.arg("-i").arg(input_file);
+    }
+    ffmpeg.arg("-filter_complex");
+
+    // The dry run files don't exist yet, so there is nothing to probe
+    let formats = if dry_run || mix_mode == MixMode::Mix {
+        Vec::new()
+    } else {
+        audio_files.iter().map(probe_audio_stream).collect::<Result<Vec<_>, _>>()?
+    };
+    let sample_rate = formats.iter().map(|format| format.sample_rate).max();
+    match mix_mode {
+        MixMode::Mix => {
+            ffmpeg.arg(amix_filter(audio_files.len(), Some(mix_sample_rate), "stereo"));
+        }
+        MixMode::Merge if formats.windows(2).all(|pair| pair[0] == pair[1]) => {
+            ffmpeg.arg("amerge").arg("-ac").arg(format!("{}", audio_files.len()));
+        }
+        MixMode::Merge => {
+            // amerge distorts inputs at different rates, so every track is brought to the
+            // highest one first. The channels still add up, nothing is mixed.
+            let sample_rate = sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+            let channels: u32 = formats.iter().map(|format| format.channels).sum();
+            verbose!(
+                "Audio tracks differ ({}), merging them at {} Hz into {} channels",
+                formats.iter().map(|format| format.to_string()).collect::<Vec<_>>().join(", "),
+                sample_rate,
+                channels
+            );
+            ffmpeg
+                .arg(amerge_filter(audio_files.len(), sample_rate))
+                .arg("-ac")
+                .arg(channels.to_string());
+        }
+    }
+
+    ffmpeg.args(encoding.codec_args()).arg(&output_file);
+
+    if let Err(e) = run_command(&mut ffmpeg, dry_run, "Merging audio") {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(output_file.clone())
+}
+
+// Merge keeps the channels of every track, so clips with other tracks end up in another
+// format and can't be joined anymore
+fn check_matching_clip_audio(
+    input_files: &[PathBuf],
+    audio_files: &[PathBuf]
+) -> Result<(), MyError> {
+    let Some((first_audio, rest)) = audio_files.split_first() else {
+        return Ok(());
+    };
+    let first = probe_audio_stream(first_audio)?;
+    for (index, audio_file) in rest.iter().enumerate() {
+        let info = probe_audio_stream(audio_file)?;
+        if info != first {
+            return Err(
+                MyError::InvalidInput(
+                    format!(
+                        "Merging the tracks of {} gives {} but {} gives {}, clips with \
+                         different audio tracks have to be mixed",
+                        input_files[index + 1].display(),
+                        info,
+                        input_files[0].display(),
+                        first
+                    )
+                )
+            );
+        }
+    }
+    Ok(())
+}
+
+// Resamples every input to `sample_rate` and puts their channels side by side
+fn amerge_filter(input_count: usize, sample_rate: u32) -> String {
+    let mut filter = String::new();
+    for index in 0..input_count {
+        filter.push_str(&format!("[{}:a]aresample={}[a{}];", index, sample_rate, index));
+    }
+    for index in 0..input_count {
+        filter.push_str(&format!("[a{}]", index));
+    }
+    filter.push_str(&format!("amerge=inputs={}", input_count));
+    filter
+}
+
+// Brings every input to one sample rate and layout and mixes them. The track volumes were
+// already applied, so amix must not scale the inputs down again.
+fn amix_filter(input_count: usize, sample_rate: Option<u32>, layout: &str) -> String {
+    let mut filter = String::new();
+    for index in 0..input_count {
+        filter.push_str(&format!("[{}:a]", index));
+        if let Some(sample_rate) = sample_rate {
+            filter.push_str(&format!("aresample={},", sample_rate));
+        }
+        filter.push_str(&format!("aformat=channel_layouts={}[a{}];", layout, index));
+    }
+    for index in 0..input_count {
+        filter.push_str(&format!("[a{}]", index));
+    }
+    filter.push_str(&format!("amix=inputs={}:duration=longest:normalize=0", input_count));
+    filter
+}
+
+pub fn concatenate_audio_files(
+    audio_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    let list_file = write_concat_list(&audio_files, dry_run)?;
+
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg
+        .arg("-y")
+        .arg("-f")
+        .arg("concat")
+        .arg("-safe")
+        .arg("0")
+        .arg("-i")
+        .arg(concat_list_path(&list_file))
+        .arg("-c")
+        .arg("copy")
+        .arg(&output_file);
+
+    if let Err(e) = run_command(&mut ffmpeg, dry_run, "Concatenating audio") {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(output_file.clone())
+}
+
+pub fn concatenate_video_files(
+    video_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    dry_run: bool,
+    total_us: u64,
+    on_progress: impl FnMut(f32)
+) -> Result<PathBuf, MyError> {
+    // Write file paths to the temporary file
+    let list_file = write_concat_list(&video_files, dry_run)?;
+
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg
+        .arg("-y")
+        .arg("-f")
+        .arg("concat")
+        .arg("-safe")
+        .arg("0")
+        .arg("-i")
+        .arg(concat_list_path(&list_file))
+        .arg("-c")
+        .arg("copy")
+        .arg("-an")
+        .arg("-progress")
+        .arg("pipe:1")
+        .arg("-nostats")
+        .arg(&output_file);
+
+    if dry_run {
+        run_command(&mut ffmpeg, dry_run, "Concatenating video")?;
+    } else {
+        let (status, stderr) = run_ffmpeg_with_progress(ffmpeg, total_us, on_progress)?;
+        check_status(status, "Concatenating video", &stderr)?;
+    }
+
+    Ok(output_file.clone())
+}
+
+// Text burned into one clip while re-encoding
+struct ClipLabel {
+    text: String,
+    // Where the clip starts in the output, to show the running time from there
+    start_us: Option<u64>,
+}
+
+// drawtext values go through the option parser and then the filtergraph parser,
+// each one needs its own layer of escaping
+fn escape_drawtext(text: &str) -> String {
+    let escape = |text: &str, special: &[char]| {
+        let mut escaped = String::new();
+        for c in text.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    escape(&escape(text, &['\\', '\'', ':']), &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn drawtext_filter(label: &ClipLabel) -> String {
+    let style = "fontsize=24:fontcolor=white:box=1:boxcolor=black@0.5:boxborderw=6";
+    // Without expansion a `%` in the file name stays as it is
+    let mut filter = format!(
+        ",drawtext=text={}:expansion=none:x=12:y=12:{}",
+        escape_drawtext(&label.text),
+        style
+    );
+    if let Some(start_us) = label.start_us {
+        let running_time = format!("%{{pts:hms:{:.3}}}", (start_us as f64) / 1_000_000.0);
+        filter.push_str(
+            &format!(",drawtext=text={}:x=12:y=52:{}", escape_drawtext(&running_time), style)
+        );
+    }
+    filter
+}
+
+// The concat and crossfade filters open every clip at once, so long lists are joined in
+// batches of this many clips to stay clear of the open file limit
+pub const DEFAULT_BATCH_SIZE: usize = 50;
+
+fn batch_size(options: &EncodeOptions) -> usize {
+    if options.batch_size == 0 { DEFAULT_BATCH_SIZE } else { options.batch_size.max(2) }
+}
+
+// Consecutive ranges of at most `batch_size` items. A lone last item joins the batch before
+// it, every batch has at least two files to join.
+fn batch_ranges(count: usize, batch_size: usize) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = (0..count)
+        .step_by(batch_size)
+        .map(|start| start..(start + batch_size).min(count))
+        .collect();
+    if ranges.len() > 1 && ranges.last().is_some_and(|range| range.len() == 1) {
+        ranges.pop();
+        if let Some(last) = ranges.last_mut() {
+            last.end = count;
+        }
+    }
+    ranges
+}
+
+// Files a multi-input step joins in one ffmpeg call
+struct Batch {
+    // 0 for the clips themselves, later rounds join the results of the round before
+    round: usize,
+    // Position of the files in the list of their round
+    range: Range<usize>,
+    files: Vec<PathBuf>,
+    durations_us: Vec<u64>,
+}
+
+// Length of the files joined end to end, each transition overlaps two of them
+fn joined_duration_us(durations_us: &[u64], overlap_us: u64) -> u64 {
+    let total: u64 = durations_us.iter().sum();
+    total.saturating_sub(overlap_us * (durations_us.len().saturating_sub(1) as u64))
+}
+
+// Runs `join` on batches of files until one call can take the rest, which writes
+// `output_file`. The batch results are deleted once the output is written.
+fn run_in_batches(
+    files: Vec<PathBuf>,
+    durations_us: Vec<u64>,
+    overlap_us: u64,
+    output_file: &Path,
+    batch_size: usize,
+    mut join: impl FnMut(Batch, PathBuf) -> Result<PathBuf, MyError>
+) -> Result<PathBuf, MyError> {
+    let name = output_file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mut files = files;
+    let mut durations_us = durations_us;
+    let mut batch_files: Vec<PathBuf> = Vec::new();
+    let mut round = 0;
+    while files.len() > batch_size {
+        let ranges = batch_ranges(files.len(), batch_size);
+        // One batch is all of the files, joining its result again would only copy it
+        if ranges.len() == 1 {
+            break;
+        }
+        verbose!("Joining {} files in batches of {}", files.len(), batch_size);
+        let mut round_files: Vec<PathBuf> = Vec::new();
+        let mut round_durations_us: Vec<u64> = Vec::new();
+        for (index, range) in ranges.into_iter().enumerate() {
+            let batch = Batch {
+                round,
+                range: range.clone(),
+                files: files[range.clone()].to_vec(),
+                durations_us: durations_us[range].to_vec(),
+            };
+            let duration_us = joined_duration_us(&batch.durations_us, overlap_us);
+            let batch_output = output_file.with_file_name(
+                format!("batch-{}-{}_{}", round, index, name)
+            );
+            match join(batch, batch_output) {
+                Ok(batch_file) => {
+                    batch_files.push(batch_file.clone());
+                    round_files.push(batch_file);
+                    round_durations_us.push(duration_us);
+                }
+                Err(e) => {
+                    cleanup_temp_files(batch_files);
+                    return Err(e);
+                }
+            }
+        }
+        files = round_files;
+        durations_us = round_durations_us;
+        round += 1;
+    }
+    let batch = Batch { round, range: 0..files.len(), files, durations_us };
+    let result = join(batch, output_file.to_path_buf());
+    cleanup_temp_files(batch_files);
+    result
+}
+
+// reencode_video_files on batches of clips. The batch results are encoded the same way, so
+// they are stream copied together unless the transitions between them need another encode.
+#[allow(clippy::too_many_arguments)]
+fn reencode_video_batches(
+    video_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    target: &VideoStreamInfo,
+    encoding: &VideoEncoding,
+    crossfade: Option<f64>,
+    clip_durations_us: &[u64],
+    labels: &[ClipLabel],
+    tonemap: &[bool],
+    batch_size: usize,
+    dry_run: bool,
+    total_us: u64,
+    on_progress: &mut dyn FnMut(f32)
+) -> Result<PathBuf, MyError> {
+    let overlap_us = crossfade.map_or(0, |duration| (duration * 1_000_000.0) as u64);
+    let clip_durations_us = clip_durations_us.to_vec();
+    // Progress follows the clips, joining the batches afterwards is quick
+    let mut encoded_us = 0;
+    let join = |batch: Batch, batch_output: PathBuf| {
+        let Batch { round, range, files, durations_us } = batch;
+        let batch_us = joined_duration_us(&durations_us, overlap_us);
+        let batch_crossfade = crossfade.map(|duration| (duration, durations_us.as_slice()));
+        if round > 0 {
+            return match batch_crossfade {
+                Some(_) =>
+                    reencode_video_files(
+                        files,
+                        batch_output,
+                        target,
+                        encoding,
+                        batch_crossfade,
+                        &[],
+                        &[],
+                        dry_run,
+                        batch_us,
+                        |_| {}
+                    ),
+                None => concatenate_video_files(files, batch_output, dry_run, batch_us, |_| {}),
+            };
+        }
+        let start_us = encoded_us;
+        encoded_us += batch_us;
+        reencode_video_files(
+            files,
+            batch_output,
+            target,
+            encoding,
+            batch_crossfade,
+            labels.get(range.clone()).unwrap_or_default(),
+            tonemap.get(range).unwrap_or_default(),
+            dry_run,
+            batch_us,
+            |fraction| {
+                let done_us = (start_us as f32) + fraction * (batch_us as f32);
+                on_progress((done_us / (total_us.max(1) as f32)).min(1.0))
+            }
+        )
+    };
+    run_in_batches(video_files, clip_durations_us, overlap_us, &output_file, batch_size, join)
+}
+
+// Joins clips with the concat filter, scaling every clip to the target size and frame rate
+// first since the filter needs matching inputs. Audio is processed on its own path, so only
+// the video streams go through the filter.
+// With a crossfade the clips are chained through xfade instead, which needs the duration of
+// every clip to know where each transition starts.
+#[allow(clippy::too_many_arguments)]
+fn reencode_video_files(
+    video_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    target: &VideoStreamInfo,
+    encoding: &VideoEncoding,
+    crossfade: Option<(f64, &[u64])>,
+    // Parallel to the video files, empty for no labels
+    labels: &[ClipLabel],
+    // Parallel as well, the HDR clips to convert to SDR
+    tonemap: &[bool],
+    dry_run: bool,
+    total_us: u64,
+    on_progress: impl FnMut(f32)
+) -> Result<PathBuf, MyError> {
+    let mut filter = String::new();
+    for index in 0..video_files.len() {
+        let label = labels.get(index).map(drawtext_filter).unwrap_or_default();
+        let tonemap = if tonemap.get(index) == Some(&true) {
+            format!("{},", TONEMAP_FILTER)
+        } else {
+            String::new()
+        };
+        filter.push_str(
+            &format!(
+                "[{i}:v:0]{tonemap}scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={fps}{label},format={pix_fmt}[v{i}];",
+                i = index,
+                tonemap = tonemap,
+                pix_fmt = encoding.pixel_format(),
+                w = target.width,
+                h = target.height,
+                fps = target.frame_rate,
+                label = label
+            )
+        );
+    }
+    match crossfade {
+        Some((duration, clip_durations_us)) if video_files.len() > 1 => {
+            filter.push_str(&xfade_filter(duration, clip_durations_us));
+        }
+        _ => {
+            for index in 0..video_files.len() {
+                filter.push_str(&format!("[v{}]", index));
+            }
+            filter.push_str(&format!("concat=n={}:v=1:a=0", video_files.len()));
+        }
+    }
+    filter.push_str(&format!("{}[outv]", encoding.encoder.filter_suffix()));
+
+    // The first pass only writes the stats the second one spreads the bitrate by
+    let null_output = if cfg!(target_os = "windows") { "NUL" } else { "/dev/null" };
+    let passes: Vec<(Vec<OsString>, PathBuf)> = match &encoding.passlog {
+        Some(passlog) => {
+            let pass_args = |pass: u32| -> Vec<OsString> {
+                let pass = pass.to_string();
+                vec!["-pass".into(), pass.into(), "-passlogfile".into(), passlog.into()]
+            };
+            let mut first_pass = pass_args(1);
+            first_pass.extend(["-f".into(), "null".into()]);
+            vec![(first_pass, PathBuf::from(null_output)), (pass_args(2), output_file.clone())]
+        }
+        None => vec![(Vec::new(), output_file.clone())],
+    };
+
+    let pass_count = passes.len();
+    let mut on_progress = on_progress;
+    for (pass_index, (pass_args, pass_output)) in passes.into_iter().enumerate() {
+        let stage = if pass_count > 1 {
+            format!("Re-encoding video (pass {} of {})", pass_index + 1, pass_count)
+        } else {
+            "Re-encoding video".to_string()
+        };
+        let mut ffmpeg = ffmpeg_command();
+        ffmpeg.arg("-y").args(encoding.encoder.input_args());
+        for video_file in &video_files {
+            ffmpeg.arg("-i").arg(video_file);
+        }
+        ffmpeg
+            .arg("-filter_complex")
+            .arg(&filter)
+            .arg("-map")
+            .arg("[outv]")
+            .args(encoding.codec_args())
+            // Keeps variable frame rate sources from being muxed with uneven timestamps
+            .args(constant_frame_rate_args())
+            .args(pass_args)
+            .arg("-an")
+            .arg("-progress")
+            .arg("pipe:1")
+            .arg("-nostats")
+            .arg(&pass_output);
+
+        if dry_run {
+            run_command(&mut ffmpeg, dry_run, &stage)?;
+        } else {
+            let (status, stderr) = run_ffmpeg_with_progress(ffmpeg, total_us, |fraction| {
+                on_progress(((pass_index as f32) + fraction) / (pass_count as f32))
+            })?;
+            check_status(status, &stage, &stderr)?;
+        }
+    }
+
+    Ok(output_file)
+}
+
+// Chains `[v0]`, `[v1]`, ... through xfade, every transition starts `duration` seconds before
+// the end of everything joined so far. The last link is left unlabelled for the caller.
+fn xfade_filter(duration: f64, clip_durations_us: &[u64]) -> String {
+    let mut filter = String::new();
+    let mut previous = "v0".to_string();
+    let mut offset = 0.0;
+    for index in 1..clip_durations_us.len() {
+        offset += (clip_durations_us[index - 1] as f64) / 1_000_000.0 - duration;
+        if index > 1 {
+            filter.push_str(&format!("[x{}];", index - 1));
+            previous = format!("x{}", index - 1);
+        }
+        filter.push_str(
+            &format!(
+                "[{}][v{}]xfade=transition=fade:duration={}:offset={:.3}",
+                previous,
+                index,
+                duration,
+                offset
+            )
+        );
+    }
+    filter
+}
+
+// afade filters for the start and end of the output, fades longer than the output are cut
+// to its length. None when there is nothing to fade.
+fn audio_fade_filter(fade_in: Option<f64>, fade_out: Option<f64>, total_us: u64) -> Option<String> {
+    let total = (total_us as f64) / 1_000_000.0;
+    let mut filters: Vec<String> = Vec::new();
+    if let Some(duration) = fade_in.filter(|duration| *duration > 0.0) {
+        filters.push(format!("afade=t=in:st=0:d={:.3}", duration.min(total)));
+    }
+    if let Some(duration) = fade_out.filter(|duration| *duration > 0.0) {
+        let duration = duration.min(total);
+        filters.push(format!("afade=t=out:st={:.3}:d={:.3}", total - duration, duration));
+    }
+    (!filters.is_empty()).then(|| filters.join(","))
+}
+
+// Brings the joined audio to one rate and channel count, aac stutters at the joins of audio that
+// changes rate midway. None when it is already in that format.
+fn resample_audio(
+    input_file: &PathBuf,
+    output_file: PathBuf,
+    sample_rate: u32,
+    channels: Option<u32>,
+    encoding: AudioEncoding,
+    dry_run: bool
+) -> Result<Option<PathBuf>, MyError> {
+    // The dry run file doesn't exist yet, so show the command that may run
+    if !dry_run {
+        let info = probe_audio_stream(input_file)?;
+        if info.sample_rate == sample_rate && channels.is_none_or(|count| count == info.channels) {
+            return Ok(None);
+        }
+        verbose!("Resampling the audio from {} to {} Hz", info, sample_rate);
+    }
+
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg.arg("-y").arg("-i").arg(input_file).arg("-ar").arg(sample_rate.to_string());
+    if let Some(channels) = channels {
+        ffmpeg.arg("-ac").arg(channels.to_string());
+    }
+    ffmpeg.args(encoding.codec_args()).arg(&output_file);
+
+    if let Err(e) = run_command(&mut ffmpeg, dry_run, "Resampling audio") {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(Some(output_file))
+}
+
+fn fade_audio(
+    input_file: &PathBuf,
+    output_file: PathBuf,
+    filter: &str,
+    encoding: AudioEncoding,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg
+        .arg("-y")
+        .arg("-i")
+        .arg(input_file)
+        .arg("-af")
+        .arg(filter)
+        .args(encoding.codec_args())
+        .arg(&output_file);
+
+    if let Err(e) = run_command(&mut ffmpeg, dry_run, "Fading audio") {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(output_file)
+}
+
+// Like concatenate_audio_files, but overlaps consecutive files with acrossfade so the
+// audio lines up with the xfade transitions. The overlap means the audio is re-encoded.
+fn crossfade_audio_files(
+    audio_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    duration: f64,
+    encoding: AudioEncoding,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    let mut links: Vec<String> = Vec::new();
+    let mut previous = "0:a".to_string();
+    for index in 1..audio_files.len() {
+        let label = if index + 1 == audio_files.len() {
+            "outa".to_string()
+        } else {
+            format!("a{}", index)
+        };
+        links.push(format!("[{}][{}:a]acrossfade=d={}[{}]", previous, index, duration, label));
+        previous = label;
+    }
+
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg.arg("-y");
+    for audio_file in &audio_files {
+        ffmpeg.arg("-i").arg(audio_file);
+    }
+    // A single file has nothing to fade into and is only re-encoded
+    if links.is_empty() {
+        ffmpeg.arg("-map").arg("0:a");
+    } else {
+        ffmpeg.arg("-filter_complex").arg(links.join(";")).arg("-map").arg("[outa]");
+    }
+    ffmpeg.args(encoding.codec_args()).arg(&output_file);
+
+    if let Err(e) = run_command(&mut ffmpeg, dry_run, "Crossfading audio") {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(output_file)
+}
+
+// Every transition overlaps two clips, so all but the last clip lose the crossfade
+// duration in the output
+fn crossfaded_durations_us(clip_durations_us: &[u64], crossfade_us: u64) -> Vec<u64> {
+    let last = clip_durations_us.len().saturating_sub(1);
+    clip_durations_us
+        .iter()
+        .enumerate()
+        .map(|(index, duration)| {
+            if index < last { duration.saturating_sub(crossfade_us) } else { *duration }
+        })
+        .collect()
+}
+
+// First loudnorm pass, only analyses the audio and prints its stats as JSON on stderr
+fn measure_loudness(
+    input_file: &PathBuf,
+    target_lufs: f32,
+    dry_run: bool
+) -> Result<Option<LoudnormStats>, MyError> {
+    let mut command = Tool::Ffmpeg.command();
+    command
+        .arg("-hide_banner")
+        .arg("-i")
+        .arg(input_file)
+        .arg("-af")
+        .arg(format!("loudnorm=I={}:TP=-2:LRA=11:print_format=json", target_lufs))
+        .arg("-f")
+        .arg("null")
+        .arg("-");
+
+    if dry_run {
+        status!("{}", format_command(&command));
+        return Ok(None);
+    }
+
+    let stderr = run_analysis(&mut command, "Measuring loudness")?;
+
+    let json = match (stderr.rfind('{'), stderr.rfind('}')) {
+        (Some(start), Some(end)) if start < end => &stderr[start..=end],
+        _ => {
+            return Err(MyError::Other("Loudness measurement printed no stats".to_string()));
+        }
+    };
+    let stats: LoudnormStats = serde_json
+        ::from_str(json)
+        .map_err(|e| MyError::Other(format!("Invalid loudness stats: {}", e)))?;
+
+    Ok(Some(stats))
+}
+
+fn normalize_loudness(
+    input_file: &PathBuf,
+    output_file: PathBuf,
+    target_lufs: f32,
+    sample_rate: u32,
+    encoding: AudioEncoding,
+    dry_run: bool
+) -> Result<PathBuf, MyError> {
+    let mut filter = format!("loudnorm=I={}:TP=-2:LRA=11", target_lufs);
+    if let Some(stats) = measure_loudness(input_file, target_lufs, dry_run)? {
+        filter.push_str(
+            &format!(
+                ":measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+                stats.input_i,
+                stats.input_tp,
+                stats.input_lra,
+                stats.input_thresh,
+                stats.target_offset
+            )
+        );
+    }
+
+    // loudnorm upsamples internally, so bring the rate back down for the output
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg
+        .arg("-y")
+        .arg("-i")
+        .arg(input_file)
+        .arg("-af")
+        .arg(filter)
+        .arg("-ar")
+        .arg(sample_rate.to_string())
+        .args(encoding.codec_args())
+        .arg(&output_file);
+
+    if let Err(e) = run_command(&mut ffmpeg, dry_run, "Normalizing loudness") {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(output_file)
+}
+
+// The resolution if one was asked for, otherwise `width` wide and as tall as the first clip's
+// aspect ratio makes it. Even sizes keep every encoder happy.
+fn animation_size(
+    first_clip: &VideoStreamInfo,
+    resolution: Option<(u32, u32)>,
+    width: u32
+) -> (u32, u32) {
+    if let Some(resolution) = resolution {
+        return resolution;
+    }
+    let (clip_width, clip_height) = if first_clip.rotation % 180 == 90 {
+        (first_clip.height, first_clip.width)
+    } else {
+        (first_clip.width, first_clip.height)
+    };
+    let even = |size: f64| ((size / 2.0).round() as u32).max(1) * 2;
+    let height = ((width as f64) * (clip_height as f64)) / (clip_width.max(1) as f64);
+    (even(width as f64), even(height))
+}
+
+// Scales every clip into the frame (letterboxed like --resolution) at the animation frame
+// rate and joins them, the last link is left unlabelled for the caller
+fn animation_filter(clip_count: usize, (width, height): (u32, u32), fps: f64) -> String {
+    let mut filter = String::new();
+    for index in 0..clip_count {
+        filter.push_str(
+            &format!(
+                "[{i}:v:0]fps={fps},scale={w}:{h}:force_original_aspect_ratio=decrease:flags=lanczos,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1[v{i}];",
+                i = index,
+                fps = fps,
+                w = width,
+                h = height
+            )
+        );
+    }
+    for index in 0..clip_count {
+        filter.push_str(&format!("[v{}]", index));
+    }
+    filter.push_str(&format!("concat=n={}:v=1:a=0", clip_count));
+    filter
+}
+
+// Trims are applied while reading, so the clips don't need to be cut first
+fn add_animation_inputs(
+    ffmpeg: &mut Command,
+    input_files: &[PathBuf],
+    trims: &[Option<TrimRange>]
+) {
+    for (index, input_file) in input_files.iter().enumerate() {
+        if let Some(trim) = trims.get(index).copied().flatten() {
+            if let Some(start) = trim.start {
+                ffmpeg.arg("-ss").arg(start.to_string());
+            }
+            if let Some(end) = trim.end {
+                ffmpeg.arg("-to").arg(end.to_string());
+            }
+        }
+        ffmpeg.arg("-i").arg(input_file);
+    }
+}
+
+// A GIF is written in two passes: palettegen picks the colors from the joined clips, then
+// paletteuse maps every frame to them. `palette_file` is where the palette goes, None writes
+// an animated WebP in one pass instead.
+#[allow(clippy::too_many_arguments)]
+fn export_animation(
+    input_files: &[PathBuf],
+    output_file: &Path,
+    size: (u32, u32),
+    fps: f64,
+    palette_file: Option<&Path>,
+    options: &EncodeOptions,
+    total_us: u64,
+    on_progress: impl FnMut(f32)
+) -> Result<(), MyError> {
+    let dry_run = options.dry_run;
+    let filter = animation_filter(input_files.len(), size, fps);
+    let threads = |ffmpeg: &mut Command| {
+        if options.threads > 0 {
+            ffmpeg.arg("-threads").arg(options.threads.to_string());
+        }
+    };
+
+    if let Some(palette_file) = palette_file {
+        let mut ffmpeg = ffmpeg_command();
+        ffmpeg.arg("-y");
+        add_animation_inputs(&mut ffmpeg, input_files, &options.trims);
+        ffmpeg
+            .arg("-filter_complex")
+            .arg(format!("{},palettegen=stats_mode=diff[outv]", filter))
+            .args(["-map", "[outv]", "-frames:v", "1"]);
+        threads(&mut ffmpeg);
+        ffmpeg.arg(palette_file);
+        run_command(&mut ffmpeg, dry_run, "Generating the GIF palette")?;
+    }
+
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg.arg(if options.overwrite { "-y" } else { "-n" });
+    add_animation_inputs(&mut ffmpeg, input_files, &options.trims);
+    match palette_file {
+        Some(palette_file) => {
+            // The palette is the last input
+            ffmpeg
+                .arg("-i")
+                .arg(palette_file)
+                .arg("-filter_complex")
+                .arg(
+                    format!(
+                        "{}[joined];[joined][{}:v]paletteuse=dither=sierra2_4a[outv]",
+                        filter,
+                        input_files.len()
+                    )
+                )
+                .args(["-map", "[outv]"]);
+        }
+        None => {
+            ffmpeg
+                .arg("-filter_complex")
+                .arg(format!("{}[outv]", filter))
+                .args(["-map", "[outv]", "-c:v", "libwebp_anim", "-q:v", "75"]);
+        }
+    }
+    ffmpeg.args(["-loop", "0"]);
+    threads(&mut ffmpeg);
+    // Last so they can override anything above
+    ffmpeg.args(&options.extra_ffmpeg_args);
+    ffmpeg.arg("-progress").arg("pipe:1").arg("-nostats").arg(output_file);
+
+    if dry_run {
+        run_command(&mut ffmpeg, dry_run, "Writing the animation")
+    } else {
+        let (status, stderr) = run_ffmpeg_with_progress(ffmpeg, total_us, on_progress)?;
+        check_status(status, "Writing the animation", &stderr)
+    }
+}
+
+// `-` or `pipe:1` as the output streams the result to stdout in pipe_format
+#[allow(clippy::too_many_arguments)]
+fn combine_video_and_audio(
+    video_file: PathBuf,
+    audio_file: PathBuf,
+    output_file: PathBuf,
+    output_audio: OutputAudio,
+    subtitle_source: Option<&PathBuf>,
+    chapters_file: Option<&PathBuf>,
+    rotation: u32,
+    threads: u32,
+    overwrite: bool,
+    pipe_format: PipeFormat,
+    metadata: &OutputMetadata,
+    extra_args: &[String],
+    dry_run: bool
+) -> Result<(), MyError> {
+    let to_stdout = is_stdout_output(&output_file);
+    if to_stdout {
+        validate_pipe_format(pipe_format)?;
+    }
+    let is_mp4 = output_file
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mp4"));
+
+    // The intermediate containers drop the rotation of stream copied clips, so it is set again
+    let display_rotation = rotation != 0 && ffmpeg_supports_display_rotation();
+
+    // -n makes ffmpeg fail instead of replacing a file that appeared while the job ran
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg.arg(if overwrite { "-y" } else { "-n" });
+    if display_rotation {
+        ffmpeg.arg("-display_rotation:v:0").arg(format!("-{}", rotation));
+    }
+    ffmpeg.arg("-i").arg(&video_file).arg("-i").arg(&audio_file);
+
+    // Every input has to come before the output options
+    if let Some(subtitle_source) = subtitle_source {
+        ffmpeg.arg("-i").arg(subtitle_source);
+    }
+    if let Some(chapters_file) = chapters_file {
+        ffmpeg.arg("-f").arg("ffmetadata").arg("-i").arg(chapters_file);
+    }
+
+    if subtitle_source.is_some() {
+        // A third input breaks the automatic stream selection, so map every stream explicitly.
+        // mp4 can only hold text subtitles as mov_text.
+        ffmpeg
+            .args(["-map", "0:v:0", "-map", "1:a:0", "-map", "2:s?"])
+            .arg("-c:s")
+            .arg(if is_mp4 { "mov_text" } else { "copy" });
+    }
+    if chapters_file.is_some() {
+        // The metadata file is the last input, stripped outputs only take its chapters
+        let metadata_index = if subtitle_source.is_some() { 3 } else { 2 };
+        if !metadata.strip {
+            ffmpeg.arg("-map_metadata").arg(metadata_index.to_string());
+        }
+        ffmpeg.arg("-map_chapters").arg(metadata_index.to_string());
+    }
+    ffmpeg.args(metadata.args());
+
+    ffmpeg.arg("-c:v").arg("copy").args(output_audio.codec_args());
+    if rotation != 0 && !display_rotation {
+        ffmpeg.arg("-metadata:s:v:0").arg(format!("rotate={}", rotation));
+    }
+
+    // Only the audio is encoded here
+    if output_audio.codec != AudioCodec::Copy && threads > 0 {
+        ffmpeg.arg("-threads").arg(threads.to_string());
+    }
+
+    // Move the index to the front so browsers can start playing before the download ends
+    if is_mp4 {
+        ffmpeg.arg("-movflags").arg("+faststart");
+    }
+    // Last so they can override anything above
+    ffmpeg.args(extra_args);
+
+    // There is no extension to guess the container from, and ffmpeg only knows `pipe:1`.
+    // Its stdout is inherited, only stderr is captured.
+    if to_stdout {
+        ffmpeg.arg("-f").arg(pipe_format.muxer()).arg("pipe:1");
+    } else {
+        ffmpeg.arg(&output_file);
+    }
+
+    run_command(&mut ffmpeg, dry_run, "Combining video and audio")
+}
+
+// Encodes the joined audio on its own for --audio-only, the container follows the extension
+#[allow(clippy::too_many_arguments)]
+fn export_audio(
+    audio_file: &PathBuf,
+    output_file: PathBuf,
+    output_audio: OutputAudio,
+    threads: u32,
+    overwrite: bool,
+    pipe_format: PipeFormat,
+    metadata: &OutputMetadata,
+    extra_args: &[String],
+    dry_run: bool
+) -> Result<(), MyError> {
+    let to_stdout = is_stdout_output(&output_file);
+    if to_stdout {
+        validate_pipe_format(pipe_format)?;
+    }
+
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg
+        .arg(if overwrite { "-y" } else { "-n" })
+        .arg("-i")
+        .arg(audio_file)
+        .arg("-map")
+        .arg("0:a:0")
+        .args(output_audio.codec_args())
+        .args(metadata.args());
+    if output_audio.codec != AudioCodec::Copy && threads > 0 {
+        ffmpeg.arg("-threads").arg(threads.to_string());
+    }
+    ffmpeg.args(extra_args);
+    if to_stdout {
+        ffmpeg.arg("-f").arg(pipe_format.muxer()).arg("pipe:1");
+    } else {
+        ffmpeg.arg(&output_file);
+    }
+
+    run_command(&mut ffmpeg, dry_run, "Writing audio")
+}
+
+pub fn extract_thumbnail(input: &PathBuf, at_seconds: f64) -> Result<PathBuf, MyError> {
+    let thumbnail_dir = std::env::temp_dir().join("video_editor_thumbnails");
+    std::fs::create_dir_all(&thumbnail_dir)?;
+
+    // Named after a hash of the whole path, clips in different folders may share a name
+    let mut hasher = DefaultHasher::new();
+    input.hash(&mut hasher);
+    let output_file = thumbnail_dir.join(format!("{:016x}_{}.png", hasher.finish(), at_seconds));
+
+    let mut command = ffmpeg_command();
+    command
+        .arg("-y")
+        .arg("-ss")
+        .arg(at_seconds.to_string())
+        .arg("-i")
+        .arg(input)
+        .arg("-frames:v")
+        .arg("1")
+        .arg("-vf")
+        .arg("scale=-2:36")
+        .arg(&output_file);
+
+    let stage = format!("Extracting thumbnail of {}", input.display());
+    run_command(&mut command, false, &stage)?;
+
+    // ffmpeg exits cleanly without writing anything when seeking past the end
+    if !output_file.exists() {
+        return Err(MyError::Other(format!("No frame at {}s", at_seconds)));
+    }
+
+    Ok(output_file)
+}
+
+fn clip_duration_us(source_file: &PathBuf, trim: Option<TrimRange>) -> Result<u64, MyError> {
+    let duration_us = probe_duration_us(source_file)?;
+    match trim {
+        Some(trim) => Ok(trim.duration_us(duration_us)),
+        None => Ok(duration_us),
+    }
+}
+
+fn audio_encoding(options: &EncodeOptions) -> AudioEncoding {
+    AudioEncoding {
+        format: options.intermediate_audio,
+        quality: options.intermediate_quality,
+    }
+}
+
+// The sample rate and channel count the final audio is brought to, channels are None when
+// merged tracks keep theirs
+fn output_audio_format(options: &EncodeOptions) -> (u32, Option<u32>) {
+    let channels = options.channels.or((options.mix_mode == MixMode::Mix).then_some(2));
+    (options.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE), channels)
+}
+
+fn video_encoding(options: &EncodeOptions) -> VideoEncoding {
+    VideoEncoding {
+        encoder: resolve_encoder(options.encoder),
+        quality: options.video_quality.clone(),
+        preset: options.preset,
+        threads: options.threads,
+        passlog: None,
+        color: None,
+    }
+}
+
+// Below this even a static screen recording turns into blocks
+const MIN_TARGET_VIDEO_BITRATE: u64 = 100_000;
+// Share of the target size left for the container
+const MUXING_OVERHEAD: f64 = 0.02;
+
+// Bits per second the video can use so video and audio together end up at `size_bytes`
+fn target_video_bitrate(size_bytes: u64, total_us: u64, audio_bits: u64) -> Result<u64, MyError> {
+    let seconds = (total_us as f64) / 1_000_000.0;
+    if seconds <= 0.0 {
+        return Err(MyError::InvalidInput("--target-size needs clips with a duration".to_string()));
+    }
+    let available_bits = (size_bytes as f64) * 8.0 * (1.0 - MUXING_OVERHEAD) - (audio_bits as f64);
+    let bitrate = (available_bits / seconds).max(0.0) as u64;
+    if bitrate < MIN_TARGET_VIDEO_BITRATE {
+        let needed_bytes =
+            ((MIN_TARGET_VIDEO_BITRATE as f64) * seconds + (audio_bits as f64)) /
+            8.0 /
+            (1.0 - MUXING_OVERHEAD);
+        return Err(
+            MyError::InvalidInput(
+                format!(
+                    "A target size of {:.1} MB is too small for {:.0}s of video, \
+                     it needs at least {:.1} MB",
+                    (size_bytes as f64) / 1_000_000.0,
+                    seconds,
+                    needed_bytes / 1_000_000.0
+                )
+            )
+        );
+    }
+    Ok(bitrate)
+}
+
+// ffmpeg's default bitrates, copied audio keeps the size of the final audio file
+fn final_audio_bits(output_audio: OutputAudio, audio_file: &Path, total_us: u64) -> u64 {
+    let default_bitrate = match output_audio.codec {
+        AudioCodec::Aac | AudioCodec::Mp3 => 128_000,
+        AudioCodec::Opus => 96_000,
+        AudioCodec::Copy => {
+            return std::fs::metadata(audio_file).map_or(0, |metadata| metadata.len() * 8);
+        }
+    };
+    let bitrate = output_audio.bitrate.map_or(default_bitrate, u64::from);
+    bitrate * total_us / 1_000_000
+}
+
+// ffmetadata with one chapter per clip, in microseconds to match the probed durations
+fn build_chapter_metadata(durations: &[u64]) -> String {
+    let mut metadata = String::from(";FFMETADATA1\n");
+    let mut start = 0;
+    for (index, duration) in durations.iter().enumerate() {
+        metadata.push_str(
+            &format!(
+                "[CHAPTER]\nTIMEBASE=1/1000000\nSTART={}\nEND={}\ntitle=Chapter {}\n",
+                start,
+                start + duration,
+                index + 1
+            )
+        );
+        start += duration;
+    }
+    metadata
+}
+
+// Only the last extension counts (`clip.final.mp4` is an mp4), a clip without one is
+// written as mkv since that holds any codec
+fn clip_extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "mkv".to_string())
+}
+
+// Intermediates are named after the clip position, two inputs may share a file stem
+fn intermediate_path(work_dir: &Path, index: usize, name: &str, extension: &str) -> PathBuf {
+    work_dir.join(format!("clip-{}_{}.{}", index, name, extension))
+}
+
+// Extracts every audio track of a clip and merges them into a single file. Tracks are
+// probed on the source file since a trimmed clip may not exist yet in dry-run mode.
+fn extract_clip_audio(
+    index: usize,
+    source_file: &PathBuf,
+    file_path: &PathBuf,
+    work_dir: &Path,
+    options: &EncodeOptions,
+    temp_files: &Mutex<TempFiles>,
+    stage_times: &StageTimes
+) -> Result<PathBuf, MyError> {
+    let encoding = audio_encoding(options);
+    let format = encoding.format;
+    let dry_run = options.dry_run;
+    let track_count = probe_audio_tracks(source_file)?;
+    // Pairs of the position the volume is taken from and the track index, which only differ
+    // with track roles. A dropped background is never extracted.
+    let tracks: Vec<(usize, usize)> = if track_count == 0 {
+        Vec::new()
+    } else {
+        let tracks = match options.track_roles {
+            Some(roles) => roles.tracks(source_file, track_count)?,
+            None => (0..track_count).collect(),
+        };
+        tracks
+            .into_iter()
+            .enumerate()
+            .skip(usize::from(options.drop_background))
+            .collect()
+    };
+    if tracks.is_empty() {
+        // Keep the audio in step with the video by filling the clip with silence
+        let silence_path = intermediate_path(work_dir, index, "silence", format.extension());
+        let trim = options.trims.get(index).copied().flatten();
+        let duration_us = clip_duration_us(source_file, trim)?;
+        let (sample_rate, _) = output_audio_format(options);
+        let silence = generate_silence(duration_us, &silence_path, sample_rate, encoding, dry_run)?;
+        temp_files.lock().unwrap().push(silence.clone());
+        return Ok(silence);
+    }
+
+    let clip_volume = options.clip_volumes.get(index).copied().unwrap_or(1.0);
+    let mut track_audio_files: Vec<PathBuf> = Vec::new();
+    for (position, track_index) in tracks {
+        // A volume given by hand wins over the measured one
+        let volume = if options.auto_gain && position >= options.volumes.len() {
+            detect_volume(source_file, track_index, dry_run)?
+        } else {
+            track_volume(&options.volumes, position)
+        };
+        let volume = volume * clip_volume;
+        let (track_audio, temp_track_files) = extract_and_adjust_audio(
+            file_path,
+            track_index,
+            volume,
+            encoding,
+            intermediate_path(
+                work_dir,
+                index,
+                &format!("track-{}", track_index),
+                format.extension()
+            ),
+            dry_run
+        )?;
+        track_audio_files.push(track_audio);
+        temp_files.lock().unwrap().extend(temp_track_files);
+    }
+
+    // Mix still runs a lone track through amix to bring it to the format every clip shares
+    let clip_audio = if track_audio_files.len() == 1 && options.mix_mode == MixMode::Merge {
+        track_audio_files.remove(0)
+    } else {
+        let merged_audio_path = intermediate_path(
+            work_dir,
+            index,
+            "merged_audio",
+            format.extension()
+        );
+        let temp_merged = stage_times.step("merge", Some(source_file), || {
+            merge_audio_tracks(
+                track_audio_files,
+                merged_audio_path,
+                encoding,
+                options.mix_mode,
+                output_audio_format(options).0,
+                dry_run
+            )
+        })?;
+        temp_files.lock().unwrap().push(temp_merged.clone());
+        temp_merged
+    };
+    if options.no_pad {
+        return Ok(clip_audio);
+    }
+
+    // The trimmed clip doesn't exist in dry-run mode, its length is worked out instead
+    let video_us = if dry_run {
+        clip_duration_us(source_file, options.trims.get(index).copied().flatten())?
+    } else {
+        probe_video_duration_us(file_path)?
+    };
+    let fitted_path = intermediate_path(work_dir, index, "fitted_audio", format.extension());
+    let fitted = stage_times.step("pad", Some(source_file), || {
+        fit_audio_duration(&clip_audio, video_us, fitted_path, encoding, dry_run)
+    })?;
+    match fitted {
+        Some(fitted) => {
+            temp_files.lock().unwrap().push(fitted.clone());
+            Ok(fitted)
+        }
+        None => Ok(clip_audio),
+    }
+}
+
+// Closer than this and the audio is left alone, saving an encode
+const AUDIO_FIT_TOLERANCE_US: u64 = 5_000;
+
+// Encoded audio rarely ends exactly with the video, and since the clips are joined end to end
+// every difference moves the audio of the following clips. Pads the audio with silence or cuts
+// it to `duration_us`, None when it already fits.
+fn fit_audio_duration(
+    audio_file: &PathBuf,
+    duration_us: u64,
+    output_file: PathBuf,
+    encoding: AudioEncoding,
+    dry_run: bool
+) -> Result<Option<PathBuf>, MyError> {
+    // The dry run file doesn't exist yet, so show the command that may run
+    if !dry_run {
+        let audio_us = probe_duration_us(audio_file)?;
+        if audio_us.abs_diff(duration_us) <= AUDIO_FIT_TOLERANCE_US {
+            return Ok(None);
+        }
+        verbose!(
+            "Fitting {} from {:.3}s to {:.3}s",
+            audio_file.display(),
+            (audio_us as f64) / 1_000_000.0,
+            (duration_us as f64) / 1_000_000.0
+        );
+    }
+
+    let mut ffmpeg = ffmpeg_command();
+    ffmpeg
+        .arg("-y")
+        .arg("-i")
+        .arg(audio_file)
+        .arg("-af")
+        .arg("apad")
+        .arg("-t")
+        .arg(format!("{}", (duration_us as f64) / 1_000_000.0))
+        .args(encoding.codec_args())
+        .arg(&output_file);
+
+    if let Err(e) = run_command(&mut ffmpeg, dry_run, "Fitting audio to the video") {
+        cleanup_temp_files(vec![output_file.clone()]);
+        return Err(e);
+    }
+
+    Ok(Some(output_file))
+}
+
+// Every re-encode writes h264, stream copied clips keep the codec of the first one
+fn output_video_codec(
+    input_files: &[PathBuf],
+    options: &EncodeOptions
+) -> Result<Option<String>, MyError> {
+    if options.audio_only {
+        return Ok(None);
+    }
+    let reencoded =
+        options.concat_strategy == ConcatStrategy::ReEncode ||
+        options.crossfade.is_some() ||
+        options.label_clips ||
+        options.target_size.is_some() ||
+        options.target_resolution.is_some() ||
+        options.target_fps.is_some();
+    match input_files.first() {
+        Some(_) if reencoded => Ok(Some("h264".to_string())),
+        Some(first) => Ok(Some(probe_video_stream(first)?.codec)),
+        None => Ok(None),
+    }
+}
+
+// Everything one combine job needs, `combine_videos` runs it
+#[derive(Debug, Clone)]
+pub struct CombineConfig {
+    pub inputs: Vec<PathBuf>,
+    pub output: PathBuf,
+    pub options: EncodeOptions,
+}
+
+impl CombineConfig {
+    // Starts from EncodeOptions::default(). Unlike the CLI, which turns the first track down to
+    // 0.70 unless -v says otherwise, no volumes are set, so every track plays at 1.0.
+    pub fn builder(inputs: Vec<PathBuf>, output: impl Into<PathBuf>) -> CombineConfigBuilder {
+        CombineConfigBuilder {
+            config: CombineConfig {
+                inputs,
+                output: output.into(),
+                options: EncodeOptions::default(),
+            },
+        }
+    }
+}
+
+pub struct CombineConfigBuilder {
+    config: CombineConfig,
+}
+
+// One setter per option, a new option only needs a line here
+macro_rules! option_setters {
+    ($($name:ident: $type:ty),* $(,)?) => {
+        $(
+            pub fn $name(mut self, $name: $type) -> CombineConfigBuilder {
+                self.config.options.$name = $name;
+                self
+            }
+        )*
+    };
+}
+
+impl CombineConfigBuilder {
+    option_setters! {
+        volumes: Vec<f32>,
+        auto_gain: bool,
+        track_roles: Option<TrackRoles>,
+        drop_background: bool,
+        trims: Vec<Option<TrimRange>>,
+        clip_volumes: Vec<f32>,
+        intermediate_container: IntermediateContainer,
+        intermediate_audio: IntermediateAudio,
+        intermediate_quality: Option<f32>,
+        audio_codec: AudioCodec,
+        audio_bitrate: Option<u32>,
+        aac_profile: Option<AacProfile>,
+        mix_mode: MixMode,
+        sample_rate: Option<u32>,
+        channels: Option<u32>,
+        target_resolution: Option<(u32, u32)>,
+        target_fps: Option<f64>,
+        encoder: Encoder,
+        video_quality: Option<VideoQuality>,
+        preset: Preset,
+        target_size: Option<u64>,
+        concat_strategy: ConcatStrategy,
+        subtitles: SubtitleMode,
+        chapters: bool,
+        label_clips: bool,
+        label_time: bool,
+        audio_only: bool,
+        crossfade: Option<f64>,
+        loudness_target: Option<f32>,
+        fade_in: Option<f64>,
+        fade_out: Option<f64>,
+        keep_temp: bool,
+        resume: bool,
+        no_pad: bool,
+        threads: u32,
+        overwrite: bool,
+        pipe_format: PipeFormat,
+        extra_ffmpeg_args: Vec<String>,
+        metadata: OutputMetadata,
+        batch_size: usize,
+        animation_width: Option<u32>,
+        repeat: usize,
+        tonemap: bool,
+        dry_run: bool,
+    }
+
+    pub fn build(self) -> CombineConfig {
+        self.config
+    }
+}
+
+// The whole pipeline for callers that don't need the summary or progress updates
+pub fn combine_videos(config: CombineConfig) -> Result<(), MyError> {
+    ensure_tools_available()?;
+    combine_and_encode_videos(config, |_| {}).map(|_| ())
+}
+
+pub fn combine_and_encode_videos(
+    config: CombineConfig,
+    on_progress: impl FnMut(f32)
+) -> Result<CombineSummary, MyError> {
+    let CombineConfig { inputs: input_files, output: output_file, options } = config;
+    let options = &options;
+    validate_inputs(&input_files)?;
+    check_output_not_an_input(&input_files, &output_file)?;
+    check_ffmpeg_features(options)?;
+    validate_extra_ffmpeg_args(&options.extra_ffmpeg_args)?;
+    OutputAudio::from_options(options).validate()?;
+    if !options.overwrite && !options.dry_run && output_file.exists() {
+        return Err(MyError::InvalidInput(format!("{} already exists", output_file.display())));
+    }
+    let animation = AnimationFormat::from_output(&output_file);
+    if options.repeat > 1 {
+        if input_files.len() != 1 {
+            return Err(MyError::InvalidInput("--repeat needs a single input".to_string()));
+        }
+        if animation.is_some() {
+            return Err(
+                MyError::InvalidInput(
+                    "GIF and WebP outputs loop by themselves, leave out --repeat".to_string()
+                )
+            );
+        }
+    }
+    if animation.is_none() {
+        validate_output_container(
+            &output_file,
+            options.pipe_format,
+            output_video_codec(&input_files, options)?.as_deref(),
+            options.audio_codec.codec_name(options.intermediate_audio)
+        )?;
+    }
+
+    // Every intermediate lives here so nothing is written next to the inputs,
+    // the directory goes away on drop unless the files are kept for debugging.
+    // A resumable job always uses the same directory, and keeps it until it succeeds.
+    let (work_dir, temp_dir) = if resuming(options) {
+        let work_dir = resume_dir(&input_files, &output_file, options);
+        std::fs::create_dir_all(&work_dir)?;
+        (work_dir, None)
+    } else {
+        let temp_dir = tempfile::Builder::new().prefix("video_editor").tempdir()?;
+        (temp_dir.path().to_path_buf(), Some(temp_dir))
+    };
+    // The intermediates are deleted once the job is done, the output with them
+    let output_in_work_dir = match (work_dir.canonicalize(), canonical_output(&output_file)) {
+        (Ok(work_dir), Some(output)) => output.starts_with(work_dir),
+        _ => false,
+    };
+    if output_in_work_dir {
+        return Err(
+            MyError::InvalidInput(
+                format!(
+                    "The output {} is inside the work directory {}, which is deleted",
+                    output_file.display(),
+                    work_dir.display()
+                )
+            )
+        );
+    }
+    let job_output = output_file.clone();
+    let started = Instant::now();
+    let stage_times = StageTimes::default();
+    let result = progress_log::step("combine", Some(&job_output), || match animation {
+        Some(format) =>
+            run_animation(
+                input_files,
+                output_file,
+                format,
+                options,
+                &work_dir,
+                &stage_times,
+                on_progress
+            ),
+        None =>
+            run_pipeline(input_files, output_file, options, &work_dir, &stage_times, on_progress),
+    });
+    let result = result.map(|summary| {
+        let summary = CombineSummary {
+            elapsed_seconds: started.elapsed().as_secs_f64(),
+            stages: stage_times.into_times(),
+            ..summary
+        };
+        if !options.dry_run {
+            status!("{}", format_stage_times(&summary.stages, summary.elapsed_seconds));
+        }
+        summary
+    });
+
+    match temp_dir {
+        Some(temp_dir) if options.keep_temp => {
+            status!("Keeping temp directory: {:?}", temp_dir.into_path());
+        }
+        Some(_) => {}
+        None if result.is_err() => {
+            status!(
+                "Kept the finished steps in {:?}, run the same job with --resume to continue",
+                work_dir
+            );
+        }
+        None if options.keep_temp => status!("Keeping temp directory: {:?}", work_dir),
+        None => {
+            if let Err(e) = std::fs::remove_dir_all(&work_dir) {
+                status!("Failed to delete temp directory {:?}: {}", work_dir, e);
+            }
+        }
+    }
+
+    result
+}
+
+// Nothing is written in a dry run, so there is nothing to resume
+fn resuming(options: &EncodeOptions) -> bool {
+    options.resume && !options.dry_run
+}
+
+// Named after the job and every option the reused steps depend on, so changing the volume
+// or a trim starts over instead of picking up audio made with the old settings
+fn resume_dir(input_files: &[PathBuf], output_file: &Path, options: &EncodeOptions) -> PathBuf {
+    let settings = format!(
+        "{:?}",
+        (
+            &options.volumes,
+            options.auto_gain,
+            options.track_roles,
+            options.drop_background,
+            &options.trims,
+            &options.clip_volumes,
+            options.intermediate_audio,
+            options.intermediate_quality,
+            options.mix_mode,
+            options.sample_rate,
+            options.channels,
+            options.no_pad,
+        )
+    );
+    let mut hasher = DefaultHasher::new();
+    input_files.hash(&mut hasher);
+    output_file.hash(&mut hasher);
+    settings.hash(&mut hasher);
+    std::env::temp_dir()
+        .join("video_editor_resume")
+        .join(format!("{:016x}", hasher.finish()))
+}
+
+// Runs a step of a resumable job unless an earlier run already finished it. The marker is
+// only written once the step succeeded, so a file cut short by a crash is never reused.
+fn resumable_step(
+    work_dir: &Path,
+    name: &str,
+    source_file: &Path,
+    resume: bool,
+    run: impl FnOnce() -> Result<PathBuf, MyError>
+) -> Result<PathBuf, MyError> {
+    let marker = work_dir.join(format!("{}.done", name));
+    if resume {
+        if let Some(file) = finished_intermediate(&marker, source_file) {
+            status!("Reusing {}", file.display());
+            return Ok(file);
+        }
+        std::fs::remove_file(&marker).ok();
+    }
+    let file = run()?;
+    if resume {
+        let file_name = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        std::fs::write(&marker, file_name)?;
+    }
+    Ok(file)
+}
+
+// The file a marker points to, if it is newer than the source and ffprobe can still read it
+fn finished_intermediate(marker: &Path, source_file: &Path) -> Option<PathBuf> {
+    let file = marker.with_file_name(std::fs::read_to_string(marker).ok()?.trim());
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let source_modified = modified(source_file)?;
+    if modified(&file)? < source_modified || modified(marker)? < source_modified {
+        verbose!("{} changed since {} was made", source_file.display(), file.display());
+        return None;
+    }
+    probe_duration_us(&file.to_path_buf())
+        .ok()
+        .filter(|duration_us| *duration_us > 0)?;
+    Some(file)
+}
+
+// A GIF or WebP has no audio, so every audio step is skipped and the clips go straight
+// into the animation
+fn run_animation(
+    input_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    format: AnimationFormat,
+    options: &EncodeOptions,
+    work_dir: &Path,
+    stage_times: &StageTimes,
+    on_progress: impl FnMut(f32)
+) -> Result<CombineSummary, MyError> {
+    if options.audio_only {
+        return Err(
+            MyError::InvalidInput(
+                format!(
+                    "{} is an animation without audio, use an audio output",
+                    output_file.display()
+                )
+            )
+        );
+    }
+    let ignored: Vec<&str> = [
+        (options.crossfade.is_some(), "--crossfade"),
+        (options.label_clips, "--label-clips"),
+        (options.chapters, "--chapters"),
+        (options.target_size.is_some(), "--target-size"),
+        (options.loudness_target.is_some(), "--normalize"),
+    ]
+        .iter()
+        .filter(|(given, _)| *given)
+        .map(|(_, name)| *name)
+        .collect();
+    if !ignored.is_empty() {
+        status!("Warning: ignoring {} for the animation", ignored.join(", "));
+    }
+
+    let mut total_us = 0;
+    for (index, input_file) in input_files.iter().enumerate() {
+        total_us += clip_duration_us(input_file, options.trims.get(index).copied().flatten())?;
+    }
+    let size = animation_size(
+        &probe_video_stream(&input_files[0])?,
+        options.target_resolution,
+        options.animation_width.unwrap_or(DEFAULT_ANIMATION_WIDTH)
+    );
+    let fps = options.target_fps.unwrap_or(DEFAULT_ANIMATION_FPS);
+
+    let mut temp_files = TempFiles::new(options.keep_temp, false);
+    let palette_file = (format == AnimationFormat::Gif).then(|| work_dir.join("palette.png"));
+    if let Some(palette_file) = &palette_file {
+        temp_files.push(palette_file.clone());
+    }
+    stage_times.step("animation", None, || {
+        export_animation(
+            &input_files,
+            &output_file,
+            size,
+            fps,
+            palette_file.as_deref(),
+            options,
+            total_us,
+            on_progress
+        )
+    })?;
+
+    let temp_files_removed = temp_files.release();
+    status!("Successfully created the animation");
+    Ok(CombineSummary {
+        output: output_file,
+        inputs: input_files,
+        repeat: 1,
+        duration_seconds: (total_us as f64) / 1_000_000.0,
+        temp_files_removed,
+        ..Default::default()
+    })
+}
+
+// `[a, b]` twice is `[a, b, a, b]`
+fn repeated<T: Clone>(items: Vec<T>, times: usize) -> Vec<T> {
+    let count = items.len() * times;
+    items.into_iter().cycle().take(count).collect()
+}
+
+fn run_pipeline(
+    input_files: Vec<PathBuf>,
+    output_file: PathBuf,
+    options: &EncodeOptions,
+    work_dir: &Path,
+    stage_times: &StageTimes,
+    mut on_progress: impl FnMut(f32)
+) -> Result<CombineSummary, MyError> {
+    let dry_run = options.dry_run;
+    let resume = resuming(options);
+
+    // Probed first so colors that can't be joined fail the job before any audio work
+    let colors: Vec<ColorInfo> = if options.audio_only {
+        Vec::new()
+    } else {
+        input_files.iter().map(probe_color_info).collect::<Result<_, _>>()?
+    };
+    check_color_mix(&input_files, &colors, options.tonemap)?;
+
+    let mut temp_files = TempFiles::new(options.keep_temp, resume);
+    let mut clip_files: Vec<PathBuf> = Vec::new();
+    for (index, file_path) in input_files.iter().enumerate() {
+        match options.trims.get(index).copied().flatten() {
+            Some(trim) => {
+                let extension = clip_extension(file_path);
+                let trimmed_path = intermediate_path(work_dir, index, "trimmed", &extension);
+                let name = format!("clip-{}_trimmed", index);
+                let trimmed_file = resumable_step(work_dir, &name, file_path, resume, || {
+                    stage_times.step("trim", Some(file_path), || {
+                        trim_clip(file_path, &trim, None, trimmed_path, true, dry_run)
+                    })
+                })?;
+                temp_files.push(trimmed_file.clone());
+                clip_files.push(trimmed_file);
+            }
+            None => clip_files.push(file_path.clone()),
+        }
+    }
+
+    // Each clip is independent, so extract audio on a pool capped at the CPU count
+    let temp_files = Mutex::new(temp_files);
+    let results: Mutex<Vec<(usize, Result<PathBuf, MyError>)>> = Mutex::new(Vec::new());
+    let next_index = AtomicUsize::new(0);
+    let worker_count = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(input_files.len())
+        .max(1);
+
+    thread::scope(|scope| {
+        for _ in 0..worker_count {
+            scope.spawn(|| {
+                loop {
+                    let index = next_index.fetch_add(1, Ordering::SeqCst);
+                    if index >= input_files.len() {
+                        break;
+                    }
+                    let source_file = &input_files[index];
+                    let name = format!("clip-{}_audio", index);
+                    let result = resumable_step(work_dir, &name, source_file, resume, || {
+                        stage_times.step("extract", Some(source_file), || {
+                            extract_clip_audio(
+                                index,
+                                source_file,
+                                &clip_files[index],
+                                work_dir,
+                                options,
+                                &temp_files,
+                                stage_times
+                            )
+                        })
+                    });
+                    results.lock().unwrap().push((index, result));
+                }
+            });
+        }
+    });
+
+    let mut temp_files = temp_files.into_inner().unwrap();
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(index, _)| *index);
+
+    let mut merged_audio_files: Vec<PathBuf> = Vec::new();
+    for (_, result) in results {
+        merged_audio_files.push(result?);
+    }
+    if options.mix_mode == MixMode::Merge && !dry_run {
+        check_matching_clip_audio(&input_files, &merged_audio_files)?;
+    }
+
+    let mut clip_durations_us: Vec<u64> = Vec::new();
+    for (index, file_path) in input_files.iter().enumerate() {
+        clip_durations_us.push(
+            clip_duration_us(file_path, options.trims.get(index).copied().flatten())?
+        );
+    }
+
+    // A repeated clip is trimmed, conformed and its audio extracted once, the concat lists just
+    // name the same files again, so the audio loops exactly with the video
+    let repeat = options.repeat.max(1);
+    let inputs = input_files.clone();
+    let input_files = repeated(input_files, repeat);
+    let merged_audio_files = repeated(merged_audio_files, repeat);
+    let clip_durations_us = repeated(clip_durations_us, repeat);
+    let colors = repeated(colors, repeat);
+
+    let crossfade = options.crossfade.filter(|_| input_files.len() > 1);
+    let output_durations_us = match crossfade {
+        Some(crossfade) => {
+            let crossfade_us = (crossfade * 1_000_000.0) as u64;
+            let shortest_us = clip_durations_us.iter().copied().min().unwrap_or(0);
+            if crossfade_us >= shortest_us {
+                return Err(
+                    MyError::InvalidInput(
+                        format!(
+                            "The crossfade of {}s must be shorter than the shortest clip ({:.2}s)",
+                            crossfade,
+                            (shortest_us as f64) / 1_000_000.0
+                        )
+                    )
+                );
+            }
+            crossfaded_durations_us(&clip_durations_us, crossfade_us)
+        }
+        None => clip_durations_us.clone(),
+    };
+    let total_us: u64 = output_durations_us.iter().sum();
+
+    let chapters_file = if options.chapters {
+        let chapters_file = work_dir.join("chapters.txt");
+        std::fs::write(&chapters_file, build_chapter_metadata(&output_durations_us))?;
+        temp_files.push(chapters_file.clone());
+        Some(chapters_file)
+    } else {
+        None
+    };
+
+    let final_audio_file = work_dir.join(
+        format!("final_audio.{}", options.intermediate_audio.extension())
+    );
+    let temp_concat_audio = stage_times.step("concat_audio", None, || match crossfade {
+        Some(crossfade) =>
+            run_in_batches(
+                merged_audio_files,
+                clip_durations_us.clone(),
+                (crossfade * 1_000_000.0) as u64,
+                &final_audio_file,
+                batch_size(options),
+                |batch, batch_output| {
+                    crossfade_audio_files(
+                        batch.files,
+                        batch_output,
+                        crossfade,
+                        audio_encoding(options),
+                        dry_run
+                    )
+                }
+            ),
+        None => concatenate_audio_files(merged_audio_files, final_audio_file.clone(), dry_run),
+    })?;
+
+    temp_files.push(temp_concat_audio);
+
+    let final_audio_file = match options.loudness_target {
+        Some(target_lufs) => {
+            let normalized_audio_file = work_dir.join(
+                format!("normalized_audio.{}", options.intermediate_audio.extension())
+            );
+            let normalized_audio_file = stage_times.step("normalize", None, || {
+                normalize_loudness(
+                    &final_audio_file,
+                    normalized_audio_file,
+                    target_lufs,
+                    output_audio_format(options).0,
+                    audio_encoding(options),
+                    dry_run
+                )
+            })?;
+            temp_files.push(normalized_audio_file.clone());
+            normalized_audio_file
+        }
+        None => final_audio_file,
+    };
+
+    // After normalizing, which would otherwise lift the quiet ends back up
+    let final_audio_file = match audio_fade_filter(options.fade_in, options.fade_out, total_us) {
+        Some(filter) => {
+            let faded_audio_file = work_dir.join(
+                format!("faded_audio.{}", options.intermediate_audio.extension())
+            );
+            let faded_audio_file = stage_times.step("fade", None, || {
+                fade_audio(
+                    &final_audio_file,
+                    faded_audio_file,
+                    &filter,
+                    audio_encoding(options),
+                    dry_run
+                )
+            })?;
+            temp_files.push(faded_audio_file.clone());
+            faded_audio_file
+        }
+        None => final_audio_file,
+    };
+
+    let (sample_rate, channels) = output_audio_format(options);
+    let resampled_audio_file = work_dir.join(
+        format!("resampled_audio.{}", options.intermediate_audio.extension())
+    );
+    let resampled = stage_times.step("resample", None, || {
+        resample_audio(
+            &final_audio_file,
+            resampled_audio_file,
+            sample_rate,
+            channels,
+            audio_encoding(options),
+            dry_run
+        )
+    })?;
+    let final_audio_file = match resampled {
+        Some(resampled_audio_file) => {
+            temp_files.push(resampled_audio_file.clone());
+            resampled_audio_file
+        }
+        None => final_audio_file,
+    };
+
+    // There is no video to mux the audio into
+    if options.audio_only {
+        stage_times.step("export_audio", None, || {
+            export_audio(
+                &final_audio_file,
+                output_file.clone(),
+                OutputAudio::from_options(options),
+                options.threads,
+                options.overwrite,
+                options.pipe_format,
+                &options.metadata,
+                &options.extra_ffmpeg_args,
+                dry_run
+            )
+        })?;
+        let temp_files_removed = temp_files.release();
+        status!("Successfully combined audio");
+        return Ok(CombineSummary {
+            output: output_file,
+            inputs,
+            repeat,
+            duration_seconds: (total_us as f64) / 1_000_000.0,
+            temp_files_removed,
+            ..Default::default()
+        });
+    }
+
+    // Trimmed clips keep every stream, so their subtitles line up with the output
+    let clip_subtitle_source = clip_files[0].clone();
+
+    let tonemap: Vec<bool> = colors
+        .iter()
+        .map(|color| options.tonemap && color.is_hdr())
+        .collect();
+    let output_color = output_color(&colors, options.tonemap);
+
+    // Transitions, labels, a target size and tonemapping can't be stream copied
+    let concat_strategy = if
+        crossfade.is_some() ||
+        options.label_clips ||
+        options.target_size.is_some() ||
+        tonemap.contains(&true)
+    {
+        ConcatStrategy::ReEncode
+    } else {
+        options.concat_strategy
+    };
+
+    // Audio is extracted from the unconformed clips, only the video stream needs re-encoding.
+    // The concat filter scales and resamples every clip itself, so it skips this pass.
+    let needs_conform =
+        concat_strategy == ConcatStrategy::StreamCopy &&
+        (options.target_resolution.is_some() || options.target_fps.is_some());
+    // Re-encoding turns the pictures upright, stream copied clips only keep their flag
+    let mut rotation = if concat_strategy == ConcatStrategy::StreamCopy && !needs_conform {
+        probe_rotation(&input_files[0])?
+    } else {
+        0
+    };
+    let video_clip_files = if needs_conform {
+        let mut encoding = video_encoding(options);
+        encoding.color = output_color.clone();
+        let mut conformed_files: Vec<PathBuf> = Vec::new();
+        for (index, clip_file) in clip_files.iter().enumerate() {
+            let conformed_path = intermediate_path(
+                work_dir,
+                index,
+                "conformed",
+                options.intermediate_container.extension()
+            );
+            let conformed_file = stage_times.step("conform", Some(&input_files[index]), || {
+                conform_clip(
+                    clip_file,
+                    options.target_resolution,
+                    options.target_fps,
+                    &encoding,
+                    conformed_path,
+                    dry_run
+                )
+            })?;
+            temp_files.push(conformed_file.clone());
+            conformed_files.push(conformed_file);
+        }
+        conformed_files
+    } else {
+        clip_files
+    };
+    let video_clip_files = repeated(video_clip_files, repeat);
+
+    let concantenated_video_file = work_dir.join(
+        format!("concatenated_video.{}", options.intermediate_container.extension())
+    );
+    let mut encoding = video_encoding(options);
+    encoding.color = output_color;
+    if let Some(size_bytes) = options.target_size {
+        // -pass is an x264 option, the hardware encoders have no two pass mode
+        if encoding.encoder != Encoder::Software {
+            return Err(
+                MyError::InvalidInput("--target-size needs the software encoder".to_string())
+            );
+        }
+        let output_audio = OutputAudio::from_options(options);
+        let audio_bits = final_audio_bits(output_audio, &final_audio_file, total_us);
+        let bitrate = target_video_bitrate(size_bytes, total_us, audio_bits)?;
+        verbose!("Encoding the video at {} kbit/s to reach the target size", bitrate / 1000);
+        encoding.quality = Some(VideoQuality::Bitrate(format!("{}k", bitrate / 1000)));
+        encoding.passlog = Some(work_dir.join("passlog"));
+    }
+    let mut labels: Vec<ClipLabel> = Vec::new();
+    if options.label_clips {
+        let mut start_us = 0;
+        for (input_file, duration_us) in input_files.iter().zip(&output_durations_us) {
+            let name = input_file.file_name().unwrap_or(input_file.as_os_str());
+            labels.push(ClipLabel {
+                text: name.to_string_lossy().to_string(),
+                start_us: options.label_time.then_some(start_us),
+            });
+            start_us += duration_us;
+        }
+    }
+    let reencode = |files: Vec<PathBuf>, on_progress: &mut dyn FnMut(f32)| {
+        // Match the first clip unless a resolution was asked for
+        let mut target = probe_video_stream(&input_files[0])?;
+        // Decoding already turns rotated clips upright, so the frames are sideways
+        // compared to the size the first clip was recorded at
+        if target.rotation % 180 == 90 {
+            std::mem::swap(&mut target.width, &mut target.height);
+        }
+        if let Some((width, height)) = options.target_resolution {
+            target.width = width;
+            target.height = height;
+        }
+        if let Some(fps) = options.target_fps {
+            target.frame_rate = fps.to_string();
+        }
+        reencode_video_batches(
+            files,
+            concantenated_video_file.clone(),
+            &target,
+            &encoding,
+            crossfade,
+            &clip_durations_us,
+            &labels,
+            &tonemap,
+            batch_size(options),
+            dry_run,
+            total_us,
+            on_progress
+        )
+    };
+    let concat_result = stage_times.step("concat_video", None, || match concat_strategy {
+        ConcatStrategy::StreamCopy => {
+            let result = concatenate_video_files(
+                video_clip_files.clone(),
+                concantenated_video_file.clone(),
+                dry_run,
+                total_us,
+                &mut on_progress
+            );
+            match result {
+                // Clips that looked compatible can still trip up the concat demuxer,
+                // the concat filter decodes them and gets another chance
+                Err(e) if !dry_run && !matches!(e, MyError::Cancelled) => {
+                    status!("Warning: stream copy concat failed, re-encoding instead (slower)");
+                    verbose!("{}", e);
+                    rotation = 0;
+                    reencode(video_clip_files, &mut on_progress)
+                }
+                result => result,
+            }
+        }
+        ConcatStrategy::ReEncode => reencode(video_clip_files, &mut on_progress),
+    });
+    temp_files.push(concat_result?);
+
+    stage_times.step("mux", None, || {
+        combine_video_and_audio(
+            concantenated_video_file,
+            final_audio_file,
+            output_file.clone(),
+            OutputAudio::from_options(options),
+            // Subtitles can't be stream copied through the concat, the first clip's are kept
+            (options.subtitles == SubtitleMode::Copy).then_some(&clip_subtitle_source),
+            chapters_file.as_ref(),
+            rotation,
+            options.threads,
+            options.overwrite,
+            options.pipe_format,
+            &options.metadata,
+            &options.extra_ffmpeg_args,
+            dry_run
+        )
+    })?;
+
+    let temp_files_removed = temp_files.release();
+    status!("Successfully combined videos");
+    Ok(CombineSummary {
+        output: output_file,
+        inputs,
+        repeat,
+        duration_seconds: (total_us as f64) / 1_000_000.0,
+        temp_files_removed,
+        ..Default::default()
+    })
+}
+
+// Returns the files that were actually deleted
+// A file that can't be deleted is reported and skipped, cleanup must not fail the job
+fn cleanup_temp_files(temp_files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let mut removed: Vec<PathBuf> = Vec::new();
+    for temp_file in temp_files {
+        if temp_file.exists() {
+            verbose!("Deleting temp file: {:?}", temp_file);
+            match std::fs::remove_file(&temp_file) {
+                Ok(()) => removed.push(temp_file),
+                Err(e) => status!("Failed to delete temp file {:?}: {}", temp_file, e),
+            }
+        }
+    }
+    removed
+}
+
+// Like cleanup_temp_files, but keeps the files around for debugging when asked to
+fn release_temp_files(temp_files: Vec<PathBuf>, keep_temp: bool) -> Vec<PathBuf> {
+    if !keep_temp {
+        return cleanup_temp_files(temp_files);
+    }
+
+    for temp_file in temp_files {
+        if temp_file.exists() {
+            status!("Keeping temp file: {:?}", temp_file);
+        }
+    }
+    Vec::new()
+}
+
+// Intermediate files of a job, deleted when the guard is dropped so early returns and
+// panics clean up too. A resumable job keeps them when it fails.
+struct TempFiles {
+    files: Vec<PathBuf>,
+    keep: bool,
+    resumable: bool,
+}
+
+impl TempFiles {
+    fn new(keep: bool, resumable: bool) -> TempFiles {
+        TempFiles { files: Vec::new(), keep, resumable }
+    }
+
+    fn push(&mut self, file: PathBuf) {
+        self.files.push(file);
+    }
+
+    fn extend(&mut self, files: impl IntoIterator<Item = PathBuf>) {
+        self.files.extend(files);
+    }
+
+    // Cleans up right away and returns the removed files for the job summary
+    fn release(mut self) -> Vec<PathBuf> {
+        release_temp_files(std::mem::take(&mut self.files), self.keep)
+    }
+}
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        // Only reached without release() when the job failed
+        if self.resumable {
+            return;
+        }
+        release_temp_files(std::mem::take(&mut self.files), self.keep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ffmpeg_available() -> bool {
+        Tool::Ffmpeg.command()
+            .arg("-version")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok()
+    }
+
+    #[test]
+    fn natural_cmp_orders_numbers_by_value() {
+        let mut files = vec![
+            PathBuf::from("clip10.mp4"),
+            PathBuf::from("clip2.mp4"),
+            PathBuf::from("clip1.mp4"),
+            PathBuf::from("clip02b.mp4")
+        ];
+        natural_sort(&mut files);
+        assert_eq!(files, vec![
+            PathBuf::from("clip1.mp4"),
+            PathBuf::from("clip2.mp4"),
+            PathBuf::from("clip02b.mp4"),
+            PathBuf::from("clip10.mp4")
+        ]);
+    }
+
+    #[test]
+    fn globbed_clips_are_sorted_by_their_numbers_unless_asked_not_to() {
+        let dir = tempfile::tempdir().unwrap();
+        for number in 1..=12 {
+            std::fs::write(dir.path().join(format!("clip{}.mp4", number)), b"").unwrap();
+        }
+        let pattern = vec![dir.path().join("clip*.mp4").into_os_string()];
+        let names = |files: Vec<PathBuf>| -> Vec<String> {
+            files
+                .iter()
+                .map(|file| file.file_stem().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+
+        let sorted = names(expand_inputs(&pattern, false, true).unwrap());
+        let expected: Vec<String> = (1..=12).map(|number| format!("clip{}", number)).collect();
+        assert_eq!(sorted, expected);
+        let unsorted = names(expand_inputs(&pattern, false, false).unwrap());
+        assert_eq!(&unsorted[..4], ["clip1", "clip10", "clip11", "clip12"]);
+        let directory = vec![dir.path().as_os_str().to_os_string()];
+        assert_eq!(names(expand_inputs(&directory, false, true).unwrap()), expected);
+    }
+
+    #[test]
+    fn chapter_metadata_starts_each_chapter_where_the_last_ended() {
+        assert_eq!(
+            build_chapter_metadata(&[2_000_000, 3_500_000]),
+            ";FFMETADATA1\n\
+             [CHAPTER]\nTIMEBASE=1/1000000\nSTART=0\nEND=2000000\ntitle=Chapter 1\n\
+             [CHAPTER]\nTIMEBASE=1/1000000\nSTART=2000000\nEND=5500000\ntitle=Chapter 2\n"
+        );
+    }
+
+    #[test]
+    fn xfade_transitions_start_before_the_end_of_the_joined_clips() {
+        assert_eq!(
+            xfade_filter(0.5, &[2_000_000, 3_000_000, 1_000_000]),
+            "[v0][v1]xfade=transition=fade:duration=0.5:offset=1.500[x1];\
+             [x1][v2]xfade=transition=fade:duration=0.5:offset=4.000"
+        );
+        assert_eq!(
+            crossfaded_durations_us(&[2_000_000, 3_000_000, 1_000_000], 500_000),
+            vec![1_500_000, 2_500_000, 1_000_000]
+        );
+    }
+
+    #[test]
+    fn watched_clips_are_grouped_by_the_name_before_the_last_underscore() {
+        let set_name = |path: &str| watch::set_name(Path::new(path)).unwrap();
+        assert_eq!(set_name("/in/stream_1.mkv"), "stream");
+        assert_eq!(set_name("/in/2024_01_07_part2.mkv"), "2024_01_07");
+        assert_eq!(set_name("/in/single.mkv"), "single");
+    }
+
+    #[test]
+    fn ffmpeg_version_is_read_from_the_banner() {
+        let banner = |version: &str| {
+            format!(
+                "ffmpeg version {} Copyright (c) 2000-2023 the FFmpeg developers\n\
+                 built with gcc 13 (Ubuntu 13.2.0-23ubuntu3)\n\
+                 libavutil      58. 29.100 / 58. 29.100",
+                version
+            )
+        };
+        assert_eq!(parse_ffmpeg_version(&banner("6.1.1-3ubuntu5")), Some((6, 1, 1)));
+        assert_eq!(parse_ffmpeg_version(&banner("4.4.2-0ubuntu0.22.04.1")), Some((4, 4, 2)));
+        assert_eq!(parse_ffmpeg_version(&banner("5.1-3")), Some((5, 1, 0)));
+        assert_eq!(parse_ffmpeg_version(&banner("n7.0")), Some((7, 0, 0)));
+        assert_eq!(parse_ffmpeg_version(&banner("7.0.1-full_build-www.gyan.dev")), Some((7, 0, 1)));
+        assert_eq!(parse_ffmpeg_version(&banner("N-112345-gdeadbeef")), None);
+        assert_eq!(
+            parse_ffmpeg_version(&banner("2024-03-14-git-1b4b3f8b43-full_build-www.gyan.dev")),
+            None
+        );
+        assert_eq!(parse_ffmpeg_version("ffprobe version 6.1"), None);
+    }
+
+    #[test]
+    fn rotations_are_normalized_to_0_359() {
+        assert_eq!(normalize_rotation(-90.0), 270);
+        assert_eq!(normalize_rotation(450.0), 90);
+    }
+
+    #[test]
+    fn output_templates_are_filled_from_the_inputs() {
+        let inputs = vec![PathBuf::from("clips/stream_1.mkv"), PathBuf::from("clips/stream_2.mkv")];
+        assert_eq!(
+            expand_template("{first_stem}_{count}_clips.mp4", &inputs),
+            "stream_1_2_clips.mp4"
+        );
+        assert_eq!(expand_template("{unknown}.mp4", &inputs), "{unknown}.mp4");
+
+        let dated = expand_template("{date}.mp4", &inputs).into_string().unwrap();
+        assert_eq!(dated.len(), "2024-01-07.mp4".len());
+        assert_eq!(&dated[4..5], "-");
+    }
+
+    #[test]
+    fn only_supported_extensions_count_as_videos() {
+        assert!(is_video_file(Path::new("clip.MOV")));
+        assert!(is_video_file(Path::new("clip.webm")));
+        assert!(!is_video_file(Path::new("notes.txt")));
+        assert!(!is_video_file(Path::new("clip")));
+        assert!(matches!(check_video_file(Path::new("song.mp3")), Err(MyError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn temp_files_are_removed_when_the_guard_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let removed = dir.path().join("removed.ogg");
+        let kept = dir.path().join("kept.ogg");
+        std::fs::write(&removed, b"").unwrap();
+        std::fs::write(&kept, b"").unwrap();
+
+        let mut temp_files = TempFiles::new(false, false);
+        temp_files.push(removed.clone());
+        drop(temp_files);
+        assert!(!removed.exists());
+
+        let mut temp_files = TempFiles::new(true, false);
+        temp_files.push(kept.clone());
+        assert!(temp_files.release().is_empty());
+        assert!(kept.exists());
+    }
+
+    #[test]
+    fn resumed_steps_only_reuse_finished_files_newer_than_their_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source.mkv");
+        let audio = dir.path().join("clip-0_merged_audio.mkv");
+        std::fs::write(&source, b"").unwrap();
+        let marker = dir.path().join("clip-0_audio.done");
+
+        // A failed step leaves no marker behind
+        let failed = resumable_step(dir.path(), "clip-0_audio", &source, true, || {
+            Err(MyError::Other("ffmpeg crashed".to_string()))
+        });
+        assert!(failed.is_err());
+        assert!(!marker.exists());
+
+        if !tools_available() {
+            return;
+        }
+        generate_clip(&audio, 1, "16x16");
+        let made = resumable_step(dir.path(), "clip-0_audio", &source, true, || Ok(audio.clone()));
+        assert_eq!(made.unwrap(), audio);
+        assert!(marker.exists());
+        let reused = resumable_step(dir.path(), "clip-0_audio", &source, true, || {
+            panic!("a finished step must not run again")
+        });
+        assert_eq!(reused.unwrap(), audio);
+
+        // Touching the source makes the old result stale
+        let later = SystemTime::now() + Duration::from_secs(60);
+        std::fs::File::options().write(true).open(&source).unwrap().set_modified(later).unwrap();
+        assert_eq!(finished_intermediate(&marker, &source), None);
+        // A file cut short can't be probed
+        std::fs::write(&audio, b"truncated").unwrap();
+        std::fs::File::options().write(true).open(&audio).unwrap().set_modified(later).unwrap();
+        std::fs::File::options().write(true).open(&marker).unwrap().set_modified(later).unwrap();
+        assert_eq!(finished_intermediate(&marker, &source), None);
+    }
+
+    #[test]
+    fn resume_dirs_change_with_the_audio_settings() {
+        let inputs = vec![PathBuf::from("a.mkv"), PathBuf::from("b.mkv")];
+        let output = Path::new("out.mp4");
+        let options = EncodeOptions::default();
+        let louder = EncodeOptions { volumes: vec![1.5], ..EncodeOptions::default() };
+        let overwrite = EncodeOptions { overwrite: true, ..EncodeOptions::default() };
+        let quieter_clip = EncodeOptions {
+            clip_volumes: vec![1.0, 0.5],
+            ..EncodeOptions::default()
+        };
+
+        let dir = resume_dir(&inputs, output, &options);
+        assert_eq!(dir, resume_dir(&inputs, output, &options));
+        assert_eq!(dir, resume_dir(&inputs, output, &overwrite));
+        assert_ne!(dir, resume_dir(&inputs, output, &louder));
+        assert_ne!(dir, resume_dir(&inputs, output, &quieter_clip));
+        assert_ne!(dir, resume_dir(&inputs[..1], output, &options));
+        let mono = EncodeOptions { channels: Some(1), ..EncodeOptions::default() };
+        assert_ne!(dir, resume_dir(&inputs, output, &mono));
+    }
+
+    #[test]
+    fn average_frame_rates_well_below_the_nominal_one_are_variable() {
+        assert_eq!(parse_frame_rate("30000/1001"), Some(30000.0 / 1001.0));
+        assert_eq!(parse_frame_rate("25"), Some(25.0));
+        assert_eq!(parse_frame_rate("0/0"), None);
+
+        assert!(!is_variable_frame_rate("60/1", "60/1"));
+        assert!(!is_variable_frame_rate("30000/1001", "2997/100"));
+        assert!(is_variable_frame_rate("60/1", "4730/100"));
+        // Unknown averages aren't reported
+        assert!(!is_variable_frame_rate("60/1", "0/0"));
+    }
+
+    #[test]
+    fn log_file_timestamps_are_utc_dates() {
+        let at = |seconds| std::time::UNIX_EPOCH + Duration::from_secs(seconds);
+        assert_eq!(progress_log::timestamp(at(0)), "1970-01-01T00:00:00Z");
+        assert_eq!(progress_log::timestamp(at(1_704_602_738)), "2024-01-07T04:45:38Z");
+        // Leap day
+        assert_eq!(progress_log::timestamp(at(1_709_208_000)), "2024-02-29T12:00:00Z");
+    }
+
+    #[test]
+    fn track_roles_must_be_distinct_tracks_of_the_clip() {
+        assert!(matches!(TrackRoles::new(1, 1), Err(MyError::InvalidInput(_))));
+
+        let roles = TrackRoles::new(2, 0).unwrap();
+        let clip = Path::new("clip.mkv");
+        assert_eq!(roles.tracks(clip, 3).unwrap(), vec![2, 0]);
+        assert!(matches!(roles.tracks(clip, 2), Err(MyError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn media_info_lists_the_video_and_every_audio_track() {
+        let info = serde_json::json!({
+            "streams": [
+                {
+                    "codec_type": "video",
+                    "codec_name": "h264",
+                    "width": 1920,
+                    "height": 1080,
+                    "r_frame_rate": "30000/1001"
+                },
+                {
+                    "codec_type": "audio",
+                    "codec_name": "aac",
+                    "sample_rate": "48000",
+                    "channels": 2
+                },
+                {
+                    "codec_type": "audio",
+                    "codec_name": "opus",
+                    "sample_rate": "48000",
+                    "channels": 1
+                }
+            ],
+            "format": { "format_name": "matroska,webm", "duration": "65.400000" }
+        });
+        let info = parse_media_info(&info);
+        assert_eq!(info.resolution, Some((1920, 1080)));
+        assert_eq!(info.audio_tracks, vec!["aac 48000 Hz stereo", "opus 48000 Hz mono"]);
+        assert_eq!(
+            info.to_string(),
+            "Container: matroska,webm\nDuration: 0:01:05\nVideo: h264 1920x1080 @ 29.97 fps\n\
+             Audio tracks: 2\n  Track 0: aac 48000 Hz stereo\n  Track 1: opus 48000 Hz mono"
+        );
+
+        assert_eq!(info.streams.len(), 3);
+        assert_eq!(info.streams[1].detail, "48000 Hz stereo");
+        assert_eq!(
+            format_stream_table(&info.streams[..1]),
+            "  #  type       codec        details            duration\n  \
+               0  video      h264         1920x1080          -"
+        );
+
+        let audio = parse_media_info(&serde_json::json!({ "streams": [], "format": {} }));
+        assert_eq!(audio.video_codec, None);
+        assert!(audio.to_string().contains("Video: none"));
+    }
+
+    #[test]
+    fn auto_gain_reaches_the_target_without_clipping() {
+        let stderr = "[Parsed_volumedetect_0 @ 0x1] n_samples: 96000\n\
+                      [Parsed_volumedetect_0 @ 0x1] mean_volume: -26.0 dB\n\
+                      [Parsed_volumedetect_0 @ 0x1] max_volume: -10.0 dB\n";
+        assert_eq!(parse_volumedetect(stderr), Some((-26.0, -10.0)));
+        assert_eq!(parse_volumedetect("no stats"), None);
+
+        // 6 dB up to reach the target
+        assert!((auto_gain(-26.0, -10.0) - 1.995).abs() < 0.01);
+        // Limited to 1 dB of headroom by the peak
+        assert!((auto_gain(-26.0, -3.0) - 1.259).abs() < 0.01);
+        // Loud tracks are turned down
+        assert!(auto_gain(-10.0, -1.0) < 1.0);
+    }
+
+    #[test]
+    fn clip_labels_are_escaped_for_the_filtergraph() {
+        assert_eq!(escape_drawtext("clip_1.mkv"), "clip_1.mkv");
+        assert_eq!(escape_drawtext("it's 1:2"), "it\\\\\\'s 1\\\\:2");
+        assert_eq!(escape_drawtext("a, [b]; c"), "a\\, \\[b\\]\\; c");
+        assert_eq!(escape_drawtext("C:\\clips"), "C\\\\:\\\\\\\\clips");
+
+        let label = ClipLabel { text: "100%.mkv".to_string(), start_us: Some(61_500_000) };
+        let filter = drawtext_filter(&label);
+        assert!(filter.starts_with(",drawtext=text=100%.mkv:expansion=none:"));
+        assert!(filter.contains(",drawtext=text=%{pts\\\\:hms\\\\:61.500}:"));
+    }
+
+    #[test]
+    fn batches_cover_every_file_in_order_without_lone_files() {
+        assert_eq!(batch_ranges(3, 50), vec![0..3]);
+        assert_eq!(batch_ranges(100, 50), vec![0..50, 50..100]);
+        assert_eq!(batch_ranges(101, 50), vec![0..50, 50..101]);
+
+        let ranges = batch_ranges(1000, 7);
+        let covered: Vec<usize> = ranges.iter().cloned().flatten().collect();
+        assert_eq!(covered, (0..1000).collect::<Vec<_>>());
+        assert!(ranges.iter().all(|range| range.len() >= 2 && range.len() <= 8));
+    }
+
+    #[test]
+    fn joining_in_batches_gives_the_same_result_as_one_join() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..237)
+            .map(|index| {
+                let file = dir.path().join(format!("clip-{}.txt", index));
+                std::fs::write(&file, format!("{},", index)).unwrap();
+                file
+            })
+            .collect();
+        // Stands in for ffmpeg, joins the files end to end
+        let join = |batch: Batch, output: PathBuf| {
+            assert!(batch.files.len() <= 10);
+            let mut joined = String::new();
+            for file in &batch.files {
+                joined.push_str(&std::fs::read_to_string(file)?);
+            }
+            std::fs::write(&output, joined)?;
+            Ok(output)
+        };
+
+        let output = dir.path().join("joined.txt");
+        let durations_us = vec![1_000_000; files.len()];
+        run_in_batches(files.clone(), durations_us, 0, &output, 10, join).unwrap();
+        let expected: String = (0..237).map(|index| format!("{},", index)).collect();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), expected);
+        // Only the clips and the output are left, the batch results are gone
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), files.len() + 1);
+
+        assert_eq!(joined_duration_us(&[3_000_000, 3_000_000, 3_000_000], 500_000), 8_000_000);
+    }
+
+    #[test]
+    fn one_more_file_than_a_batch_is_joined_straight_to_the_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: Vec<PathBuf> = (0..11).map(|index| dir.path().join(index.to_string())).collect();
+        let output = dir.path().join("joined.txt");
+        let mut joins: Vec<(usize, usize, PathBuf)> = Vec::new();
+        let join = |batch: Batch, batch_output: PathBuf| {
+            joins.push((batch.round, batch.files.len(), batch_output.clone()));
+            Ok(batch_output)
+        };
+
+        run_in_batches(files, vec![1_000_000; 11], 0, &output, 10, join).unwrap();
+        assert_eq!(joins, vec![(0, 11, output)]);
+    }
+
+    #[test]
+    fn extra_ffmpeg_args_that_break_the_output_command_are_rejected() {
+        let args = |args: &[&str]| -> Vec<String> {
+            args.iter().map(|arg| arg.to_string()).collect()
+        };
+        assert!(validate_extra_ffmpeg_args(&[]).is_ok());
+        assert!(validate_extra_ffmpeg_args(&args(&["-map", "0:v", "-map", "1:a"])).is_ok());
+        assert!(validate_extra_ffmpeg_args(&args(&["-metadata", "title=Day 1"])).is_ok());
+        assert!(validate_extra_ffmpeg_args(&args(&["title=Day 1"])).is_err());
+        assert!(validate_extra_ffmpeg_args(&args(&["-i", "music.mp3"])).is_err());
+        assert!(validate_extra_ffmpeg_args(&args(&["-metadata", "-y"])).is_err());
+        assert!(validate_extra_ffmpeg_args(&args(&["-shortest", " "])).is_err());
+    }
+
+    #[test]
+    fn output_audio_bitrates_and_profiles_need_an_encoder() {
+        let voice = OutputAudio {
+            codec: AudioCodec::Aac,
+            bitrate: Some(96_000),
+            aac_profile: Some(AacProfile::Low),
+        };
+        assert_eq!(voice.codec_args(), vec![
+            "-c:a",
+            "aac",
+            "-b:a",
+            "96000",
+            "-profile:a",
+            "aac_low",
+            "-strict",
+            "experimental"
+        ]);
+        assert!(voice.validate().is_ok());
+
+        let copied = OutputAudio { codec: AudioCodec::Copy, ..voice };
+        assert_eq!(copied.codec_args(), vec!["-c:a", "copy"]);
+        assert!(copied.validate().is_err());
+        let opus = OutputAudio { codec: AudioCodec::Opus, bitrate: None, ..voice };
+        assert!(opus.validate().is_err());
+        assert!(OutputAudio { aac_profile: None, ..opus }.validate().is_ok());
+    }
+
+    #[test]
+    fn audio_fades_are_cut_to_the_output_length() {
+        assert_eq!(audio_fade_filter(None, Some(0.0), 10_000_000), None);
+        assert_eq!(
+            audio_fade_filter(Some(2.0), Some(3.0), 60_000_000).as_deref(),
+            Some("afade=t=in:st=0:d=2.000,afade=t=out:st=57.000:d=3.000")
+        );
+        assert_eq!(
+            audio_fade_filter(None, Some(30.0), 12_500_000).as_deref(),
+            Some("afade=t=out:st=0.000:d=12.500")
+        );
+        assert_eq!(
+            audio_fade_filter(Some(30.0), None, 12_500_000).as_deref(),
+            Some("afade=t=in:st=0:d=12.500")
+        );
+    }
+
+    #[test]
+    fn nested_steps_only_count_for_the_inner_stage() {
+        let stage_times = StageTimes::default();
+        let started = Instant::now();
+        for _ in 0..2 {
+            stage_times
+                .step("extract", None, || {
+                    thread::sleep(Duration::from_millis(10));
+                    stage_times.step("merge", None, || {
+                        thread::sleep(Duration::from_millis(100));
+                        Ok(())
+                    })
+                })
+                .unwrap();
+        }
+        let wall = started.elapsed().as_secs_f64();
+        let times = stage_times.into_times();
+        assert_eq!((times[0].stage.as_str(), times[0].runs), ("extract", 2));
+        assert_eq!((times[1].stage.as_str(), times[1].runs), ("merge", 2));
+        assert!(times[0].seconds >= 0.02, "{:?}", times);
+        assert!(times[1].seconds >= 0.2, "{:?}", times);
+        // Counted twice the stages would add up to more than the time that passed, however
+        // slow the machine is
+        assert!(times[0].seconds + times[1].seconds <= wall + 1e-6, "{:?} in {}s", times, wall);
+
+        let stages = vec![
+            StageTime { stage: "extract".to_string(), runs: 3, seconds: 12.34 },
+            StageTime { stage: "mux".to_string(), runs: 1, seconds: 1.0 }
+        ];
+        assert_eq!(
+            format_stage_times(&stages, 10.0),
+            [
+                "Time per stage:",
+                "  extract     12.3s (3 runs)",
+                "  mux          1.0s",
+                "  total       10.0s",
+            ].join("\n")
+        );
+    }
+
+    #[test]
+    fn splitting_gives_numbered_parts_up_to_the_end() {
+        assert_eq!(
+            split_ranges(&[30.0, 105.0]),
+            vec![
+                TrimRange { start: None, end: Some(30.0) },
+                TrimRange { start: Some(30.0), end: Some(105.0) },
+                TrimRange { start: Some(105.0), end: None }
+            ]
+        );
+        let segment = |output: &str, index, count| segment_path(Path::new(output), index, count);
+        assert_eq!(segment("out/parts.mkv", 0, 3), PathBuf::from("out/parts_1.mkv"));
+        assert_eq!(segment("parts.mkv", 9, 12), PathBuf::from("parts_10.mkv"));
+        assert_eq!(segment("parts.mkv", 0, 12), PathBuf::from("parts_01.mkv"));
+        assert_eq!(segment("parts", 1, 2), PathBuf::from("parts_2"));
+    }
+
+    #[test]
+    fn animations_are_picked_by_extension_and_sized_after_the_first_clip() {
+        let format = |output: &str| AnimationFormat::from_output(Path::new(output));
+        assert_eq!(format("out.GIF"), Some(AnimationFormat::Gif));
+        assert_eq!(format("out.webp"), Some(AnimationFormat::Webp));
+        assert_eq!(format("out.mp4"), None);
+        assert_eq!(format("-"), None);
+
+        let clip = VideoStreamInfo {
+            codec: "h264".to_string(),
+            width: 1920,
+            height: 1080,
+            frame_rate: "60/1".to_string(),
+            rotation: 0,
+        };
+        assert_eq!(animation_size(&clip, None, 480), (480, 270));
+        assert_eq!(animation_size(&clip, None, 321), (322, 180));
+        assert_eq!(animation_size(&clip, Some((320, 320)), 480), (320, 320));
+        let portrait = VideoStreamInfo { rotation: 90, ..clip };
+        assert_eq!(animation_size(&portrait, None, 270), (270, 480));
+
+        assert_eq!(
+            animation_filter(2, (480, 270), 15.0),
+            "[0:v:0]fps=15,scale=480:270:force_original_aspect_ratio=decrease:flags=lanczos,\
+pad=480:270:(ow-iw)/2:(oh-ih)/2,setsar=1[v0];\
+[1:v:0]fps=15,scale=480:270:force_original_aspect_ratio=decrease:flags=lanczos,\
+pad=480:270:(ow-iw)/2:(oh-ih)/2,setsar=1[v1];\
+[v0][v1]concat=n=2:v=1:a=0"
+        );
+    }
+
+    #[test]
+    fn target_sizes_leave_room_for_the_audio() {
+        // 10 MB over 100 s with 1 MB of audio
+        let bitrate = target_video_bitrate(10_000_000, 100_000_000, 8_000_000).unwrap();
+        assert_eq!(bitrate, 704_000);
+        assert!(target_video_bitrate(1_000_000, 100_000_000, 8_000_000).is_err());
+        assert!(target_video_bitrate(10_000_000, 0, 0).is_err());
+    }
+
+    #[test]
+    fn concat_list_line_escapes_single_quotes() {
+        assert_eq!(
+            concat_list_line(Path::new("/videos/my clip.mp4")),
+            b"file '/videos/my clip.mp4'"
+        );
+        assert_eq!(
+            concat_list_line(Path::new("/videos/my'clip.mp4")),
+            b"file '/videos/my'\\''clip.mp4'"
+        );
+    }
+
+    // Latin-1 `é`, a name an old camera or a zip from another system may well have
+    #[cfg(unix)]
+    fn non_utf8_path(dir: &Path, prefix: &str) -> PathBuf {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+        let mut name = prefix.as_bytes().to_vec();
+        name.extend_from_slice(b"caf\xe9.mp4");
+        dir.join(OsStr::from_bytes(&name))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_names_are_kept_byte_for_byte() {
+        let clip = non_utf8_path(Path::new("/videos"), "");
+        assert_eq!(concat_list_line(&clip), b"file '/videos/caf\xe9.mp4'");
+
+        let first = segment_path(&clip, 0, 2);
+        assert_eq!(first.file_name().unwrap().as_encoded_bytes(), b"caf\xe9_1.mp4");
+
+        let watched = non_utf8_path(Path::new("/in"), "day_");
+        let set = watch::set_name(&watched).unwrap();
+        assert_eq!(set.as_encoded_bytes(), b"day");
+        let stem = expand_template("{first_stem}.mkv", &[clip]);
+        assert_eq!(stem.as_encoded_bytes(), b"caf\xe9.mkv");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn concatenates_files_with_non_utf8_names() {
+        if !ffmpeg_available() {
+            eprintln!("ffmpeg not found, skipping");
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = non_utf8_path(dir.path(), "");
+        generate_clip(&clip, 1, "64x64");
+        let inputs = expand_inputs(&[clip.clone().into_os_string()], false, true).unwrap();
+        assert_eq!(inputs, std::slice::from_ref(&clip));
+
+        let output = non_utf8_path(dir.path(), "out_");
+        let clips = vec![clip.clone(), clip];
+        concatenate_video_files(clips, output.clone(), false, 0, |_| {}).unwrap();
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn concatenates_files_with_quotes_in_their_names() {
+        if !ffmpeg_available() {
+            eprintln!("ffmpeg not found, skipping");
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("my'clip.mp4");
+        generate_clip(&clip, 1, "64x64");
+
+        let output = dir.path().join("out.mp4");
+        let clips = vec![clip.clone(), clip];
+        concatenate_video_files(clips, output.clone(), false, 0, |_| {}).unwrap();
+        assert!(output.exists());
+    }
+
+    #[test]
+    fn only_streamable_containers_can_be_piped() {
+        assert!(is_stdout_output(Path::new("-")));
+        assert!(is_stdout_output(Path::new("pipe:1")));
+        assert!(!is_stdout_output(Path::new("out.mkv")));
+        assert!(validate_pipe_format(PipeFormat::Matroska).is_ok());
+        assert!(matches!(validate_pipe_format(PipeFormat::Mp4), Err(MyError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn intermediate_names_only_depend_on_the_last_extension() {
+        assert_eq!(clip_extension(Path::new("a.mp4")), "mp4");
+        assert_eq!(clip_extension(Path::new("a.b.mp4")), "mp4");
+        assert_eq!(clip_extension(Path::new("noext")), "mkv");
+        assert_eq!(clip_extension(Path::new("clips.v2/noext")), "mkv");
+        assert_eq!(clip_extension(Path::new("/videos/2024.01/clip.final.mov")), "mov");
+
+        // The clip position keeps clips with the same stem apart
+        let work_dir = Path::new("/tmp/job");
+        assert_eq!(
+            intermediate_path(work_dir, 3, "trimmed", &clip_extension(Path::new("a.b.mp4"))),
+            work_dir.join("clip-3_trimmed.mp4")
+        );
+        assert_ne!(
+            intermediate_path(work_dir, 0, "track-0", "ogg"),
+            intermediate_path(work_dir, 1, "track-0", "ogg")
+        );
+    }
+
+    #[test]
+    fn output_containers_must_hold_the_codecs() {
+        let check = |output: &str, video: Option<&str>, audio: &str| {
+            validate_output_container(Path::new(output), PipeFormat::Matroska, video, audio)
+        };
+        assert!(check("out.mp4", Some("h264"), "aac").is_ok());
+        assert!(check("OUT.MKV", Some("hevc"), "vorbis").is_ok());
+        assert!(check("out.m4a", None, "aac").is_ok());
+        assert!(check("-", Some("h264"), "vorbis").is_ok());
+
+        let rejected = [
+            check("out.xyz", Some("h264"), "aac"),
+            check("out", Some("h264"), "aac"),
+            check("out.mp4", Some("h264"), "vorbis"),
+            check("out.webm", Some("h264"), "opus"),
+            check("out.m4a", Some("h264"), "aac"),
+        ];
+        for result in rejected {
+            assert!(matches!(result, Err(MyError::InvalidInput(_))));
+        }
+    }
+
+    // The pipeline tests below run real ffmpeg jobs on tiny synthetic clips and skip
+    // themselves when ffmpeg or ffprobe isn't installed
+
+    fn tools_available() -> bool {
+        if ensure_tools_available().is_ok() {
+            return true;
+        }
+        eprintln!("ffmpeg/ffprobe not found, skipping");
+        false
+    }
+
+    fn generate_clip(path: &Path, seconds: u32, size: &str) {
+        let status = Tool::Ffmpeg.command()
+            .args(["-y", "-hide_banner", "-loglevel", "error"])
+            .args(["-f", "lavfi", "-i"])
+            .arg(format!("testsrc=duration={}:size={}:rate=10", seconds, size))
+            .args(["-f", "lavfi", "-i"])
+            .arg(format!("sine=frequency=440:duration={}", seconds))
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "aac", "-shortest"])
+            .arg(path)
+            .status()
+            .unwrap();
+        assert!(status.success(), "Failed to generate {}", path.display());
+    }
+
+    fn stream_types(path: &PathBuf) -> Vec<String> {
+        let output = Tool::Ffprobe.command()
+            .args(["-v", "error", "-show_entries", "stream=codec_type", "-of", "csv=p=0"])
+            .arg(path)
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    fn assert_combined(output: &PathBuf, expected_seconds: f64) {
+        assert!(output.exists());
+        assert_eq!(stream_types(output), vec!["video", "audio"]);
+        let duration = probe_duration(output).unwrap();
+        assert!(
+            (duration - expected_seconds).abs() < 0.25,
+            "expected about {}s, got {}s",
+            expected_seconds,
+            duration
+        );
+    }
+
+    #[test]
+    fn combines_clips_by_stream_copy() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.mp4");
+        let second = dir.path().join("second.mp4");
+        generate_clip(&first, 1, "64x64");
+        generate_clip(&second, 2, "64x64");
+
+        let output = dir.path().join("out.mp4");
+        let summary = combine_and_encode_videos(
+            CombineConfig::builder(vec![first, second], &output).build(),
+            |_| {}
+        ).unwrap();
+
+        assert_eq!(summary.inputs.len(), 2);
+        assert_combined(&output, 3.0);
+    }
+
+    #[test]
+    fn writes_only_the_audio_in_audio_only_mode() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.mp4");
+        let second = dir.path().join("second.mp4");
+        generate_clip(&first, 1, "64x64");
+        generate_clip(&second, 2, "64x64");
+
+        let output = dir.path().join("out.m4a");
+        let config = CombineConfig::builder(vec![first, second], &output).audio_only(true).build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+
+        assert_eq!(stream_types(&output), vec!["audio"]);
+        assert!((probe_duration(&output).unwrap() - 3.0).abs() < 0.25);
+    }
+
+    #[test]
+    fn combines_clips_of_different_sizes_by_re_encoding() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.mp4");
+        let second = dir.path().join("second.mp4");
+        generate_clip(&first, 1, "64x64");
+        generate_clip(&second, 1, "96x48");
+
+        let output = dir.path().join("out.mp4");
+        let config = CombineConfig::builder(vec![first, second], &output)
+            .concat_strategy(ConcatStrategy::ReEncode)
+            .build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+
+        assert_combined(&output, 2.0);
+        assert_eq!(probe_video_stream(&output).unwrap().width, 64);
+    }
+
+    #[test]
+    fn merges_tracks_with_different_sample_rates_and_channels() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mono = dir.path().join("mono.ogg");
+        let stereo = dir.path().join("stereo.ogg");
+        for (path, sample_rate, channels) in [(&mono, "44100", "1"), (&stereo, "48000", "2")] {
+            let status = Tool::Ffmpeg.command()
+                .args(["-y", "-hide_banner", "-loglevel", "error"])
+                .args(["-f", "lavfi", "-i", "sine=frequency=440:duration=1"])
+                .args(["-ar", sample_rate, "-ac", channels, "-c:a", "libvorbis"])
+                .arg(path)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+
+        let output = dir.path().join("merged.ogg");
+        merge_audio_tracks(
+            vec![mono, stereo],
+            output.clone(),
+            AudioEncoding::default(),
+            MixMode::Merge,
+            DEFAULT_SAMPLE_RATE,
+            false
+        ).unwrap();
+
+        // The mono and the stereo track side by side
+        assert_eq!(probe_audio_stream(&output).unwrap(), AudioStreamInfo {
+            sample_rate: 48_000,
+            channels: 3,
+        });
+        assert!((probe_duration(&output).unwrap() - 1.0).abs() < 0.1);
+        assert_eq!(
+            amerge_filter(2, 48_000),
+            "[0:a]aresample=48000[a0];[1:a]aresample=48000[a1];[a0][a1]amerge=inputs=2"
+        );
+    }
+
+    #[test]
+    fn combines_clips_with_different_track_counts() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let one_track = dir.path().join("one_track.mp4");
+        generate_clip(&one_track, 1, "64x64");
+        // A game track in stereo and a mono microphone track at another rate
+        let two_tracks = dir.path().join("two_tracks.mkv");
+        let status = Tool::Ffmpeg.command()
+            .args(["-y", "-hide_banner", "-loglevel", "error"])
+            .args(["-f", "lavfi", "-i", "testsrc=duration=2:size=64x64:rate=10"])
+            .args(["-f", "lavfi", "-i", "sine=frequency=440:duration=2"])
+            .args(["-f", "lavfi", "-i", "sine=frequency=880:duration=2:sample_rate=44100"])
+            .args(["-map", "0", "-map", "1", "-map", "2"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "aac"])
+            .args(["-ac:a:0", "2", "-ac:a:1", "1"])
+            .arg(&two_tracks)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(probe_audio_tracks(&two_tracks).unwrap(), 2);
+
+        let output = dir.path().join("out.mkv");
+        let config = CombineConfig::builder(vec![two_tracks, one_track], &output)
+            .concat_strategy(ConcatStrategy::ReEncode)
+            .build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+
+        assert_combined(&output, 3.0);
+    }
+
+    #[test]
+    fn dropped_background_tracks_are_never_extracted() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("two_tracks.mkv");
+        let status = Tool::Ffmpeg.command()
+            .args(["-y", "-hide_banner", "-loglevel", "error"])
+            .args(["-f", "lavfi", "-i", "testsrc=duration=1:size=64x64:rate=10"])
+            .args(["-f", "lavfi", "-i", "sine=frequency=440:duration=1"])
+            .args(["-f", "lavfi", "-i", "sine=frequency=880:duration=1"])
+            .args(["-map", "0", "-map", "1", "-map", "2"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "aac"])
+            .arg(&clip)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let options = EncodeOptions {
+            drop_background: true,
+            no_pad: true,
+            ..EncodeOptions::default()
+        };
+        let temp_files = Mutex::new(TempFiles::new(true, false));
+        let stage_times = StageTimes::default();
+        extract_clip_audio(0, &clip, &clip, dir.path(), &options, &temp_files, &stage_times)
+            .unwrap();
+        let extension = options.intermediate_audio.extension();
+        assert!(!intermediate_path(dir.path(), 0, "track-0", extension).exists());
+        assert!(intermediate_path(dir.path(), 0, "track-1", extension).exists());
+    }
+
+    #[test]
+    fn clip_audio_steps_are_timed() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("clip.mp4");
+        generate_clip(&clip, 1, "64x48");
+
+        let options = EncodeOptions { no_pad: true, ..EncodeOptions::default() };
+        let temp_files = Mutex::new(TempFiles::new(true, false));
+        let stage_times = StageTimes::default();
+        extract_clip_audio(0, &clip, &clip, dir.path(), &options, &temp_files, &stage_times)
+            .unwrap();
+        let stages: Vec<String> = stage_times
+            .into_times()
+            .into_iter()
+            .map(|time| time.stage)
+            .collect();
+        // Mix runs even a lone track through the merge step
+        assert_eq!(stages, vec!["merge"]);
+    }
+
+    #[test]
+    fn pads_short_clip_audio_to_the_video_length() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut clips: Vec<PathBuf> = Vec::new();
+        for name in ["first.mp4", "second.mp4"] {
+            // The audio stops half a second before the video
+            let clip = dir.path().join(name);
+            let status = Tool::Ffmpeg.command()
+                .args(["-y", "-hide_banner", "-loglevel", "error"])
+                .args(["-f", "lavfi", "-i", "testsrc=duration=2:size=64x64:rate=10"])
+                .args(["-f", "lavfi", "-i", "sine=frequency=440:duration=1.5"])
+                .args(["-c:v", "libx264", "-pix_fmt", "yuv420p", "-c:a", "aac"])
+                .arg(&clip)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            clips.push(clip);
+        }
+
+        let output = dir.path().join("out.mp4");
+        combine_and_encode_videos(CombineConfig::builder(clips, &output).build(), |_| {}).unwrap();
+
+        let stream_duration = |stream: &str| {
+            let output = Tool::Ffprobe.command()
+                .args(["-v", "error", "-select_streams", stream])
+                .args(["-show_entries", "stream=duration", "-of", "csv=p=0"])
+                .arg(&output)
+                .output()
+                .unwrap();
+            String::from_utf8_lossy(&output.stdout).trim().parse::<f64>().unwrap()
+        };
+        let drift = (stream_duration("v:0") - stream_duration("a:0")).abs();
+        assert!(drift < 0.1, "audio and video differ by {}s", drift);
+    }
+
+    #[test]
+    fn matroska_duration_tags_are_read_as_seconds() {
+        assert_eq!(parse_tag_duration("00:01:02.500000000"), Some(62.5));
+        assert_eq!(parse_tag_duration("01:00:00"), Some(3600.0));
+        assert_eq!(parse_tag_duration("N/A"), None);
+    }
+
+    #[test]
+    fn crossfades_shorten_the_output_by_the_overlap() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.mp4");
+        let second = dir.path().join("second.mp4");
+        generate_clip(&first, 1, "64x64");
+        generate_clip(&second, 2, "64x64");
+
+        let output = dir.path().join("out.mp4");
+        let config = CombineConfig::builder(vec![first, second], &output)
+            .crossfade(Some(0.5))
+            .build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+
+        assert_combined(&output, 2.5);
+    }
+
+    // Tags `input` as recorded by a phone held upright
+    fn rotate_clip(input: &Path, output: &Path) {
+        let mut command = Tool::Ffmpeg.command();
+        command.args(["-y", "-hide_banner", "-loglevel", "error"]);
+        if ffmpeg_supports_display_rotation() {
+            command.args(["-display_rotation:v:0", "-90"]).arg("-i").arg(input);
+        } else {
+            command.arg("-i").arg(input).args(["-metadata:s:v:0", "rotate=90"]);
+        }
+        let status = command.args(["-c", "copy"]).arg(output).status().unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn keeps_the_rotation_of_stream_copied_clips() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("clip.mp4");
+        let rotated = dir.path().join("rotated.mp4");
+        generate_clip(&clip, 1, "64x32");
+        rotate_clip(&clip, &rotated);
+        assert_eq!(probe_rotation(&rotated).unwrap(), 90);
+        assert!(describe_stream_mismatch(&[rotated.clone(), clip]).unwrap().is_some());
+
+        let output = dir.path().join("out.mp4");
+        let config = CombineConfig::builder(vec![rotated.clone(), rotated], &output).build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+
+        assert_combined(&output, 2.0);
+        assert_eq!(probe_rotation(&output).unwrap(), 90);
+    }
+
+    #[test]
+    fn trims_clips_before_combining() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("clip.mp4");
+        generate_clip(&clip, 3, "64x64");
+
+        let output = dir.path().join("out.mp4");
+        let config = CombineConfig::builder(vec![clip.clone(), clip], &output)
+            .trims(vec![Some(TrimRange { start: Some(1.0), end: None }), None])
+            .build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+
+        assert_combined(&output, 5.0);
+    }
+
+    #[test]
+    fn splits_a_clip_into_parts_that_cover_all_of_it() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("clip.mkv");
+        generate_clip(&clip, 3, "64x64");
+
+        let output = dir.path().join("part.mkv");
+        assert!(split_video(&clip, &[2.0, 1.0], &output, true, false, false).is_err());
+        assert!(split_video(&clip, &[5.0], &output, true, false, false).is_err());
+
+        let parts = split_video(&clip, &[1.0, 2.0], &output, true, false, false).unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                dir.path().join("part_1.mkv"),
+                dir.path().join("part_2.mkv"),
+                dir.path().join("part_3.mkv")
+            ]
+        );
+        for part in &parts {
+            assert_eq!(stream_types(part), vec!["video", "audio"]);
+            assert!((probe_duration(part).unwrap() - 1.0).abs() < 0.25, "{}", part.display());
+        }
+        // The parts are outputs of their own, so a second split doesn't replace them unasked
+        assert!(split_video(&clip, &[1.0, 2.0], &output, true, false, false).is_err());
+        split_video(&clip, &[1.0, 2.0], &output, true, true, false).unwrap();
+    }
+
+    #[test]
+    fn empty_and_truncated_inputs_are_reported_with_a_reason() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("clip.mp4");
+        generate_clip(&clip, 1, "64x64");
+        let empty = dir.path().join("empty.mp4");
+        std::fs::write(&empty, b"").unwrap();
+        // Cut off before the index, like a download that stopped halfway
+        let truncated = dir.path().join("truncated.mp4");
+        let bytes = std::fs::read(&clip).unwrap();
+        std::fs::write(&truncated, &bytes[..bytes.len() / 4]).unwrap();
+
+        let unreadable = find_unreadable_inputs(&[clip, empty.clone(), truncated.clone()]);
+        let files: Vec<&PathBuf> = unreadable.iter().map(|(file, _)| file).collect();
+        assert_eq!(files, vec![&empty, &truncated]);
+        assert_eq!(unreadable[0].1, "the file is empty");
+        assert!(!unreadable[1].1.is_empty());
+    }
+
+    #[test]
+    fn combines_clips_into_a_looping_gif() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.mp4");
+        let second = dir.path().join("second.mp4");
+        generate_clip(&first, 1, "64x48");
+        generate_clip(&second, 1, "96x48");
+
+        let output = dir.path().join("out.gif");
+        let config = CombineConfig::builder(vec![first, second], &output)
+            .animation_width(Some(32))
+            .target_fps(Some(10.0))
+            .build();
+        let summary = combine_and_encode_videos(config, |_| {}).unwrap();
+
+        assert_eq!(stream_types(&output), vec!["video"]);
+        let stream = probe_video_stream(&output).unwrap();
+        assert_eq!((stream.codec.as_str(), stream.width, stream.height), ("gif", 32, 24));
+        assert!((summary.duration_seconds - 2.0).abs() < 0.25);
+        assert!(summary.temp_files_removed.iter().any(|file| file.ends_with("palette.png")));
+    }
+
+    #[test]
+    fn keeps_hdr_color_tags_unless_tonemapping() {
+        let sdr = ColorInfo::bt709();
+        let hdr = ColorInfo {
+            primaries: Some("bt2020".to_string()),
+            transfer: Some("smpte2084".to_string()),
+            space: Some("bt2020nc".to_string()),
+        };
+        assert!(hdr.is_hdr());
+        assert!(!sdr.is_hdr());
+        assert!(!ColorInfo::default().is_hdr());
+        assert_eq!(
+            hdr.output_args(),
+            ["-color_primaries", "bt2020", "-color_trc", "smpte2084", "-colorspace", "bt2020nc"]
+        );
+        assert!(ColorInfo::default().output_args().is_empty());
+
+        let colors = vec![sdr.clone(), hdr.clone()];
+        assert_eq!(output_color(&colors, false), Some(hdr.clone()));
+        assert_eq!(output_color(&colors, true), Some(sdr.clone()));
+        assert_eq!(output_color(&[sdr], false), None);
+
+        let encoding = VideoEncoding { color: Some(hdr), ..Default::default() };
+        assert_eq!(encoding.pixel_format(), "yuv420p10le");
+        let args = encoding.codec_args();
+        assert_eq!(args[args.len() - 2..], ["-colorspace", "bt2020nc"]);
+        assert_eq!(VideoEncoding::default().pixel_format(), "yuv420p");
+    }
+
+    #[test]
+    fn hdr_and_sdr_clips_are_only_joined_when_tonemapping() {
+        let hdr = ColorInfo { transfer: Some("arib-std-b67".to_string()), ..Default::default() };
+        let inputs = vec![PathBuf::from("sdr.mp4"), PathBuf::from("hdr.mp4")];
+        let mixed = vec![ColorInfo::bt709(), hdr.clone()];
+
+        match check_color_mix(&inputs, &mixed, false) {
+            Err(MyError::InvalidInput(message)) => assert!(message.contains("hdr.mp4")),
+            other => panic!("expected an invalid input error, got {:?}", other),
+        }
+        assert!(check_color_mix(&inputs, &mixed, true).is_ok());
+        assert!(check_color_mix(&inputs, &[hdr.clone(), hdr], false).is_ok());
+        assert!(check_color_mix(&inputs, &[], false).is_ok());
+    }
+
+    #[test]
+    fn output_metadata_args() {
+        assert!(OutputMetadata::default().args().is_empty());
+        let metadata = OutputMetadata {
+            strip: true,
+            title: Some("Day 1".to_string()),
+            author: Some("me".to_string()),
+        };
+        assert_eq!(
+            metadata.args(),
+            [
+                "-map_metadata",
+                "-1",
+                "-map_metadata:s",
+                "-1",
+                "-metadata",
+                "title=Day 1",
+                "-metadata",
+                "artist=me",
+            ]
+        );
+    }
+
+    #[test]
+    fn stripped_outputs_only_keep_the_given_tags() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.mkv");
+        let tagged = dir.path().join("tagged.mkv");
+        let second = dir.path().join("second.mkv");
+        generate_clip(&first, 1, "64x48");
+        generate_clip(&second, 1, "64x48");
+        let status = Tool::Ffmpeg.command()
+            .args(["-y", "-hide_banner", "-loglevel", "error", "-i"])
+            .arg(&first)
+            .args(["-c", "copy", "-metadata", "location=+52.2297+021.0122/"])
+            .arg(&tagged)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let output = dir.path().join("out.mkv");
+        let config = CombineConfig::builder(vec![tagged, second], &output)
+            .metadata(OutputMetadata {
+                strip: true,
+                title: Some("Day 1".to_string()),
+                author: None,
+            })
+            .build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+
+        let probe = Tool::Ffprobe.command()
+            .args(["-v", "error", "-show_entries", "format_tags", "-of", "json"])
+            .arg(&output)
+            .output()
+            .unwrap();
+        let info: serde_json::Value = serde_json::from_slice(&probe.stdout).unwrap();
+        let tags = &info["format"]["tags"];
+        assert_eq!(tags["title"].as_str(), Some("Day 1"));
+        assert!(tags["location"].is_null() && tags["LOCATION"].is_null());
+    }
+
+    #[test]
+    fn repeats_a_single_clip_with_its_audio() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let clip = dir.path().join("clip.mp4");
+        generate_clip(&clip, 1, "64x48");
+
+        let output = dir.path().join("loop.mp4");
+        let config = CombineConfig::builder(vec![clip.clone()], &output).repeat(3).build();
+        let summary = combine_and_encode_videos(config, |_| {}).unwrap();
+        assert_combined(&output, 3.0);
+        assert_eq!((summary.inputs.len(), summary.repeat), (1, 3));
+
+        // Conformed once, the copies name the same file
+        let conformed = dir.path().join("conformed.mp4");
+        let config = CombineConfig::builder(vec![clip.clone()], &conformed)
+            .repeat(3)
+            .target_fps(Some(15.0))
+            .build();
+        let summary = combine_and_encode_videos(config, |_| {}).unwrap();
+        assert_combined(&conformed, 3.0);
+        let conform = summary.stages.iter().find(|stage| stage.stage == "conform").unwrap();
+        assert_eq!(conform.runs, 1);
+
+        let two_inputs = CombineConfig::builder(vec![clip.clone(), clip], dir.path().join("x.mp4"))
+            .repeat(2)
+            .build();
+        let result = combine_and_encode_videos(two_inputs, |_| {});
+        assert!(matches!(result, Err(MyError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn outputs_that_are_an_input_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("clip.mp4");
+        std::fs::write(&input, b"").unwrap();
+        let inputs = vec![input.clone()];
+
+        assert!(check_output_not_an_input(&inputs, &dir.path().join("out.mp4")).is_ok());
+        assert!(check_output_not_an_input(&inputs, Path::new("-")).is_ok());
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        let through_sub = dir.path().join("sub/../clip.mp4");
+        assert!(matches!(
+            check_output_not_an_input(&inputs, &through_sub),
+            Err(MyError::InvalidInput(_))
+        ));
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link.mp4");
+            std::os::unix::fs::symlink(&input, &link).unwrap();
+            assert!(check_output_not_an_input(&inputs, &link).is_err());
+        }
+
+        let config = CombineConfig::builder(vec![input.clone(), input.clone()], &input)
+            .overwrite(true)
+            .build();
+        let result = combine_and_encode_videos(config, |_| {});
+        assert!(matches!(result, Err(MyError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn resamples_clips_recorded_at_different_rates() {
+        if !tools_available() {
+            return;
+        }
+
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.mp4");
+        generate_clip(&first, 2, "64x48");
+        let second = dir.path().join("second.mp4");
+        let status = Tool::Ffmpeg.command()
+            .args(["-y", "-hide_banner", "-loglevel", "error", "-i"])
+            .arg(&first)
+            .args(["-c:v", "copy", "-c:a", "aac", "-ar", "48000"])
+            .arg(&second)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(probe_audio_stream(&first).unwrap().sample_rate, 44_100);
+
+        let inputs = vec![first, second];
+        let output = dir.path().join("combined.mp4");
+        let config = CombineConfig::builder(inputs.clone(), &output).build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+        assert_combined(&output, 4.0);
+        assert_eq!(probe_audio_stream(&output).unwrap(), AudioStreamInfo {
+            sample_rate: 48_000,
+            channels: 2,
+        });
+
+        let mono = dir.path().join("mono.mp4");
+        let config = CombineConfig::builder(inputs, &mono)
+            .sample_rate(Some(44_100))
+            .channels(Some(1))
+            .build();
+        combine_and_encode_videos(config, |_| {}).unwrap();
+        assert_combined(&mono, 4.0);
+        assert_eq!(probe_audio_stream(&mono).unwrap(), AudioStreamInfo {
+            sample_rate: 44_100,
+            channels: 1,
+        });
+    }
+
+    #[test]
+    fn only_x264_gets_the_preset() {
+        let encoding = VideoEncoding {
+            quality: Some(VideoQuality::Crf(20)),
+            preset: Preset::Slow,
+            ..Default::default()
+        };
+        assert_eq!(encoding.codec_args(), vec!["-c:v", "libx264", "-crf", "20", "-preset", "slow"]);
+
+        let nvenc = VideoEncoding { encoder: Encoder::Nvenc, preset: Preset::Slow, ..encoding };
+        assert!(!nvenc.codec_args().contains(&"-preset".to_string()));
+    }
+}
@@ -1,14 +1,24 @@
-use std::fmt;
-use std::{ error::Error, path::PathBuf, process::Command, io::BufWriter };
-use std::io::Write;
-use clap::Parser;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::{ path::{ Path, PathBuf }, process::{ Command, Stdio } };
+use std::io::{ IsTerminal, Write };
+use std::thread;
+use std::time::{ Duration, Instant };
+use clap::{ builder::ArgPredicate, CommandFactory, Parser, Subcommand, ValueEnum };
+use clap_complete::Shell;
+use fltk::browser::HoldBrowser;
 use fltk::frame::Frame;
-use fltk::input::FloatInput;
+use fltk::image::SharedImage;
+use fltk::input::{ FloatInput, Input };
+use fltk::menu::Choice;
+use fltk::misc::{ Progress, Spinner };
+use fltk::text::{ TextBuffer, TextDisplay };
+use fltk::valuator::HorValueSlider;
 use fltk::{
     app,
-    button::Button,
+    button::{ Button, CheckButton },
     dialog::*,
-    enums::{ Event, Shortcut },
+    enums::{ CallbackTrigger, Event, Key, Shortcut },
     group::Flex,
     menu::{ MenuFlag, SysMenuBar },
     prelude::*,
@@ -17,65 +27,556 @@ use fltk::{
 };
 use fltk_theme::{ widget_themes, ThemeType, WidgetTheme };
 
+use video_editor::{
+    AacProfile, AudioCodec, AudioEncoding, CombineConfig, ConcatStrategy, DEFAULT_BATCH_SIZE,
+    Encoder, IntermediateAudio, IntermediateContainer, LogLevel, MAX_VOLUME, MIN_VOLUME, MixMode,
+    MyError, OutputEstimate, OutputMetadata, PipeFormat, Preset, SUPPORTED_EXTENSIONS,
+    SubtitleMode, Tool, TrackRoles, TrimRange, VideoQuality, cancel_running_jobs,
+    check_output_not_an_input, check_video_file, clear_cancel_request, combine_and_encode_videos,
+    combine_videos, concatenate_audio_files, concatenate_video_files, describe_stream_mismatch,
+    describe_variable_frame_rate, ensure_tools_available, estimate_output, expand_inputs,
+    expand_template, extract_and_adjust_audio, extract_thumbnail, find_unreadable_inputs,
+    format_stream_table, format_trim_bound, is_stdout_output, is_video_file, json_output,
+    natural_sort, pipe_output, probe_audio_tracks, probe_available_encoders, probe_duration_us,
+    probe_media_info, segment_path, set_json_output, set_log_level, set_pipe_output, set_tool_path,
+    split_video, track_volume, validate_inputs, validate_pipe_format, status, verbose,
+    progress_log, watch,
+};
+
+mod config;
+mod manifest;
+
+use config::Config;
+
 static STATE: Lazy<app::GlobalState<State>> = Lazy::new(app::GlobalState::<State>::get);
 
 #[derive(Debug, Parser)]
 #[clap(name = "Video Editor", version = "0.1.0", author = "Gabriel Kaszewski")]
 struct Args {
-    #[clap(short, long)]
-    input: Option<Vec<String>>,
-    #[clap(short, long)]
-    output: Option<String>,
-    #[clap(short, long, default_value = "0.70")]
-    volume: f32,
+    #[clap(subcommand)]
+    command: Option<CliCommand>,
     #[clap(short, long, default_value = "false")]
     cli_mode: bool,
+    // Kept at the top level so `--cli-mode` works without the combine subcommand
+    #[clap(flatten)]
+    combine: CombineArgs,
+    /// Print a single JSON object with the result instead of progress messages
+    #[clap(long, global = true)]
+    json: bool,
+    /// Print more details, repeat for ffmpeg debug output (`-v` is taken by --volume)
+    #[clap(long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only print errors
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// ffmpeg binary to run instead of the one on PATH, overrides FFMPEG_PATH
+    #[clap(long, global = true)]
+    ffmpeg_bin: Option<PathBuf>,
+    /// ffprobe binary to run instead of the one on PATH, overrides FFPROBE_PATH
+    #[clap(long, global = true)]
+    ffprobe_bin: Option<PathBuf>,
 }
 
-#[derive(Debug)]
-struct MyError {
-    message: String,
+// Parsed once per run, so the size of the combine arguments doesn't matter
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Subcommand)]
+enum CliCommand {
+    /// Combine videos into one file, same as `--cli-mode`
+    Combine(CombineArgs),
+    /// Extract one audio track of a video, adjusting its volume
+    ExtractAudio {
+        #[clap(short, long)]
+        input: PathBuf,
+        #[clap(short, long)]
+        output: PathBuf,
+        /// Index of the audio track, starting at 0
+        #[clap(long, default_value = "0")]
+        track: usize,
+        #[clap(short, long, default_value = "1.0", value_parser = parse_volume)]
+        volume: f32,
+        /// Codec the track is encoded with, should match the output extension
+        #[clap(long, value_enum, default_value = "vorbis")]
+        format: IntermediateAudio,
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Join audio files end to end with stream copy, they need the same format
+    ConcatAudio {
+        #[clap(short, long, required = true)]
+        input: Vec<PathBuf>,
+        #[clap(short, long)]
+        output: PathBuf,
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Join the video streams of files end to end with stream copy, audio is dropped
+    ConcatVideo {
+        #[clap(short, long, required = true)]
+        input: Vec<PathBuf>,
+        #[clap(short, long)]
+        output: PathBuf,
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Cut a video into numbered parts at the given times, the last part runs to the end
+    Split {
+        #[clap(short, long)]
+        input: PathBuf,
+        /// Name the parts are numbered after, `parts.mkv` gives `parts_1.mkv`, `parts_2.mkv`, ...
+        #[clap(short, long)]
+        output: PathBuf,
+        /// Times to cut at, in seconds or [HH:]MM:SS, e.g. `0:30,1:45`
+        #[clap(long, required = true, value_delimiter = ',', value_parser = parse_split_time)]
+        at: Vec<f64>,
+        /// Re-encode the video so every part starts on the exact frame, by default the parts
+        /// are stream copied and start at the keyframe before
+        #[clap(long)]
+        reencode: bool,
+        /// Replace parts that already exist instead of asking
+        #[clap(long, conflicts_with = "no_overwrite")]
+        overwrite: bool,
+        /// Fail if a part already exists instead of asking
+        #[clap(short = 'n', long)]
+        no_overwrite: bool,
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Print the streams of every input (index, type, codec, size or sample rate, duration),
+    /// to spot what keeps them from being stream copied together
+    Streams {
+        #[clap(short, long, required = true)]
+        input: Vec<PathBuf>,
+    },
+    /// Print a completion script for a shell to stdout, e.g. `video_editor completions bash`
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Debug, Clone, clap::Args)]
+struct CombineArgs {
+    /// Input file, directory or glob pattern such as `clips/*.mp4`, can be repeated
+    #[clap(short, long)]
+    input: Option<Vec<OsString>>,
+    /// Also pick up videos in subdirectories of directory inputs
+    #[clap(long)]
+    recursive: bool,
+    /// Keep directory and glob matches in plain name order instead of sorting numbers by
+    /// value (`clip2` before `clip10`)
+    #[clap(long)]
+    no_sort: bool,
+    /// Leave out inputs ffprobe can't read or that have no duration (e.g. truncated downloads)
+    /// instead of failing before anything runs
+    #[clap(long)]
+    skip_bad: bool,
+    /// Output file, or the folder the results go to with --watch
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// Output file named from the inputs, `{first_stem}`, `{count}` and `{date}` are filled in,
+    /// e.g. `{first_stem}_combined.mp4`
+    #[clap(long, conflicts_with_all = ["output", "watch"])]
+    output_template: Option<String>,
+    /// Folder a relative --output is resolved against instead of the working directory,
+    /// created if it doesn't exist
+    #[clap(long)]
+    output_dir: Option<PathBuf>,
+    /// Keep watching this folder and combine every complete set of clips that appears in it
+    #[clap(long, conflicts_with_all = ["input", "trim"])]
+    watch: Option<PathBuf>,
+    /// TOML file with a `[[job]]` per output, each with its own inputs, output and settings,
+    /// the other options are the defaults of every job
+    #[clap(long, conflicts_with_all = ["input", "output", "output_template", "watch", "trim"])]
+    manifest: Option<PathBuf>,
+    /// File names picked up by --watch
+    #[clap(long, default_value = "*.mkv")]
+    watch_pattern: String,
+    /// Number of clips that make a complete set with --watch, clips belong to the set named
+    /// by their file name up to the last `_`
+    #[clap(long, default_value_t = 2, value_parser = clap::value_parser!(u16).range(1..))]
+    watch_count: u16,
+    /// Comma separated volume per audio track, missing tracks default to 1.0.
+    /// With --auto-gain only the given tracks are set by hand
+    #[clap(
+        short,
+        long,
+        value_delimiter = ',',
+        default_value = "0.70",
+        default_value_if("auto_gain", ArgPredicate::IsPresent, None),
+        value_parser = parse_volume
+    )]
+    volume: Vec<f32>,
+    /// Measure every audio track and pick a volume that brings it to a common loudness
+    #[clap(long)]
+    auto_gain: bool,
+    /// Index of the audio track with the game or music, only it and the voiceover track are
+    /// used and -v then sets their volumes in that order
+    #[clap(long, requires = "voiceover_track")]
+    background_track: Option<usize>,
+    /// Index of the audio track with the microphone, see --background-track
+    #[clap(long, requires = "background_track")]
+    voiceover_track: Option<usize>,
+    /// Leave out the background track (track 0, or --background-track) and only keep the
+    /// voiceover, -v still counts the background first
+    #[clap(long)]
+    drop_background: bool,
+    #[clap(long)]
+    dry_run: bool,
+    #[clap(long, value_enum, default_value = "ts")]
+    intermediate_container: IntermediateContainer,
+    #[clap(long, value_enum, default_value = "vorbis")]
+    intermediate_audio: IntermediateAudio,
+    /// Vorbis quality of the intermediate audio from 0 to 10 (-q:a), higher keeps more detail
+    /// for the final encode, ffmpeg picks 3 by default. wav and flac are lossless anyway.
+    #[clap(long, value_parser = parse_intermediate_quality)]
+    intermediate_quality: Option<f32>,
+    #[clap(long, value_enum, default_value = "aac")]
+    audio_codec: AudioCodec,
+    /// Bitrate of the output audio, e.g. `96k` for voice or `256k` for music,
+    /// the encoder picks one when not given
+    #[clap(long, value_parser = parse_audio_bitrate)]
+    audio_bitrate: Option<u32>,
+    /// AAC profile of the output audio, `low` (AAC-LC) plays everywhere
+    #[clap(long, value_enum)]
+    aac_profile: Option<AacProfile>,
+    /// How the audio tracks of every clip are combined: mix them into stereo or merge their
+    /// channels side by side
+    #[clap(long, value_enum, default_value = "mix")]
+    mix_mode: MixMode,
+    /// Sample rate of the output audio in Hz, 48000 by default
+    #[clap(long, value_parser = clap::value_parser!(u32).range(8000..=192_000))]
+    sample_rate: Option<u32>,
+    /// Channels of the output audio, stereo by default, merged tracks keep theirs without it
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..=8))]
+    channels: Option<u32>,
+    /// Trim an input to a time range in seconds, e.g. `0=5-30` keeps 5s to 30s of the first input
+    #[clap(long, value_parser = parse_trim_arg)]
+    trim: Vec<(usize, TrimRange)>,
+    /// Re-encode every clip to WIDTHxHEIGHT before concatenating, e.g. `1920x1080`
+    #[clap(long, value_parser = parse_resolution)]
+    resolution: Option<(u32, u32)>,
+    /// Re-encode every clip to this frame rate before concatenating, e.g. `30`
+    #[clap(long, value_parser = parse_fps)]
+    fps: Option<f64>,
+    /// Width of a `.gif` or `.webp` output, the height follows the first clip. --resolution
+    /// sets both instead and --fps the frame rate, which is 15 unless given
+    #[clap(long, value_parser = clap::value_parser!(u32).range(16..=3840))]
+    animation_width: Option<u32>,
+    /// Play the only input this many times in a row, e.g. to loop a background clip. The clip
+    /// is prepared once and the copies are joined like separate inputs
+    #[clap(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    repeat: u16,
+    /// Convert HDR (PQ or HLG) clips to SDR while re-encoding instead of keeping them HDR,
+    /// needs an ffmpeg built with zimg
+    #[clap(long)]
+    tonemap: bool,
+    /// Video encoder used whenever clips have to be re-encoded
+    #[clap(long, value_enum, default_value = "software")]
+    hwaccel: Encoder,
+    /// How clips are joined, picked from the inputs when not given
+    #[clap(long, value_enum)]
+    concat_strategy: Option<ConcatStrategy>,
+    /// Re-encode clips with a variable frame rate to a constant one instead of only warning
+    #[clap(long)]
+    fix_vfr: bool,
+    /// Whether to keep the subtitles of the first clip
+    #[clap(long, value_enum, default_value = "drop")]
+    subtitles: SubtitleMode,
+    /// Add a chapter for every input clip
+    #[clap(long)]
+    chapters: bool,
+    /// Burn the file name of every clip into its top left corner, the clips are then re-encoded
+    #[clap(long)]
+    label_clips: bool,
+    /// Also show the running time of the output below the clip labels
+    #[clap(long, requires = "label_clips")]
+    label_time: bool,
+    /// Only write the joined audio, e.g. to `out.m4a`, the video of the clips is skipped
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "resolution",
+            "fps",
+            "concat_strategy",
+            "fix_vfr",
+            "chapters",
+            "label_clips",
+            "crf",
+            "video_bitrate",
+        ]
+    )]
+    audio_only: bool,
+    /// Fade between consecutive clips for this many seconds, e.g. `0.5`, clips are then
+    /// re-encoded. 0 keeps hard cuts
+    #[clap(long, value_parser = parse_crossfade, default_value_t = 0.0)]
+    crossfade: f64,
+    /// Constant quality (0-51, lower is better) used whenever clips are re-encoded
+    #[clap(long, value_parser = clap::value_parser!(u8).range(0..=51))]
+    crf: Option<u8>,
+    /// Target video bitrate used whenever clips are re-encoded, e.g. `8M`
+    #[clap(long, conflicts_with = "crf")]
+    video_bitrate: Option<String>,
+    /// x264 preset used whenever clips are re-encoded, slower ones give smaller files at the
+    /// same quality
+    #[clap(long, value_enum, default_value = "medium")]
+    preset: Preset,
+    /// Re-encode in two passes so the output ends up at about this size, e.g. `100MB`
+    #[clap(
+        long,
+        value_parser = parse_target_size,
+        conflicts_with_all = ["crf", "video_bitrate", "hwaccel", "audio_only"]
+    )]
+    target_size: Option<u64>,
+    /// Normalize the loudness of the final audio (EBU R128, two pass)
+    #[clap(long)]
+    normalize: bool,
+    #[clap(long, default_value = "-23", allow_hyphen_values = true)]
+    target_lufs: f32,
+    /// Fade the final audio in over this many seconds, e.g. `2`
+    #[clap(long, value_parser = parse_fade, default_value_t = 0.0)]
+    fade_in: f64,
+    /// Fade the final audio out over this many seconds at the end of the output
+    #[clap(long, value_parser = parse_fade, default_value_t = 0.0)]
+    fade_out: f64,
+    /// Keep the intermediate files instead of deleting them, useful for bug reports
+    #[clap(long)]
+    keep_temp: bool,
+    /// Keep the finished steps of a failed run and reuse them when the same job is run again
+    /// with --resume, clips that changed since are redone
+    #[clap(long)]
+    resume: bool,
+    /// Leave the audio of every clip as long as it is instead of padding or cutting it to the
+    /// length of the video
+    #[clap(long)]
+    no_pad: bool,
+    /// Append a JSON line with the timestamp, stage, input and status of every pipeline step
+    /// to this file, so a run that died shows how far it got
+    #[clap(long)]
+    log_file: Option<PathBuf>,
+    /// Number of threads ffmpeg encodes with, 0 lets ffmpeg decide
+    #[clap(long, default_value_t = 0)]
+    threads: u32,
+    /// Replace the output file if it already exists instead of asking
+    #[clap(long, conflicts_with = "no_overwrite")]
+    overwrite: bool,
+    /// Fail if the output file already exists instead of asking
+    #[clap(short = 'n', long)]
+    no_overwrite: bool,
+    /// Start right away instead of asking after the job summary
+    #[clap(short = 'y', long)]
+    yes: bool,
+    /// Container used when the output is `-` or `pipe:1` (stdout)
+    #[clap(long, value_enum, default_value_t = PipeFormat::Matroska)]
+    pipe_format: PipeFormat,
+    /// Pass an argument to the ffmpeg call that writes the output, repeat it for every word,
+    /// e.g. `--extra-ffmpeg-arg=-metadata --extra-ffmpeg-arg=title=Day 1`
+    #[clap(long, allow_hyphen_values = true)]
+    extra_ffmpeg_arg: Vec<String>,
+    /// Leave out the tags the clips carry, like the recording device, creation time or location
+    #[clap(long)]
+    strip_metadata: bool,
+    /// Title tag of the output
+    #[clap(long)]
+    title: Option<String>,
+    /// Author of the output, written as the artist tag
+    #[clap(long)]
+    author: Option<String>,
+    /// Most clips a single ffmpeg call opens at once when re-encoding or crossfading, longer
+    /// lists are joined in batches so they don't run into the open file limit
+    #[clap(long, default_value_t = DEFAULT_BATCH_SIZE, value_parser = parse_batch_size)]
+    batch_size: usize,
+}
+
+const RESOLUTION_PRESETS: [(u32, u32); 5] = [
+    (3840, 2160),
+    (2560, 1440),
+    (1920, 1080),
+    (1280, 720),
+    (854, 480),
+];
+
+fn parse_resolution(value: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid resolution '{}', expected WIDTHxHEIGHT", value);
+    let lowercase = value.to_lowercase();
+    let (width, height) = lowercase.split_once('x').ok_or_else(invalid)?;
+
+    match (width.trim().parse::<u32>(), height.trim().parse::<u32>()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
 }
 
-impl MyError {
-    fn new(message: &str) -> MyError {
-        MyError {
-            message: message.to_string(),
+fn parse_volume(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(volume) if (MIN_VOLUME..=MAX_VOLUME).contains(&volume) => Ok(volume),
+        Ok(_) =>
+            Err(format!("Volume `{}` must be between {} and {}", value, MIN_VOLUME, MAX_VOLUME)),
+        Err(_) => Err(format!("Invalid volume `{}`, expected a number", value)),
+    }
+}
+
+fn parse_fps(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(fps) if fps > 0.0 && fps.is_finite() => Ok(fps),
+        _ => Err(format!("Invalid frame rate `{}`, expected a positive number", value)),
+    }
+}
+
+// Seconds, `MM:SS` or `HH:MM:SS`, e.g. `90`, `1:30` or `0:01:30.5`
+fn parse_split_time(value: &str) -> Result<f64, String> {
+    let invalid = || format!("Invalid time `{}`, expected seconds or [HH:]MM:SS", value);
+    let parts: Vec<&str> = value.trim().split(':').collect();
+    if parts.len() > 3 {
+        return Err(invalid());
+    }
+    let mut seconds = 0.0;
+    for (index, part) in parts.iter().enumerate() {
+        let number = part.parse::<f64>().map_err(|_| invalid())?;
+        // Only the first part may go past 59
+        if number < 0.0 || !number.is_finite() || (index > 0 && number >= 60.0) {
+            return Err(invalid());
         }
+        seconds = seconds * 60.0 + number;
+    }
+    if seconds <= 0.0 {
+        return Err(format!("Can't split at `{}`, the clip starts there", value));
     }
+    Ok(seconds)
 }
 
-impl fmt::Display for MyError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.message)
+fn parse_intermediate_quality(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(quality) if (0.0..=10.0).contains(&quality) => Ok(quality),
+        _ => Err(format!("Invalid quality `{}`, expected a number between 0 and 10", value)),
     }
 }
 
-impl Error for MyError {}
+fn parse_crossfade(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err(format!("Invalid crossfade `{}`, expected a number of seconds", value)),
+    }
+}
 
-impl From<std::io::Error> for MyError {
-    fn from(error: std::io::Error) -> Self {
-        MyError::new(&error.to_string())
+fn parse_fade(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => Ok(seconds),
+        _ => Err(format!("Invalid fade `{}`, expected a number of seconds", value)),
     }
 }
 
-impl From<Box<dyn Error>> for MyError {
-    fn from(error: Box<dyn Error>) -> Self {
-        MyError::new(&error.to_string())
+// A number of bits per second with an optional `k`, e.g. `192k` or `128000`
+fn parse_audio_bitrate(value: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid audio bitrate `{}`, expected e.g. `192k`", value);
+    let (number, multiplier) = match value.strip_suffix(['k', 'K']) {
+        Some(number) => (number, 1000.0),
+        None => (value, 1.0),
+    };
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return Err(invalid());
     }
+    let bitrate = number.parse::<f64>().map_err(|_| invalid())? * multiplier;
+    if !(8_000.0..=640_000.0).contains(&bitrate) {
+        return Err(format!("The audio bitrate `{}` is out of range (8k-640k)", value));
+    }
+    Ok(bitrate.round() as u32)
+}
+
+fn parse_batch_size(value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(size) if size >= 2 => Ok(size),
+        _ => Err(format!("Invalid batch size `{}`, expected at least 2", value)),
+    }
+}
+
+// Decimal units like the size estimate, `100MB`, `1.5G` or plain bytes
+fn parse_target_size(value: &str) -> Result<u64, String> {
+    let invalid = || format!("Invalid size `{}`, expected e.g. `100MB` or `1.5GB`", value);
+    let upper = value.trim().to_ascii_uppercase();
+    let number_end = upper
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(upper.len());
+    let (number, unit) = upper.split_at(number_end);
+    let multiplier = match unit.trim() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1_000.0,
+        "M" | "MB" => 1_000_000.0,
+        "G" | "GB" => 1_000_000_000.0,
+        _ => return Err(invalid()),
+    };
+    match number.parse::<f64>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok((number * multiplier) as u64),
+        _ => Err(invalid()),
+    }
+}
+
+// Accepts `START-END` in seconds, either side may be left out
+fn parse_trim_range(value: &str) -> Result<TrimRange, String> {
+    let (start, end) = value
+        .split_once('-')
+        .ok_or_else(|| format!("Invalid trim range '{}', expected START-END", value))?;
+    let parse_bound = |bound: &str| -> Result<Option<f64>, String> {
+        if bound.trim().is_empty() {
+            return Ok(None);
+        }
+        bound
+            .trim()
+            .parse::<f64>()
+            .map(Some)
+            .map_err(|_| format!("Invalid time '{}' in trim range", bound))
+    };
+
+    let trim = TrimRange {
+        start: parse_bound(start)?,
+        end: parse_bound(end)?,
+    };
+    if let (Some(start), Some(end)) = (trim.start, trim.end) {
+        if start >= end {
+            return Err(format!("Trim start {} must be before the end {}", start, end));
+        }
+    }
+
+    Ok(trim)
+}
+
+fn parse_trim_arg(value: &str) -> Result<(usize, TrimRange), String> {
+    let (index, range) = value
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid trim '{}', expected INDEX=START-END", value))?;
+    let index = index
+        .trim()
+        .parse::<usize>()
+        .map_err(|_| format!("Invalid input index '{}' in trim", index))?;
+
+    Ok((index, parse_trim_range(range)?))
 }
 
 #[derive(Debug)]
 struct State {
     video_files: Vec<PathBuf>,
-    volume: f32,
+    // Parallel to video_files, the gain of every clip on top of the track volumes
+    clip_volumes: Vec<f32>,
+    volumes: Vec<f32>,
+    audio_codec: AudioCodec,
+    trims: HashMap<PathBuf, TrimRange>,
+    // None while the thumbnail is being extracted or when extraction failed
+    thumbnails: HashMap<PathBuf, Option<PathBuf>>,
+    // Where Combine writes to without asking, None opens the save dialog
+    output_path: Option<PathBuf>,
+    config_path: PathBuf,
 }
 
 impl State {
-    fn new() -> Self {
+    fn new(config_path: PathBuf) -> Self {
         Self {
             video_files: Vec::new(),
-            volume: 0.7,
+            clip_volumes: Vec::new(),
+            volumes: vec![0.7],
+            audio_codec: AudioCodec::default(),
+            trims: HashMap::new(),
+            thumbnails: HashMap::new(),
+            output_path: None,
+            config_path,
         }
     }
 }
@@ -88,6 +589,13 @@ fn create_menu(menu_bar: &mut SysMenuBar) {
         MenuFlag::Normal,
         menu_callback
     );
+    menu_bar.add("&File/Export...\t", Shortcut::Ctrl | 'e', MenuFlag::Normal, menu_callback);
+    menu_bar.add(
+        "&Edit/Combine videos\t",
+        Shortcut::Ctrl | Key::Enter,
+        MenuFlag::Normal,
+        menu_callback
+    );
 }
 
 fn menu_callback(menu_bar: &mut impl MenuExt) {
@@ -96,329 +604,1725 @@ fn menu_callback(menu_bar: &mut impl MenuExt) {
             "&File/Import new videos...\t" => {
                 videos_import_callback();
             }
-            _ => println!("Unknown menu item: {}", menu_path),
+            // Export always asks where to save, Combine uses the output field like the button
+            "&File/Export...\t" => {
+                combine_menu_callback(true);
+            }
+            "&Edit/Combine videos\t" => {
+                combine_menu_callback(false);
+            }
+            _ => verbose!("Unknown menu item: {}", menu_path),
         }
     }
 }
 
+// The menu has no sender of its own, the channel is global per message type. Like the
+// button, it does nothing while a job runs.
+fn combine_menu_callback(pick_output: bool) {
+    let idle = app::widget_from_id::<Button>("combine_button")
+        .is_some_and(|button| button.active());
+    if !idle {
+        return;
+    }
+    if pick_output {
+        let config = Config::load(&STATE.with(|s| s.config_path.clone()));
+        match pick_output_file(false, config.last_output_dir.as_ref()) {
+            Some(output_file) => set_output_path(Some(output_file)),
+            None => {
+                return;
+            }
+        }
+    }
+    combine_button_callback(app::Sender::<JobMessage>::get(), false);
+}
+
 fn videos_import_callback() {
     let mut file_dialog = FileDialog::new(FileDialogType::BrowseMultiFile);
     file_dialog.set_option(FileDialogOptions::UseFilterExt);
-    file_dialog.set_filter("Video Files\t*.{mp4,mkv}\n");
+    file_dialog.set_filter(&video_file_filter());
     file_dialog.show();
     let file_names = file_dialog.filenames();
-    println!("Selected videos: {:?}", file_names);
+    if file_names.is_empty() {
+        // An empty selection means the dialog was cancelled unless it reports an error
+        if let Some(message) = file_dialog.error_message() {
+            if message != "No error" {
+                alert_default(&format!("Failed to import videos: {}", message));
+            }
+        }
+        return;
+    }
+    verbose!("Selected videos: {:?}", file_names);
     STATE.with(move |s| {
         s.video_files = file_names
             .iter()
             .map(|f| PathBuf::from(f))
             .collect();
+        // The dialog returns the files in the order they were clicked
+        natural_sort(&mut s.video_files);
+        s.clip_volumes = vec![1.0; s.video_files.len()];
     });
+    refresh_video_list();
+    refresh_volume_inputs();
+    refresh_estimate();
 }
 
-fn window_callback(_wind: &mut Window) {
-    if app::event() == Event::Close {
-        app::quit();
+fn refresh_video_list() {
+    request_thumbnails();
+    let videos = STATE.with(|s| s.video_files.clone());
+    let clip_volumes = STATE.with(|s| s.clip_volumes.clone());
+    let trims = STATE.with(|s| s.trims.clone());
+    let thumbnails = STATE.with(|s| s.thumbnails.clone());
+    if let Some(mut video_list) = app::widget_from_id::<HoldBrowser>("video_list") {
+        let selected = video_list.value();
+        video_list.clear();
+        for (index, video) in videos.iter().enumerate() {
+            let mut line = video.to_string_lossy().to_string();
+            if let Some(trim) = trims.get(video) {
+                line.push_str(&format!(" [{}]", trim));
+            }
+            let clip_volume = clip_volumes.get(index).copied().unwrap_or(1.0);
+            if clip_volume != 1.0 {
+                line.push_str(&format!(" [volume x{}]", clip_volume));
+            }
+            video_list.add(&line);
+            let thumbnail = thumbnails
+                .get(video)
+                .cloned()
+                .flatten()
+                .and_then(|path| SharedImage::load(path).ok());
+            if thumbnail.is_some() {
+                let line = video_list.size();
+                video_list.set_icon(line, thumbnail);
+            }
+        }
+        if selected > 0 && selected <= video_list.size() {
+            video_list.select(selected);
+        }
     }
 }
 
-fn remove_extension(path: &PathBuf) -> String {
-    match path.file_stem() {
-        Some(stem) => {
-            let mut new_path = path.clone();
-            new_path.set_file_name(stem);
-
-            new_path.to_string_lossy().into_owned()
+// Extracts thumbnails for clips that don't have one yet on a background thread each,
+// the list is refreshed on the main thread once a thumbnail is ready
+fn request_thumbnails() {
+    let missing: Vec<PathBuf> = STATE.with(|s| {
+        let missing: Vec<PathBuf> = s.video_files
+            .iter()
+            .filter(|video| !s.thumbnails.contains_key(*video))
+            .cloned()
+            .collect();
+        for video in &missing {
+            s.thumbnails.insert(video.clone(), None);
         }
-        None => path.to_string_lossy().into_owned(),
+        missing
+    });
+
+    for video in missing {
+        thread::spawn(move || {
+            // Clips shorter than a second have no frame to grab at the usual offset
+            let thumbnail = extract_thumbnail(&video, 1.0).or_else(|_|
+                extract_thumbnail(&video, 0.0)
+            );
+            match thumbnail {
+                Ok(thumbnail) => {
+                    app::awake_callback(move || {
+                        let video = video.clone();
+                        let thumbnail = thumbnail.clone();
+                        STATE.with(move |s| {
+                            s.thumbnails.insert(video.clone(), Some(thumbnail.clone()));
+                        });
+                        refresh_video_list();
+                    });
+                }
+                Err(e) => status!("Failed to extract thumbnail of {:?}: {}", video, e),
+            }
+        });
     }
 }
 
-fn extract_and_adjust_audio(
-    input_file: &PathBuf,
-    track_index: usize,
-    volume: f32
-) -> Result<(PathBuf, Vec<PathBuf>), MyError> {
-    let output_file = format!("{}_track-{}.ogg", remove_extension(input_file), track_index);
-    let temp_files: Vec<PathBuf> = vec![PathBuf::from(output_file.clone())];
+fn selected_video() -> Option<PathBuf> {
+    let video_list = app::widget_from_id::<HoldBrowser>("video_list")?;
+    let selected = video_list.value();
+    if selected < 1 {
+        return None;
+    }
 
-    let status = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-hide_banner")
-        .arg("-i")
-        .arg(input_file)
-        .args(&["-map", &format!("0:a:{}", track_index)])
-        .arg("-af")
-        .arg(&format!("volume={}", volume))
-        .arg("-acodec")
-        .arg("libvorbis")
-        .arg(&output_file)
-        .spawn()?
-        .wait()?;
-
-    if !status.success() {
-        cleanup_temp_files(temp_files);
-        return Err(
-            std::io::Error::new(std::io::ErrorKind::Other, "Failed to extract audio").into()
-        );
+    STATE.with(move |s| s.video_files.get((selected - 1) as usize).cloned())
+}
+
+fn selected_index() -> Option<usize> {
+    let video_list = app::widget_from_id::<HoldBrowser>("video_list")?;
+    let selected = video_list.value();
+    if selected < 1 || selected > video_list.size() {
+        return None;
     }
+    Some((selected - 1) as usize)
+}
 
-    Ok((PathBuf::from(output_file), temp_files))
+fn video_list_callback() {
+    let trim = selected_video()
+        .and_then(|video| STATE.with(move |s| s.trims.get(&video).copied()))
+        .unwrap_or_default();
+    if let Some(mut start_input) = app::widget_from_id::<FloatInput>("trim_start_input") {
+        start_input.set_value(&format_trim_bound(trim.start));
+    }
+    if let Some(mut end_input) = app::widget_from_id::<FloatInput>("trim_end_input") {
+        end_input.set_value(&format_trim_bound(trim.end));
+    }
+    let clip_volume = selected_index()
+        .and_then(|index| STATE.with(move |s| s.clip_volumes.get(index).copied()))
+        .unwrap_or(1.0);
+    if let Some(mut volume_input) = app::widget_from_id::<FloatInput>("clip_volume_input") {
+        volume_input.set_value(&clip_volume.to_string());
+    }
+    refresh_media_info();
 }
 
-fn merge_audio_tracks(audio_files: Vec<PathBuf>, output_file: PathBuf) -> Result<PathBuf, MyError> {
-    let mut input_options: Vec<String> = Vec::new();
-    for input_file in &audio_files {
-        input_options.push("-i".to_string());
-        input_options.push(input_file.to_string_lossy().to_string());
+fn set_media_info(text: &str) {
+    if
+        let Some(mut buffer) = app
+            ::widget_from_id::<TextDisplay>("media_info")
+            .and_then(|media_info| media_info.buffer())
+    {
+        buffer.set_text(text);
     }
+}
 
-    // Create the FFmpeg command
-    let ffmpeg = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-hide_banner")
-        .args(&input_options)
-        .arg("-filter_complex")
-        .arg("amerge")
-        .arg("-ac")
-        .arg(format!("{}", audio_files.len()))
-        .arg("-c:a")
-        .arg("libvorbis")
-        .arg(&output_file)
-        .spawn()?
-        .wait()?;
+// Probed on its own thread like the thumbnails, so a slow drive doesn't freeze the list
+fn refresh_media_info() {
+    let Some(video) = selected_video() else {
+        set_media_info("");
+        return;
+    };
 
-    if !ffmpeg.success() {
-        cleanup_temp_files(vec![output_file.clone()]);
-        return Err(std::io::Error::new(std::io::ErrorKind::Other, "Failed to merge audio").into());
+    thread::spawn(move || {
+        let text = match probe_media_info(&video) {
+            Ok(info) => info.to_string(),
+            Err(e) => format!("Failed to read the media info: {}", e),
+        };
+        app::awake_callback(move || {
+            // The selection may have moved on while probing
+            if selected_video().as_ref() == Some(&video) {
+                set_media_info(&text);
+            }
+        });
+    });
+}
+
+fn apply_trim_callback() {
+    let Some(video) = selected_video() else {
+        return;
+    };
+    let read_bound = |id: &str| {
+        app::widget_from_id::<FloatInput>(id).and_then(|input| input.value().parse::<f64>().ok())
+    };
+    let trim = TrimRange {
+        start: read_bound("trim_start_input"),
+        end: read_bound("trim_end_input"),
+    };
+    if let (Some(start), Some(end)) = (trim.start, trim.end) {
+        if start >= end {
+            alert_default("Trim start must be before the end");
+            return;
+        }
     }
 
-    Ok(output_file.clone())
+    STATE.with(move |s| {
+        if trim == TrimRange::default() {
+            s.trims.remove(&video);
+        } else {
+            s.trims.insert(video.clone(), trim);
+        }
+    });
+    refresh_video_list();
+    refresh_estimate();
+}
+
+fn apply_clip_volume_callback() {
+    let Some(index) = selected_index() else {
+        return;
+    };
+    let value = read_widget("clip_volume_input", String::new(), |input: FloatInput| {
+        input.value()
+    });
+    let clip_volume = match parse_clip_volume(&value) {
+        Ok(clip_volume) => clip_volume,
+        Err(message) => {
+            alert_default(&message);
+            return;
+        }
+    };
+
+    STATE.with(move |s| {
+        if let Some(slot) = s.clip_volumes.get_mut(index) {
+            *slot = clip_volume;
+        }
+    });
+    refresh_video_list();
 }
 
-fn concatenate_audio_files(
-    audio_files: Vec<PathBuf>,
-    output_file: PathBuf
-) -> Result<PathBuf, MyError> {
-    let temp_file = tempfile::NamedTempFile::new()?;
-    let mut file = BufWriter::new(temp_file.reopen()?);
+// An empty box goes back to the clip's own volume
+fn parse_clip_volume(value: &str) -> Result<f32, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(1.0);
+    }
+    match value.parse::<f32>() {
+        Ok(volume) if (MIN_VOLUME..=MAX_VOLUME).contains(&volume) => Ok(volume),
+        Ok(_) => {
+            Err(
+                format!(
+                    "The clip volume must be between {} and {}, got {}",
+                    MIN_VOLUME,
+                    MAX_VOLUME,
+                    value
+                )
+            )
+        }
+        Err(_) => Err(format!("The clip volume `{}` is not a number", value)),
+    }
+}
 
-    for audio_file in &audio_files {
-        writeln!(file, "file '{}'", audio_file.to_string_lossy())?;
+fn read_volume_inputs() -> Vec<f32> {
+    let mut volumes: Vec<f32> = Vec::new();
+    loop {
+        let input_id = format!("volume_input_{}", volumes.len());
+        let Some(input) = app::widget_from_id::<FloatInput>(&input_id) else {
+            break;
+        };
+        let default_volume = if volumes.is_empty() { 0.7 } else { 1.0 };
+        volumes.push(input.value().parse().unwrap_or(default_volume));
     }
 
-    file.flush()?;
+    volumes
+}
 
-    let ffmpeg = Command::new("ffmpeg")
-        .arg("-hide_banner")
-        .arg("-y")
-        .arg("-f")
-        .arg("concat")
-        .arg("-safe")
-        .arg("0")
-        .arg("-i")
-        .arg(temp_file.path().to_string_lossy().to_string())
-        .arg("-c")
-        .arg("copy")
-        .arg(&output_file)
-        .spawn()?
-        .wait()?;
-
-    if !ffmpeg.success() {
-        cleanup_temp_files(vec![output_file.clone()]);
-        return Err(
-            std::io::Error::new(std::io::ErrorKind::Other, "Failed to concatenate audio").into()
-        );
+// Like read_volume_inputs, but rejects what can't be parsed and clamps what is out of range,
+// writing the clamped value back so the user sees what would be used
+fn validate_volume_inputs() -> Result<Vec<f32>, String> {
+    let mut volumes: Vec<f32> = Vec::new();
+    let mut problems: Vec<String> = Vec::new();
+    loop {
+        let track_index = volumes.len();
+        let input_id = format!("volume_input_{}", track_index);
+        let Some(mut input) = app::widget_from_id::<FloatInput>(&input_id) else {
+            break;
+        };
+        let value = input.value();
+        let volume = match value.trim().parse::<f32>() {
+            Ok(volume) if volume.is_finite() => volume,
+            _ => {
+                problems.push(
+                    format!("Volume of track {} (`{}`) is not a number", track_index, value)
+                );
+                volumes.push(1.0);
+                continue;
+            }
+        };
+        let clamped = volume.clamp(MIN_VOLUME, MAX_VOLUME);
+        if clamped != volume {
+            input.set_value(&clamped.to_string());
+            problems.push(
+                format!(
+                    "Volume of track {} must be between {} and {}, it was set to {}",
+                    track_index,
+                    MIN_VOLUME,
+                    MAX_VOLUME,
+                    clamped
+                )
+            );
+        }
+        volumes.push(clamped);
     }
 
-    Ok(output_file.clone())
+    if problems.is_empty() {
+        Ok(volumes)
+    } else {
+        Err(problems.join("\n"))
+    }
 }
 
-fn concatenate_video_files(
-    video_files: Vec<PathBuf>,
-    output_file: PathBuf
-) -> Result<PathBuf, MyError> {
-    let temp_file = tempfile::NamedTempFile::new()?;
-    let mut file = BufWriter::new(temp_file.reopen()?);
+// Rebuilds the volume row with one input per audio track of the imported videos
+fn refresh_volume_inputs() {
+    let videos = STATE.with(|s| s.video_files.clone());
+    let track_count = videos
+        .iter()
+        .filter_map(|video| probe_audio_tracks(video).ok())
+        .max()
+        .unwrap_or(1)
+        .max(1);
+    let volumes = read_volume_inputs();
 
-    // Write file paths to the temporary file
-    for video_file in video_files {
-        writeln!(file, "file '{}'", video_file.to_string_lossy())?;
+    let Some(mut volume_row) = app::widget_from_id::<Flex>("volume_row") else {
+        return;
+    };
+    volume_row.clear();
+    volume_row.begin();
+    Frame::default().with_size(100, 30).with_label("Volume:");
+    for track_index in 0..track_count {
+        let mut input = FloatInput::default()
+            .with_size(100, 30)
+            .with_id(&format!("volume_input_{}", track_index));
+        input.set_value(&track_volume(&volumes, track_index).to_string());
+        input.set_tooltip(&format!("Volume of audio track {}", track_index));
+    }
+    volume_row.end();
+    volume_row.layout();
+    volume_row.redraw();
+    for id in ["background_track_choice", "voiceover_track_choice"] {
+        if let Some(mut choice) = app::widget_from_id::<Choice>(id) {
+            fill_track_choice(&mut choice, track_count);
+        }
     }
+}
 
-    // Flush and finish writing to the temporary file
-    file.flush()?;
+// The first entry uses every track, the others pick one track, keeping the pick when it
+// still exists
+fn fill_track_choice(choice: &mut Choice, track_count: usize) {
+    let selected = choice.value();
+    choice.clear();
+    choice.add_choice("All tracks");
+    for track_index in 0..track_count {
+        choice.add_choice(&format!("Track {}", track_index));
+    }
+    let keep = selected > 0 && (selected as usize) <= track_count;
+    choice.set_value(if keep { selected } else { 0 });
+}
 
-    let ffmpeg = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-hide_banner")
-        .arg("-f")
-        .arg("concat")
-        .arg("-safe")
-        .arg("0")
-        .arg("-i")
-        .arg(temp_file.path().to_string_lossy().to_string())
-        .arg("-c")
-        .arg("copy")
-        .arg("-an")
-        .arg(&output_file)
-        .spawn()?
-        .wait()?;
-
-    if !ffmpeg.success() {
-        return Err(
-            std::io::Error::new(std::io::ErrorKind::Other, "Failed to concatenate video").into()
-        );
+// The GUI counterpart of --background-track and --voiceover-track
+fn read_track_roles() -> Result<Option<TrackRoles>, MyError> {
+    let track = |id: &str| {
+        app::widget_from_id::<Choice>(id)
+            .map(|choice| choice.value())
+            .filter(|value| *value > 0)
+            .map(|value| (value - 1) as usize)
+    };
+    match (track("background_track_choice"), track("voiceover_track_choice")) {
+        (Some(background), Some(voiceover)) => Ok(Some(TrackRoles::new(background, voiceover)?)),
+        (None, None) => Ok(None),
+        _ => Err(MyError::InvalidInput("Pick both a background and a voiceover track".to_string())),
     }
+}
 
-    Ok(output_file.clone())
+// The clips and their trims, in the order they are combined
+fn estimate_inputs() -> (Vec<PathBuf>, Vec<Option<TrimRange>>) {
+    STATE.with(|s| {
+        let trims = s.video_files
+            .iter()
+            .map(|video| s.trims.get(video).copied())
+            .collect();
+        (s.video_files.clone(), trims)
+    })
 }
 
-fn combine_video_and_audio(
-    video_file: PathBuf,
-    audio_file: PathBuf,
-    output_file: PathBuf
-) -> Result<(), Box<dyn Error>> {
-    let ffmpeg = Command::new("ffmpeg")
-        .arg("-y")
-        .arg("-hide_banner")
-        .arg("-i")
-        .arg(&video_file)
-        .arg("-i")
-        .arg(&audio_file)
-        .arg("-c:v")
-        .arg("copy")
-        .arg("-c:a")
-        .arg("aac")
-        .arg("-strict")
-        .arg("experimental")
-        .arg(&output_file)
-        .spawn()?
-        .wait()?;
-
-    if !ffmpeg.success() {
-        return Err(
-            std::io::Error
-                ::new(std::io::ErrorKind::Other, "Failed to combine video and audio")
-                .into()
-        );
+// Probes every clip, so it runs on its own thread like refresh_media_info
+fn refresh_estimate() {
+    let inputs = estimate_inputs();
+    thread::spawn(move || {
+        let (videos, clip_trims) = &inputs;
+        let text = match estimate_output(videos, clip_trims) {
+            Ok(_) if videos.is_empty() => String::new(),
+            Ok(estimate) => estimate.to_string(),
+            Err(e) => format!("Could not estimate the output: {}", e),
+        };
+        app::awake_callback(move || {
+            // The clips may have changed while probing, their own estimate is on the way
+            if estimate_inputs() != inputs {
+                return;
+            }
+            if let Some(mut estimate_label) = app::widget_from_id::<Frame>("estimate_label") {
+                estimate_label.set_label(&text);
+            }
+        });
+    });
+}
+
+// Swaps the selected entry with its neighbour, offset is -1 for up and 1 for down
+fn move_selected_video(offset: i32) {
+    let Some(mut video_list) = app::widget_from_id::<HoldBrowser>("video_list") else {
+        return;
+    };
+    let selected = video_list.value();
+    let target = selected + offset;
+    if selected < 1 || target < 1 || target > video_list.size() {
+        return;
     }
 
-    Ok(())
+    STATE.with(move |s| {
+        s.video_files.swap((selected - 1) as usize, (target - 1) as usize);
+        s.clip_volumes.swap((selected - 1) as usize, (target - 1) as usize);
+    });
+    refresh_video_list();
+    video_list.select(target);
 }
 
-fn combine_and_encode_videos(
-    input_files: Vec<PathBuf>,
-    output_file: PathBuf,
-    volume: f32
-) -> Result<(), Box<dyn Error>> {
-    let mut merged_audio_files: Vec<PathBuf> = Vec::new();
-    let mut temp_files_to_delete: Vec<PathBuf> = Vec::new();
-
-    for file_path in &input_files {
-        let (background_audio, temp_bg_file) = extract_and_adjust_audio(file_path, 0, volume)?;
-        let (voiceover_audio, temp_voice_file) = extract_and_adjust_audio(file_path, 1, 1.0)?;
-        let merged_audio_path = PathBuf::from(
-            format!("{}_merged_audio.ogg", remove_extension(file_path))
-        );
-        let temp_merged = merge_audio_tracks(
-            vec![background_audio, voiceover_audio],
-            merged_audio_path.clone()
-        )?;
-        merged_audio_files.push(merged_audio_path);
-        temp_bg_file.iter().for_each(|f| temp_files_to_delete.push(f.clone()));
-        temp_voice_file.iter().for_each(|f| temp_files_to_delete.push(f.clone()));
-        temp_files_to_delete.push(temp_merged);
-    }
-
-    let concantenated_video_file = PathBuf::from(
-        format!("{}_concatenated_video.mkv", remove_extension(&output_file))
-    );
-    let temp_concat_video = concatenate_video_files(
-        input_files.clone(),
-        concantenated_video_file.clone()
-    )?;
+fn remove_selected_video() {
+    let Some(video_list) = app::widget_from_id::<HoldBrowser>("video_list") else {
+        return;
+    };
+    let selected = video_list.value();
+    if selected < 1 || selected > video_list.size() {
+        return;
+    }
 
-    let final_audio_file = PathBuf::from(
-        format!("{}_final_audio.ogg", remove_extension(&output_file))
-    );
-    let temp_concat_audio = concatenate_audio_files(merged_audio_files, final_audio_file.clone())?;
+    STATE.with(move |s| {
+        let video = s.video_files.remove((selected - 1) as usize);
+        s.clip_volumes.remove((selected - 1) as usize);
+        s.trims.remove(&video);
+    });
+    refresh_video_list();
+    refresh_volume_inputs();
+    refresh_estimate();
+    video_list_callback();
+}
 
-    temp_files_to_delete.push(temp_concat_video);
-    temp_files_to_delete.push(temp_concat_audio);
+fn decode_percent_escapes(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+            if let Ok(byte) = u8::from_str_radix(hex, 16) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
 
-    match combine_video_and_audio(concantenated_video_file, final_audio_file, output_file) {
-        Ok(_) => {
-            cleanup_temp_files(temp_files_to_delete);
-            println!("Successfully combined videos");
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// File managers hand over newline separated paths, sometimes as file:// URIs
+fn parse_dropped_paths(text: &str) -> Vec<PathBuf> {
+    text.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            match line.strip_prefix("file://") {
+                Some(uri_path) => PathBuf::from(decode_percent_escapes(uri_path)),
+                None => PathBuf::from(line),
+            }
+        })
+        .collect()
+}
+
+fn video_file_filter() -> String {
+    format!("Video Files\t*.{{{}}}\n", SUPPORTED_EXTENSIONS.join(","))
+}
+
+fn add_video_files(mut files: Vec<PathBuf>) {
+    natural_sort(&mut files);
+    STATE.with(move |s| {
+        for file in &files {
+            if !s.video_files.contains(file) {
+                s.video_files.push(file.clone());
+                s.clip_volumes.push(1.0);
+            }
         }
-        Err(e) => {
-            cleanup_temp_files(temp_files_to_delete);
-            println!("Failed to combine videos: {}", e);
+    });
+}
+
+fn window_handle(_wind: &mut Window, event: Event) -> bool {
+    match event {
+        Event::DndEnter | Event::DndDrag | Event::DndRelease => true,
+        Event::Paste => {
+            let dropped_paths = parse_dropped_paths(&app::event_text());
+            let (dropped_files, rejected_files): (Vec<PathBuf>, Vec<PathBuf>) = dropped_paths
+                .into_iter()
+                .partition(|path| is_video_file(path));
+            verbose!("Dropped videos: {:?}", dropped_files);
+            if !rejected_files.is_empty() {
+                let names: Vec<String> = rejected_files
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                alert_default(
+                    &format!(
+                        "Skipped files that aren't supported videos ({}):\n{}",
+                        SUPPORTED_EXTENSIONS.join(", "),
+                        names.join("\n")
+                    )
+                );
+            }
+            add_video_files(dropped_files);
+            refresh_video_list();
+            refresh_volume_inputs();
+            refresh_estimate();
+            true
         }
+        _ => false,
     }
+}
 
+fn window_callback(_wind: &mut Window) {
+    if app::event() == Event::Close {
+        // Don't leave ffmpeg running behind a closed window
+        cancel_running_jobs();
+        app::quit();
+    }
+}
+
+// Sent from the job thread, handled on the main thread by handle_job_message
+enum JobMessage {
+    Progress(f32),
+    // The output file on success, how long the job took and whether it only wrote audio
+    Finished(Result<PathBuf, MyError>, Duration, bool),
+}
+
+fn handle_job_message(message: JobMessage) {
+    let mut progress_bar: Option<Progress> = app::widget_from_id("progress_bar");
+    match message {
+        JobMessage::Progress(fraction) => {
+            if let Some(progress_bar) = progress_bar.as_mut() {
+                progress_bar.set_value((fraction as f64) * 100.0);
+                progress_bar.set_label(&format!("{:.0}%", fraction * 100.0));
+            }
+        }
+        JobMessage::Finished(result, elapsed, audio_only) => {
+            clear_cancel_request();
+            set_job_running(false);
+            let notify = read_widget("notify_check", true, |check: CheckButton| check.is_checked());
+            // The dialogs below wait for a click, so the notification goes out first
+            if notify {
+                match &result {
+                    Ok(output) => {
+                        let name = output.file_name().unwrap_or(output.as_os_str());
+                        send_desktop_notification(
+                            "Combining finished",
+                            &format!("{} in {}", name.to_string_lossy(), format_elapsed(elapsed))
+                        );
+                    }
+                    Err(MyError::Cancelled) => {}
+                    Err(e) => {
+                        send_desktop_notification(
+                            "Combining failed",
+                            &format!("After {}: {}", format_elapsed(elapsed), e)
+                        );
+                    }
+                }
+            }
+            match result {
+                Ok(output) => {
+                    let what = if audio_only { "audio" } else { "videos" };
+                    let message = format!(
+                        "Successfully combined {} into {}",
+                        what,
+                        output.display()
+                    );
+                    if choice2_default(&message, "Close", "Show in folder", "") == Some(1) {
+                        if let Err(e) = open_in_file_manager(&output) {
+                            alert_default(&format!("Failed to open the file manager: {}", e));
+                        }
+                    }
+                }
+                Err(MyError::Cancelled) => {
+                    if let Some(progress_bar) = progress_bar.as_mut() {
+                        progress_bar.set_value(0.0);
+                        progress_bar.set_label("Cancelled");
+                    }
+                }
+                Err(e) => alert_default(&format!("Failed to combine videos: {}", e)),
+            }
+        }
+    }
+}
+
+// Seen even when the window is behind others. The dialog says the same, so a desktop without a
+// notification service is not an error.
+fn send_desktop_notification(summary: &str, body: &str) {
+    let result = notify_rust::Notification::new()
+        .appname("Video editor")
+        .summary(summary)
+        .body(body)
+        .show();
+    if let Err(e) = result {
+        verbose!("Failed to show the desktop notification: {}", e);
+    }
+}
+
+// `42s`, `3m 05s` or `1h 02m 03s`
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    match (seconds / 3600, (seconds % 3600) / 60, seconds % 60) {
+        (0, 0, seconds) => format!("{}s", seconds),
+        (0, minutes, seconds) => format!("{}m {:02}s", minutes, seconds),
+        (hours, minutes, seconds) => format!("{}h {:02}m {:02}s", hours, minutes, seconds),
+    }
+}
+
+// Remembered right away, it is a setting rather than part of a job
+fn notify_check_callback(check: &mut CheckButton) {
+    let config_path = STATE.with(|s| s.config_path.clone());
+    let mut config = Config::load(&config_path);
+    config.disable_notifications = !check.is_checked();
+    if let Err(e) = config.save(&config_path) {
+        status!("Failed to save config: {}", e);
+    }
+}
+
+// Explorer and Finder open the folder with the file selected, other file managers can only be
+// pointed at the folder
+fn open_in_file_manager(path: &PathBuf) -> Result<(), MyError> {
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("explorer");
+        command.arg("/select,").arg(path);
+        command
+    } else if cfg!(target_os = "macos") {
+        let mut command = Command::new("open");
+        command.arg("-R").arg(path);
+        command
+    } else {
+        let folder = path.parent().filter(|folder| !folder.as_os_str().is_empty());
+        let mut command = Command::new("xdg-open");
+        command.arg(folder.unwrap_or(Path::new(".")));
+        command
+    };
+    // The file manager outlives this call, nothing to wait for
+    command.stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
     Ok(())
 }
 
-fn cleanup_temp_files(temp_files: Vec<PathBuf>) {
-    for temp_file in temp_files {
-        if temp_file.exists() {
-            println!("Deleting temp file: {:?}", temp_file);
-            std::fs::remove_file(temp_file).expect("Failed to delete temp file");
+// Only one job at a time, the combine buttons are disabled while one runs and Cancel while
+// none does
+fn set_job_running(running: bool) {
+    for id in ["combine_button", "audio_only_button"] {
+        if let Some(mut button) = app::widget_from_id::<Button>(id) {
+            if running {
+                button.deactivate();
+            } else {
+                button.activate();
+            }
+        }
+    }
+    if let Some(mut cancel_button) = app::widget_from_id::<Button>("cancel_button") {
+        if running {
+            cancel_button.activate();
+        } else {
+            cancel_button.deactivate();
+        }
+    }
+}
+
+// Asks how to join the clips when stream copy would be slow to fix or drift, None when the
+// user has been told about an error
+fn pick_concat_strategy(videos: &[PathBuf]) -> Option<ConcatStrategy> {
+    let concat_strategy = match describe_stream_mismatch(videos) {
+        Ok(Some(mismatch)) => {
+            alert_default(
+                &format!(
+                    "The clips don't match ({}), they will be re-encoded, which is slower",
+                    mismatch
+                )
+            );
+            ConcatStrategy::ReEncode
+        }
+        Ok(None) => ConcatStrategy::StreamCopy,
+        Err(e) => {
+            alert_default(&e.to_string());
+            return None;
+        }
+    };
+    // Re-encoding already evens out the frame rate, only stream copy is affected
+    let concat_strategy = match concat_strategy {
+        ConcatStrategy::StreamCopy =>
+            match describe_variable_frame_rate(videos) {
+                Ok(Some(vfr)) => {
+                    let message = format!(
+                        "Found {}, stream copying it can drift the audio out of sync. \
+                         Re-encode to a constant frame rate (slower)?",
+                        vfr
+                    );
+                    match choice2_default(&message, "Stream copy", "Re-encode", "") {
+                        Some(1) => ConcatStrategy::ReEncode,
+                        _ => ConcatStrategy::StreamCopy,
+                    }
+                }
+                Ok(None) => ConcatStrategy::StreamCopy,
+                Err(e) => {
+                    alert_default(&e.to_string());
+                    return None;
+                }
+            }
+        ConcatStrategy::ReEncode => ConcatStrategy::ReEncode,
+    };
+    Some(concat_strategy)
+}
+
+// Reads a widget looked up by id, a missing one is logged and `default` is used instead,
+// so a lookup can't take the window down
+fn read_widget<W: 'static + Clone, T>(id: &str, default: T, read: impl FnOnce(W) -> T) -> T {
+    match app::widget_from_id::<W>(id) {
+        Some(widget) => read(widget),
+        None => {
+            status!("Warning: the {} widget is missing, using its default", id);
+            default
+        }
+    }
+}
+
+// Asks for the file to write to, None when the dialog was cancelled
+fn pick_output_file(audio_only: bool, last_output_dir: Option<&PathBuf>) -> Option<PathBuf> {
+    let mut file_dialog = FileDialog::new(FileDialogType::BrowseSaveFile);
+    file_dialog.set_option(FileDialogOptions::UseFilterExt);
+    if audio_only {
+        file_dialog.set_filter("Audio Files\t*.{m4a,ogg,opus,mp3}\n");
+    } else {
+        file_dialog.set_filter("Video Files\t*.{mkv,mp4}\nAnimations\t*.{gif,webp}\n");
+    }
+    if let Some(last_output_dir) = last_output_dir {
+        file_dialog.set_directory(last_output_dir).ok();
+    }
+    file_dialog.show();
+    let output_file = file_dialog.filename();
+    if output_file.as_os_str().is_empty() {
+        // Same as the import dialog, no file name means it was cancelled
+        if let Some(message) = file_dialog.error_message() {
+            if message != "No error" {
+                alert_default(&format!("Failed to pick the output file: {}", message));
+            }
         }
+        return None;
+    }
+    Some(output_file)
+}
+
+// Keeps the output field and the state in step
+fn set_output_path(output_path: Option<PathBuf>) {
+    let text = output_path.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+    STATE.with(move |s| {
+        s.output_path = output_path.clone();
+    });
+    if let Some(mut output_input) = app::widget_from_id::<Input>("output_input") {
+        output_input.set_value(&text);
     }
 }
 
-fn combine_button_callback() {
+fn output_input_callback(output_input: &mut Input) {
+    let value = output_input.value();
+    let output_path = Some(value.trim()).filter(|value| !value.is_empty()).map(PathBuf::from);
+    STATE.with(move |s| {
+        s.output_path = output_path.clone();
+    });
+}
+
+fn browse_output_callback() {
+    let config = Config::load(&STATE.with(|s| s.config_path.clone()));
+    if let Some(output_file) = pick_output_file(false, config.last_output_dir.as_ref()) {
+        set_output_path(Some(output_file));
+    }
+}
+
+// Fails before encoding anything instead of at the very end when the folder is read only
+fn check_output_writable(output_file: &Path) -> Result<(), MyError> {
+    let dir = match output_file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if !dir.is_dir() {
+        return Err(MyError::InvalidInput(format!("The folder {} doesn't exist", dir.display())));
+    }
+    tempfile::Builder
+        ::new()
+        .prefix(".video_editor")
+        .tempfile_in(dir)
+        .map(|_| ())
+        .map_err(|e| MyError::InvalidInput(format!("Can't write to {}: {}", dir.display(), e)))
+}
+
+// Writes only the joined audio when `audio_only` is set
+fn combine_button_callback(sender: app::Sender<JobMessage>, audio_only: bool) {
     let videos = STATE.with(|s| s.video_files.clone());
-    let vol: FloatInput = app::widget_from_id("volume_input").unwrap();
-    let volume = vol.value().parse().unwrap_or(0.7);
+    let clip_volumes = STATE.with(|s| s.clip_volumes.clone());
+    let volumes = match validate_volume_inputs() {
+        // The row always has an input per track, without any fall back to the stored volumes
+        Ok(volumes) if volumes.is_empty() => {
+            status!("Warning: the volume inputs are missing, using the last volumes");
+            STATE.with(|s| s.volumes.clone())
+        }
+        Ok(volumes) => volumes,
+        Err(message) => {
+            alert_default(&message);
+            return;
+        }
+    };
+    let codec_index = read_widget("audio_codec_choice", 0, |choice: Choice| choice.value());
+    let audio_codec = AudioCodec::value_variants()
+        .get(codec_index.max(0) as usize)
+        .copied()
+        .unwrap_or_default();
+    let mix_index = read_widget("mix_mode_choice", 0, |choice: Choice| choice.value());
+    let mix_mode = MixMode::value_variants()
+        .get(mix_index.max(0) as usize)
+        .copied()
+        .unwrap_or_default();
+    let track_roles = match read_track_roles() {
+        Ok(track_roles) => track_roles,
+        Err(e) => {
+            alert_default(&e.to_string());
+            return;
+        }
+    };
+    // The first entry of the resolution dropdown keeps the original resolution
+    let resolution_index = read_widget("resolution_choice", 0, |choice: Choice| choice.value());
+    let target_resolution = match resolution_index {
+        index if index > 0 => RESOLUTION_PRESETS.get((index - 1) as usize).copied(),
+        _ => None,
+    };
+    // An empty frame rate box keeps the native frame rate
+    let fps = read_widget("fps_input", String::new(), |input: FloatInput| input.value());
+    let target_fps = parse_fps(fps.trim()).ok();
+    let crossfade = read_widget("crossfade_input", String::new(), |input: FloatInput| {
+        input.value()
+    });
+    let crossfade = parse_crossfade(crossfade.trim())
+        .ok()
+        .filter(|seconds| *seconds > 0.0);
+    let drop_background = read_widget("drop_background_check", false, |check: CheckButton| {
+        check.is_checked()
+    });
+    let normalize = read_widget("normalize_check", false, |check: CheckButton| check.is_checked());
+    let loudness_target = normalize.then_some(-23.0);
+    let keep_temp = read_widget("keep_temp_check", false, |check: CheckButton| check.is_checked());
+    let crf = read_widget("crf_slider", 23.0, |slider: HorValueSlider| slider.value());
+    let threads = read_widget("threads_spinner", 0.0, |spinner: Spinner| spinner.value());
+    let encoder_index = read_widget("encoder_choice", 0, |choice: Choice| choice.value());
+    let encoder = Encoder::value_variants()
+        .get(encoder_index.max(0) as usize)
+        .copied()
+        .unwrap_or_default();
+    let preset_index = read_widget("preset_choice", -1, |choice: Choice| choice.value());
+    let preset = usize::try_from(preset_index)
+        .ok()
+        .and_then(|index| Preset::value_variants().get(index).copied())
+        .unwrap_or_default();
 
+    let state_volumes = volumes.clone();
     STATE.with(move |s| {
-        s.volume = volume;
+        s.volumes = state_volumes.clone();
+        s.audio_codec = audio_codec;
     });
 
     if videos.len() < 2 {
         return;
     }
 
-    let mut file_dialog = FileDialog::new(FileDialogType::BrowseSaveFile);
-    file_dialog.set_option(FileDialogOptions::UseFilterExt);
-    file_dialog.set_filter("Video Files\t*.{mkv,mp4}\n");
-    file_dialog.show();
-    let output_file = file_dialog.filename();
-    println!("Output file: {:?}", output_file);
-    combine_and_encode_videos(videos, output_file, volume).expect("Failed to combine videos");
+    if let Err(e) = ensure_tools_available() {
+        alert_default(&e.to_string());
+        return;
+    }
+
+    if let Err(e) = validate_inputs(&videos) {
+        alert_default(&e.to_string());
+        return;
+    }
+
+    // No video is written, so how the clips would be joined doesn't matter
+    let concat_strategy = if audio_only {
+        ConcatStrategy::StreamCopy
+    } else {
+        match pick_concat_strategy(&videos) {
+            Some(concat_strategy) => concat_strategy,
+            None => {
+                return;
+            }
+        }
+    };
+
+    let config_path = STATE.with(|s| s.config_path.clone());
+    let config = Config::load(&config_path);
+
+    // The output field holds a video, audio only exports are always asked for
+    let output_path = if audio_only { None } else { STATE.with(|s| s.output_path.clone()) };
+    let output_file = match output_path {
+        Some(output_path) => output_path,
+        None => {
+            let last_output_dir = config.last_output_dir.as_ref();
+            let Some(output_file) = pick_output_file(audio_only, last_output_dir) else {
+                return;
+            };
+            // Picked once, the next run goes to the same file
+            if !audio_only {
+                set_output_path(Some(output_file.clone()));
+            }
+            output_file
+        }
+    };
+    verbose!("Output file: {:?}", output_file);
+    if let Err(e) = check_output_writable(&output_file) {
+        alert_default(&e.to_string());
+        return;
+    }
+    if let Err(e) = check_output_not_an_input(&videos, &output_file) {
+        alert_default(&e.to_string());
+        return;
+    }
+    if output_file.exists() {
+        let message = format!("{} already exists, replace it?", output_file.display());
+        if choice2_default(&message, "Cancel", "Replace", "") != Some(1) {
+            return;
+        }
+    }
+
+    let trims = STATE.with(|s| s.trims.clone());
+    // The volume inputs are per track, with roles only the two picked tracks are passed on
+    let track_volumes = match track_roles {
+        Some(roles) =>
+            vec![track_volume(&volumes, roles.background), track_volume(&volumes, roles.voiceover)],
+        None => volumes.clone(),
+    };
+    let clip_trims = videos
+        .iter()
+        .map(|video| trims.get(video).copied())
+        .collect();
+    let combine_config = CombineConfig::builder(videos, output_file.clone())
+        .volumes(track_volumes)
+        .track_roles(track_roles)
+        .drop_background(drop_background)
+        .trims(clip_trims)
+        .clip_volumes(clip_volumes)
+        .audio_codec(audio_codec)
+        .mix_mode(mix_mode)
+        .target_resolution(target_resolution)
+        .target_fps(target_fps)
+        .encoder(encoder)
+        .video_quality(Some(VideoQuality::Crf(crf as u8)))
+        .preset(preset)
+        .concat_strategy(concat_strategy)
+        .audio_only(audio_only)
+        .crossfade(crossfade)
+        .loudness_target(loudness_target)
+        .keep_temp(keep_temp)
+        .threads(threads as u32)
+        // Either the file is new or replacing it was confirmed above
+        .overwrite(true)
+        .build();
+    // The job runs on its own thread, the window keeps handling events meanwhile
+    set_job_running(true);
+    if let Some(mut progress_bar) = app::widget_from_id::<Progress>("progress_bar") {
+        progress_bar.set_value(0.0);
+        progress_bar.set_label("0%");
+    }
+    let started = Instant::now();
+    thread::spawn(move || {
+        let on_progress = |fraction: f32| sender.send(JobMessage::Progress(fraction));
+        // Probing every input takes a while on slow drives, so it runs here and not in the window
+        let unreadable = find_unreadable_inputs(&combine_config.inputs);
+        let result = if unreadable.is_empty() {
+            combine_and_encode_videos(combine_config, on_progress)
+        } else {
+            let list: Vec<String> = unreadable
+                .iter()
+                .map(|(file, reason)| format!("{}: {}", file.display(), reason))
+                .collect();
+            Err(
+                MyError::InvalidInput(
+                    format!("Remove the videos that can't be read:\n{}", list.join("\n"))
+                )
+            )
+        };
+        if result.is_ok() {
+            // Loaded again so a setting changed while the job ran isn't overwritten
+            let mut config = Config::load(&config_path);
+            config.last_volumes = volumes;
+            config.last_output_dir = output_file.parent().map(|dir| dir.to_path_buf());
+            if let Err(e) = config.save(&config_path) {
+                status!("Failed to save config: {}", e);
+            }
+        }
+        let output = result.map(|summary| summary.output);
+        sender.send(JobMessage::Finished(output, started.elapsed(), audio_only));
+    });
+}
+
+// Exit codes map to the error variant so scripts can tell failures apart
+fn exit_with_error(error: MyError) -> ! {
+    if json_output() {
+        let report = serde_json::json!({
+            "error": error.kind(),
+            "message": error.to_string(),
+        });
+        println!("{}", report);
+    } else if pipe_output() {
+        eprintln!("{}", error);
+    } else {
+        println!("{}", error);
+    }
+    std::process::exit(error.exit_code());
 }
 
 fn main() {
     let args = Args::parse();
-    let input = args.input.unwrap_or(Vec::new());
-    let output = args.output.unwrap_or("".to_string());
+    set_log_level(LogLevel::from_flags(args.verbose, args.quiet));
+    if let Some(ffmpeg_bin) = args.ffmpeg_bin.clone() {
+        set_tool_path(Tool::Ffmpeg, ffmpeg_bin);
+    }
+    if let Some(ffprobe_bin) = args.ffprobe_bin.clone() {
+        set_tool_path(Tool::Ffprobe, ffprobe_bin);
+    }
 
-    if args.cli_mode {
-        if input.len() == 0 || output == "" {
-            println!("Please provide input and output files");
-            return;
+    match args.command {
+        Some(CliCommand::Combine(combine)) => {
+            set_json_output(args.json);
+            run_combine(combine, args.json, args.quiet);
         }
-        let output_ffmpeg = Command::new("ffmpeg")
-            .arg("-hide_banner")
-            .arg("-version")
-            .output()
-            .expect("Failed to run ffmpeg");
-        println!("ffmpeg version: {}", String::from_utf8_lossy(&output_ffmpeg.stdout));
-        combine_and_encode_videos(
-            input
-                .iter()
-                .map(|f| PathBuf::from(f))
-                .collect(),
-            PathBuf::from(output),
-            args.volume
-        ).expect("Failed to combine videos");
+        Some(CliCommand::Completions { shell }) => {
+            print_completions(shell, &mut std::io::stdout());
+        }
+        Some(command) => {
+            set_json_output(args.json);
+            if let Err(e) = ensure_tools_available() {
+                exit_with_error(e);
+            }
+            match run_step(command) {
+                Ok(result) => {
+                    if args.json {
+                        println!("{}", result);
+                    }
+                }
+                Err(e) => exit_with_error(e),
+            }
+        }
+        None if args.cli_mode => {
+            set_json_output(args.json);
+            run_combine(args.combine, args.json, args.quiet);
+        }
+        None => init_app(),
+    }
+}
+
+// A table per input, then what would make the concat re-encode. With --json only the
+// streams are printed, as `inputs`.
+fn print_streams(input: &[PathBuf]) -> Result<serde_json::Value, MyError> {
+    validate_inputs(input)?;
+    let json = json_output();
+    let mut inputs: Vec<serde_json::Value> = Vec::new();
+    for file in input {
+        let info = probe_media_info(file)?;
+        if !json {
+            println!("{} ({})", file.display(), info.container);
+            println!("{}\n", format_stream_table(&info.streams));
+        }
+        inputs.push(serde_json::json!({ "input": file, "streams": info.streams }));
+    }
+    // Audio only files have no video stream to compare
+    if !json && input.len() > 1 {
+        if let Ok(Some(mismatch)) = describe_stream_mismatch(input) {
+            println!("{}, stream copy won't work", mismatch);
+        }
+    }
+    Ok(serde_json::json!({ "inputs": inputs }))
+}
+
+// Named after the binary, not the display name, since that is what the shell completes
+fn print_completions(shell: Shell, out: &mut dyn Write) {
+    clap_complete::generate(shell, &mut Args::command(), "video_editor", out);
+}
+
+// Runs one building block of the pipeline on its own
+// The result is what --json prints, `output` or `outputs` for split
+fn run_step(command: CliCommand) -> Result<serde_json::Value, MyError> {
+    let output = match command {
+        CliCommand::Combine(_) => unreachable!("combine is handled by run_combine"),
+        CliCommand::Completions { .. } => unreachable!("completions don't need ffmpeg"),
+        CliCommand::ExtractAudio { input, output, track, volume, format, dry_run } => {
+            validate_inputs(std::slice::from_ref(&input))?;
+            check_output_not_an_input(std::slice::from_ref(&input), &output)?;
+            check_video_file(&input)?;
+            let (output, _) = extract_and_adjust_audio(
+                &input,
+                track,
+                volume,
+                AudioEncoding { format, quality: None },
+                output,
+                dry_run
+            )?;
+            output
+        }
+        CliCommand::ConcatAudio { input, output, dry_run } => {
+            validate_inputs(&input)?;
+            check_output_not_an_input(&input, &output)?;
+            concatenate_audio_files(input, output, dry_run)?
+        }
+        CliCommand::ConcatVideo { input, output, dry_run } => {
+            validate_inputs(&input)?;
+            check_output_not_an_input(&input, &output)?;
+            for file in &input {
+                check_video_file(file)?;
+            }
+            let mut total_us = 0;
+            for file in &input {
+                total_us += probe_duration_us(file)?;
+            }
+            concatenate_video_files(input, output, dry_run, total_us, |_| {})?
+        }
+        CliCommand::Split { input, output, at, reencode, mut overwrite, no_overwrite, dry_run } => {
+            validate_inputs(std::slice::from_ref(&input))?;
+            check_video_file(&input)?;
+            // Same rule as combine, every part that is already there is asked about
+            if !overwrite && !no_overwrite && !dry_run {
+                let count = at.len() + 1;
+                for index in 0..count {
+                    let segment = segment_path(&output, index, count);
+                    if segment.exists() && !confirm_overwrite(&segment)? {
+                        return Err(MyError::Cancelled);
+                    }
+                }
+                overwrite = true;
+            }
+            let segments = split_video(&input, &at, &output, reencode, overwrite, dry_run)?;
+            return Ok(serde_json::json!({ "outputs": segments }));
+        }
+        CliCommand::Streams { input } => {
+            return print_streams(&input);
+        }
+    };
+    Ok(serde_json::json!({ "output": output }))
+}
+
+fn run_combine(args: CombineArgs, json: bool, quiet: bool) {
+    if let Some(log_file) = &args.log_file {
+        if let Err(e) = progress_log::open(log_file) {
+            exit_with_error(e);
+        }
+    }
+    if let Err(e) = check_concat_strategy(&args) {
+        exit_with_error(e);
+    }
+    if let Some(watch_dir) = args.watch.clone() {
+        return run_watch(args, watch_dir);
+    }
+    if let Some(manifest_path) = args.manifest.clone() {
+        return run_manifest(args, manifest_path, json);
+    }
+    let input = args.input.clone().unwrap_or_default();
+    // With --output-template the name is only known once the inputs are expanded
+    let output = args.output
+        .clone()
+        .or_else(|| args.output_template.clone().map(PathBuf::from))
+        .unwrap_or_default();
+
+    if input.len() == 0 || output.as_os_str().is_empty() {
+        exit_with_error(
+            MyError::InvalidInput("Please provide input and output files".to_string())
+        );
+    }
+    let input_files = match expand_inputs(&input, args.recursive, !args.no_sort) {
+        Ok(input_files) => input_files,
+        Err(e) => exit_with_error(e),
+    };
+    let output = match &args.output_template {
+        Some(template) => PathBuf::from(expand_template(template, &input_files)),
+        None => output,
+    };
+    let output = match resolve_output_path(&output, args.output_dir.as_deref()) {
+        Ok(output) => output,
+        Err(e) => exit_with_error(e),
+    };
+    if let Err(e) = ensure_tools_available() {
+        exit_with_error(e);
+    }
+    if let Err(e) = validate_inputs(&input_files) {
+        exit_with_error(e);
+    }
+    // Before the overwrite question, replacing an input is never what was meant
+    if let Err(e) = check_output_not_an_input(&input_files, &output) {
+        exit_with_error(e);
+    }
+    let piped = is_stdout_output(&output);
+    if piped {
+        if json {
+            exit_with_error(
+                MyError::InvalidInput("--json can't be used when writing to stdout".to_string())
+            );
+        }
+        if let Err(e) = validate_pipe_format(args.pipe_format) {
+            exit_with_error(e);
+        }
+        set_pipe_output(true);
+    }
+    let output_ffmpeg = Command::new(Tool::Ffmpeg.path())
+        .arg("-hide_banner")
+        .arg("-version")
+        .output()
+        .expect("Failed to run ffmpeg");
+    verbose!("ffmpeg version: {}", String::from_utf8_lossy(&output_ffmpeg.stdout));
+    let trims = match trims_for(&args, input_files.len()) {
+        Ok(trims) => trims,
+        Err(e) => exit_with_error(e),
+    };
+    let (input_files, trims) = match check_unreadable_inputs(&args, input_files, trims) {
+        Ok(checked) => checked,
+        Err(e) => exit_with_error(e),
+    };
+    let estimate = match estimate_output(&input_files, &trims) {
+        Ok(estimate) => {
+            let repeat = f64::from(args.repeat);
+            Some(OutputEstimate {
+                duration_seconds: estimate.duration_seconds * repeat,
+                size_bytes: ((estimate.size_bytes as f64) * repeat) as u64,
+            })
+        }
+        Err(e) => {
+            verbose!("Could not estimate the output: {}", e);
+            None
+        }
+    };
+    let concat_strategy = match resolve_concat_strategy(&args, &input_files) {
+        Ok(concat_strategy) => concat_strategy,
+        Err(e) => exit_with_error(e),
+    };
+    let mut config = match combine_config(&args, input_files, output, trims, concat_strategy) {
+        Ok(config) => config,
+        Err(e) => exit_with_error(e),
+    };
+    if !config.options.overwrite && !piped && config.output.exists() && !args.no_overwrite {
+        match confirm_overwrite(&config.output) {
+            Ok(true) => {
+                config.options.overwrite = true;
+            }
+            Ok(false) => exit_with_error(MyError::Cancelled),
+            Err(e) => exit_with_error(e),
+        }
+    }
+    status!("{}", job_summary(&config, estimate.as_ref()));
+    if !args.yes && !args.dry_run {
+        match confirm_start() {
+            Ok(true) => {}
+            Ok(false) => exit_with_error(MyError::Cancelled),
+            Err(e) => exit_with_error(e),
+        }
+    }
+    let result = combine_and_encode_videos(config, |fraction| {
+        if !json && !quiet && !piped {
+            print!("\rConcatenating video: {:.0}%", fraction * 100.0);
+            std::io::stdout().flush().ok();
+        }
+    });
+    match result {
+        Ok(summary) => {
+            if json {
+                println!("{}", serde_json::to_string(&summary).unwrap());
+            }
+        }
+        Err(e) => {
+            status!("Failed to combine videos");
+            exit_with_error(e);
+        }
+    }
+}
+
+// Options that only work when the clips are re-encoded
+fn check_concat_strategy(args: &CombineArgs) -> Result<(), MyError> {
+    if args.concat_strategy != Some(ConcatStrategy::StreamCopy) {
+        return Ok(());
+    }
+    let needs_reencode = [
+        (args.crossfade > 0.0, "--crossfade"),
+        (args.fix_vfr, "--fix-vfr"),
+        (args.label_clips, "--label-clips"),
+        (args.target_size.is_some(), "--target-size"),
+        (args.tonemap, "--tonemap"),
+    ];
+    match needs_reencode.iter().find(|(given, _)| *given) {
+        Some((_, option)) => {
+            Err(MyError::InvalidInput(format!("{} needs the clips to be re-encoded", option)))
+        }
+        None => Ok(()),
+    }
+}
+
+// Probes every input before the job starts. Unreadable ones fail the job with the whole list,
+// or with --skip-bad are dropped together with their trims.
+fn check_unreadable_inputs(
+    args: &CombineArgs,
+    input_files: Vec<PathBuf>,
+    trims: Vec<Option<TrimRange>>
+) -> Result<(Vec<PathBuf>, Vec<Option<TrimRange>>), MyError> {
+    let unreadable = find_unreadable_inputs(&input_files);
+    if unreadable.is_empty() {
+        return Ok((input_files, trims));
+    }
+    if !args.skip_bad {
+        let list: Vec<String> = unreadable
+            .iter()
+            .map(|(file, reason)| format!("  {}: {}", file.display(), reason))
+            .collect();
+        return Err(
+            MyError::InvalidInput(
+                format!(
+                    "Can't read {} of the inputs, fix or remove them or pass --skip-bad:\n{}",
+                    unreadable.len(),
+                    list.join("\n")
+                )
+            )
+        );
+    }
+    for (file, reason) in &unreadable {
+        status!("Warning: skipping {}: {}", file.display(), reason);
+        progress_log::record("preflight", Some(file), "skipped", Some(reason.clone()));
+    }
+    let (input_files, trims): (Vec<PathBuf>, Vec<Option<TrimRange>>) = input_files
+        .into_iter()
+        .zip(trims)
+        .filter(|(file, _)| !unreadable.iter().any(|(bad, _)| bad == file))
+        .unzip();
+    if input_files.is_empty() {
+        return Err(MyError::InvalidInput("None of the inputs can be read".to_string()));
+    }
+    Ok((input_files, trims))
+}
+
+// One entry per input, --trim only names the inputs it cuts
+fn trims_for(args: &CombineArgs, input_count: usize) -> Result<Vec<Option<TrimRange>>, MyError> {
+    let mut trims: Vec<Option<TrimRange>> = vec![None; input_count];
+    for (index, trim) in &args.trim {
+        match trims.get_mut(*index) {
+            Some(slot) => {
+                *slot = Some(*trim);
+            }
+            None => {
+                return Err(
+                    MyError::InvalidInput(format!("Trim index {} is out of range", index))
+                );
+            }
+        }
+    }
+    Ok(trims)
+}
+
+// A relative output is joined to `output_dir` instead of depending on the working directory.
+// The folders are created, absolute paths and stdout are kept as they are.
+fn resolve_output_path(output: &Path, output_dir: Option<&Path>) -> Result<PathBuf, MyError> {
+    let Some(output_dir) = output_dir else {
+        return Ok(output.to_path_buf());
+    };
+    if is_stdout_output(output) || output.is_absolute() {
+        return Ok(output.to_path_buf());
+    }
+    std::fs::create_dir_all(output_dir)?;
+    let output = output_dir.canonicalize()?.join(output);
+    // `-o renders/out.mp4` needs its own folder too
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    Ok(output)
+}
+
+// Asks on the terminal whether to replace `output`, anything but yes keeps it.
+// Without a terminal there is nobody to ask, so the file is kept.
+fn confirm_overwrite(output: &Path) -> Result<bool, MyError> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Ok(false);
+    }
+    eprint!("{} already exists, overwrite it? [y/N] ", output.display());
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+// Asks on the terminal whether to start the job, an empty answer starts it.
+// Without a terminal there is nobody to ask, so scripts keep working without --yes.
+fn confirm_start() -> Result<bool, MyError> {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        return Ok(true);
+    }
+    eprint!("Start? [Y/n] ");
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    stdin.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "" | "y" | "yes"))
+}
+
+// What the job is about to do, printed before it starts so a mistyped command shows up
+// before hours of encoding
+fn job_summary(config: &CombineConfig, estimate: Option<&OutputEstimate>) -> String {
+    let options = &config.options;
+    let mut lines = match (config.inputs.as_slice(), options.repeat) {
+        ([input], repeat) if repeat > 1 => {
+            vec![
+                format!(
+                    "Repeating {} {} times into {}",
+                    input.display(),
+                    repeat,
+                    config.output.display()
+                )
+            ]
+        }
+        _ => {
+            vec![
+                format!("Combining {} inputs into {}", config.inputs.len(), config.output.display())
+            ]
+        }
+    };
+    if let Some(estimate) = estimate {
+        lines.push(estimate.to_string());
+    }
+    if options.audio_only {
+        lines.push("Video: none (audio only)".to_string());
     } else {
-        init_app();
+        let mut video = match options.concat_strategy {
+            ConcatStrategy::StreamCopy => "stream copy".to_string(),
+            ConcatStrategy::ReEncode => format!("re-encode with {}", options.encoder.label()),
+        };
+        match &options.video_quality {
+            Some(VideoQuality::Crf(crf)) => video.push_str(&format!(", crf {}", crf)),
+            Some(VideoQuality::Bitrate(bitrate)) => video.push_str(&format!(", {}", bitrate)),
+            None => {}
+        }
+        // Stream copied clips aren't encoded, so only a re-encode uses the preset
+        let reencode = options.concat_strategy == ConcatStrategy::ReEncode;
+        if reencode && options.encoder == Encoder::Software && options.preset != Preset::default() {
+            video.push_str(&format!(", preset {}", options.preset.name()));
+        }
+        if let Some((width, height)) = options.target_resolution {
+            video.push_str(&format!(", {}x{}", width, height));
+        }
+        if let Some(fps) = options.target_fps {
+            video.push_str(&format!(", {} fps", fps));
+        }
+        lines.push(format!("Video: {}", video));
+    }
+    let mut audio = format!("{}, {}", options.audio_codec.label(), options.mix_mode.label());
+    if let Some(bitrate) = options.audio_bitrate {
+        audio.push_str(&format!(", {} kbit/s", bitrate / 1000));
     }
+    lines.push(format!("Audio: {}", audio));
+    lines.join("\n")
+}
+
+// Picks stream copy unless the clips differ or a transition needs them re-encoded
+fn resolve_concat_strategy(
+    args: &CombineArgs,
+    input_files: &[PathBuf]
+) -> Result<ConcatStrategy, MyError> {
+    if let Some(concat_strategy) = args.concat_strategy {
+        return Ok(concat_strategy);
+    }
+    // No video is written, so there is nothing to probe
+    if args.audio_only {
+        return Ok(ConcatStrategy::StreamCopy);
+    }
+    if args.crossfade > 0.0 || args.label_clips || args.target_size.is_some() {
+        return Ok(ConcatStrategy::ReEncode);
+    }
+    if let Some(mismatch) = describe_stream_mismatch(input_files)? {
+        status!("Warning: {}, re-encoding instead of stream copying", mismatch);
+        return Ok(ConcatStrategy::ReEncode);
+    }
+    if let Some(vfr) = describe_variable_frame_rate(input_files)? {
+        if args.fix_vfr {
+            status!("Found {}, re-encoding to a constant frame rate", vfr);
+            return Ok(ConcatStrategy::ReEncode);
+        }
+        status!(
+            "Warning: found {}, stream copying it can drift the audio out of sync, \
+             use --fix-vfr to re-encode to a constant frame rate",
+            vfr
+        );
+    }
+    Ok(ConcatStrategy::StreamCopy)
+}
+
+fn combine_config(
+    args: &CombineArgs,
+    inputs: Vec<PathBuf>,
+    output: PathBuf,
+    trims: Vec<Option<TrimRange>>,
+    concat_strategy: ConcatStrategy
+) -> Result<CombineConfig, MyError> {
+    let video_quality = match (args.crf, &args.video_bitrate) {
+        (Some(crf), _) => Some(VideoQuality::Crf(crf)),
+        (None, Some(bitrate)) => Some(VideoQuality::Bitrate(bitrate.clone())),
+        (None, None) => None,
+    };
+    let track_roles = match (args.background_track, args.voiceover_track) {
+        (Some(background), Some(voiceover)) => Some(TrackRoles::new(background, voiceover)?),
+        _ => None,
+    };
+    Ok(
+        CombineConfig::builder(inputs, output)
+            .volumes(args.volume.clone())
+            .auto_gain(args.auto_gain)
+            .track_roles(track_roles)
+            .drop_background(args.drop_background)
+            .trims(trims)
+            .intermediate_container(args.intermediate_container)
+            .intermediate_audio(args.intermediate_audio)
+            .intermediate_quality(args.intermediate_quality)
+            .audio_codec(args.audio_codec)
+            .audio_bitrate(args.audio_bitrate)
+            .aac_profile(args.aac_profile)
+            .mix_mode(args.mix_mode)
+            .sample_rate(args.sample_rate)
+            .channels(args.channels)
+            .target_resolution(args.resolution)
+            .target_fps(args.fps)
+            .encoder(args.hwaccel)
+            .video_quality(video_quality)
+            .preset(args.preset)
+            .target_size(args.target_size)
+            .concat_strategy(concat_strategy)
+            .subtitles(args.subtitles)
+            .chapters(args.chapters)
+            .label_clips(args.label_clips)
+            .label_time(args.label_time)
+            .audio_only(args.audio_only)
+            .crossfade(Some(args.crossfade).filter(|seconds| *seconds > 0.0))
+            .loudness_target(args.normalize.then_some(args.target_lufs))
+            .fade_in(Some(args.fade_in).filter(|seconds| *seconds > 0.0))
+            .fade_out(Some(args.fade_out).filter(|seconds| *seconds > 0.0))
+            .keep_temp(args.keep_temp)
+            .resume(args.resume)
+            .no_pad(args.no_pad)
+            .threads(args.threads)
+            .overwrite(args.overwrite)
+            .pipe_format(args.pipe_format)
+            .extra_ffmpeg_args(args.extra_ffmpeg_arg.clone())
+            .metadata(OutputMetadata {
+                strip: args.strip_metadata,
+                title: args.title.clone(),
+                author: args.author.clone(),
+            })
+            .batch_size(args.batch_size)
+            .animation_width(args.animation_width)
+            .repeat(usize::from(args.repeat))
+            .tonemap(args.tonemap)
+            .dry_run(args.dry_run)
+            .build()
+    )
+}
+
+// Combines every complete set of clips that shows up in the watched folder, until interrupted
+fn run_watch(args: CombineArgs, watch_dir: PathBuf) {
+    let Some(output_dir) = args.output.clone() else {
+        exit_with_error(
+            MyError::InvalidInput("Please provide the output folder for --watch".to_string())
+        );
+    };
+    let output_dir = match resolve_output_path(&output_dir, args.output_dir.as_deref()) {
+        Ok(output_dir) => output_dir,
+        Err(e) => exit_with_error(e),
+    };
+    if let Err(e) = ensure_tools_available() {
+        exit_with_error(e);
+    }
+    let pattern = match glob::Pattern::new(&args.watch_pattern) {
+        Ok(pattern) => pattern,
+        Err(e) => {
+            exit_with_error(
+                MyError::InvalidInput(format!("Invalid pattern `{}`: {}", args.watch_pattern, e))
+            );
+        }
+    };
+    if let Err(e) = std::fs::create_dir_all(&output_dir) {
+        exit_with_error(e.into());
+    }
+    // Events come with absolute paths
+    let dir = match watch_dir.canonicalize() {
+        Ok(dir) => dir,
+        Err(e) => exit_with_error(e.into()),
+    };
+    let settings = watch::WatchSettings {
+        dir,
+        output_dir,
+        pattern,
+        clips_per_set: args.watch_count as usize,
+    };
+
+    let result = watch::watch_directory(&settings, |clips, output| {
+        validate_inputs(&clips)?;
+        let trims = vec![None; clips.len()];
+        let (clips, trims) = check_unreadable_inputs(&args, clips, trims)?;
+        let concat_strategy = resolve_concat_strategy(&args, &clips)?;
+        combine_videos(combine_config(&args, clips, output, trims, concat_strategy)?)
+    });
+    if let Err(e) = result {
+        exit_with_error(e);
+    }
+}
+
+// Runs every job of the manifest, a failed job is reported at the end instead of stopping
+// the ones after it
+fn run_manifest(args: CombineArgs, manifest_path: PathBuf, json: bool) {
+    let manifest = match manifest::load(&manifest_path) {
+        Ok(manifest) => manifest,
+        Err(e) => exit_with_error(e),
+    };
+    // Paths in the manifest are relative to its folder
+    let manifest_dir = match manifest_path.canonicalize() {
+        Ok(path) => path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        Err(e) => exit_with_error(e.into()),
+    };
+    if let Err(e) = ensure_tools_available() {
+        exit_with_error(e);
+    }
+
+    let total = manifest.jobs.len();
+    let mut results: Vec<(String, Result<PathBuf, MyError>)> = Vec::new();
+    for (index, job) in manifest.jobs.iter().enumerate() {
+        status!("Job {}/{}: {}", index + 1, total, job.output);
+        let result = job
+            .combine_args(&args, &manifest_dir)
+            .and_then(|job_args| run_manifest_job(&job_args, &manifest_dir));
+        if let Err(e) = &result {
+            status!("Job {} failed: {}", job.output, e);
+        }
+        results.push((job.output.clone(), result));
+    }
+
+    let failed = results
+        .iter()
+        .filter(|(_, result)| result.is_err())
+        .count();
+    if json {
+        let jobs: Vec<serde_json::Value> = results
+            .iter()
+            .map(|(output, result)| {
+                match result {
+                    Ok(path) => serde_json::json!({ "output": path }),
+                    Err(e) => {
+                        serde_json::json!({
+                            "output": output,
+                            "error": e.kind(),
+                            "message": e.to_string(),
+                        })
+                    }
+                }
+            })
+            .collect();
+        println!("{}", serde_json::json!({ "jobs": jobs }));
+    } else {
+        status!("{} of {} jobs done", total - failed, total);
+        for (output, result) in &results {
+            if let Err(e) = result {
+                status!("  {}: {}", output, e);
+            }
+        }
+    }
+    // Same exit code as any other error, the failures are listed above
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
+
+fn run_manifest_job(args: &CombineArgs, manifest_dir: &Path) -> Result<PathBuf, MyError> {
+    check_concat_strategy(args)?;
+    let patterns = args.input.as_deref().unwrap_or_default();
+    let input_files = expand_inputs(patterns, args.recursive, !args.no_sort)?;
+    validate_inputs(&input_files)?;
+    let output_dir = args.output_dir.as_deref().unwrap_or(manifest_dir);
+    let output = resolve_output_path(&args.output.clone().unwrap_or_default(), Some(output_dir))?;
+    let trims = trims_for(args, input_files.len())?;
+    let (input_files, trims) = check_unreadable_inputs(args, input_files, trims)?;
+    let concat_strategy = resolve_concat_strategy(args, &input_files)?;
+    combine_videos(combine_config(args, input_files, output.clone(), trims, concat_strategy)?)?;
+    Ok(output)
 }
 
 fn init_app() {
@@ -427,29 +2331,472 @@ fn init_app() {
     let widget_theme = WidgetTheme::new(ThemeType::Aero);
     widget_theme.apply();
 
-    let state = State::new();
+    let config_path = config::default_config_path();
+    let config = Config::load(&config_path);
+    let mut state = State::new(config_path);
+    if !config.last_volumes.is_empty() {
+        state.volumes = config.last_volumes.clone();
+    }
+    let initial_volumes = state.volumes.clone();
     app::GlobalState::new(state);
 
-    let mut wind = Window::new(100, 100, 400, 300, "Video editor");
+    let (sender, receiver) = app::channel::<JobMessage>();
+
+    let mut wind = Window::new(100, 100, 500, 970, "Video editor");
     {
         let mut col = Flex::default_fill().column();
         col.begin();
         let mut menu_bar = SysMenuBar::default().with_size(wind.width(), 30);
         create_menu(&mut menu_bar);
+        // list of imported videos, in concat order
+        let mut video_list = HoldBrowser::default().with_id("video_list");
+        video_list.set_callback(move |_| video_list_callback());
+        video_list.handle(|_, event| {
+            if event == Event::KeyDown && app::event_key() == Key::Delete {
+                remove_selected_video();
+                return true;
+            }
+            false
+        });
+        let mut media_info = TextDisplay::default().with_id("media_info");
+        media_info.set_buffer(TextBuffer::default());
+        media_info.set_tooltip("Format of the selected video");
+        let order_row = Flex::default_fill().row();
+        let mut move_up_button = Button::default().with_label("Move Up");
+        move_up_button.set_callback(move |_| move_selected_video(-1));
+        let mut move_down_button = Button::default().with_label("Move Down");
+        move_down_button.set_callback(move |_| move_selected_video(1));
+        let mut remove_button = Button::default().with_label("Remove");
+        remove_button.set_callback(move |_| remove_selected_video());
+        order_row.end();
         // create input box for volume
-        let row = Flex::default_fill().row();
+        let volume_row = Flex::default_fill().row().with_id("volume_row");
         Frame::default().with_size(100, 30).with_label("Volume:");
-        FloatInput::default().with_size(100, 30).with_id("volume_input");
-        row.end();
-        let mut button = Button::default().with_size(100, 30).with_label("Combine");
-        button.set_callback(move |_| combine_button_callback());
+        for (track_index, volume) in initial_volumes.iter().enumerate() {
+            let mut volume_input = FloatInput::default()
+                .with_size(100, 30)
+                .with_id(&format!("volume_input_{}", track_index));
+            volume_input.set_value(&volume.to_string());
+            volume_input.set_tooltip(&format!("Volume of audio track {}", track_index));
+        }
+        volume_row.end();
+        let roles_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Background:");
+        let mut background_choice = Choice::default()
+            .with_size(100, 30)
+            .with_id("background_track_choice");
+        fill_track_choice(&mut background_choice, initial_volumes.len());
+        background_choice.set_tooltip("Audio track with the game or music");
+        Frame::default().with_size(100, 30).with_label("Voiceover:");
+        let mut voiceover_choice = Choice::default()
+            .with_size(100, 30)
+            .with_id("voiceover_track_choice");
+        fill_track_choice(&mut voiceover_choice, initial_volumes.len());
+        voiceover_choice.set_tooltip("Audio track with the microphone");
+        roles_row.end();
+        let mut drop_background_check = CheckButton::default()
+            .with_label("Drop the background track, keep only the voiceover")
+            .with_id("drop_background_check");
+        drop_background_check.set_tooltip(
+            "Leaves out the first track, or the one picked as background, instead of muting it"
+        );
+        let trim_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Trim (s):");
+        let mut trim_start_input = FloatInput::default().with_id("trim_start_input");
+        trim_start_input.set_tooltip("Start of the selected clip, empty keeps the beginning");
+        let mut trim_end_input = FloatInput::default().with_id("trim_end_input");
+        trim_end_input.set_tooltip("End of the selected clip, empty keeps the rest");
+        let mut apply_trim_button = Button::default().with_label("Apply");
+        apply_trim_button.set_callback(move |_| apply_trim_callback());
+        trim_row.end();
+        let clip_volume_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Clip volume:");
+        let mut clip_volume_input = FloatInput::default().with_id("clip_volume_input");
+        clip_volume_input.set_value("1");
+        clip_volume_input.set_tooltip(
+            &format!(
+                "Multiplies the track volumes of the selected clip ({} to {})",
+                MIN_VOLUME,
+                MAX_VOLUME
+            )
+        );
+        let mut apply_clip_volume_button = Button::default().with_label("Apply");
+        apply_clip_volume_button.set_callback(move |_| apply_clip_volume_callback());
+        clip_volume_row.end();
+        let codec_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Audio codec:");
+        let mut codec_choice = Choice::default().with_size(100, 30).with_id("audio_codec_choice");
+        for codec in AudioCodec::value_variants() {
+            codec_choice.add_choice(codec.label());
+        }
+        codec_choice.set_value(0);
+        Frame::default().with_size(100, 30).with_label("Tracks:");
+        let mut mix_choice = Choice::default().with_size(100, 30).with_id("mix_mode_choice");
+        for mix_mode in MixMode::value_variants() {
+            mix_choice.add_choice(mix_mode.label());
+        }
+        mix_choice.set_value(0);
+        codec_row.end();
+        let resolution_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Resolution:");
+        let mut resolution_choice = Choice::default()
+            .with_size(100, 30)
+            .with_id("resolution_choice");
+        resolution_choice.add_choice("Original");
+        for (width, height) in RESOLUTION_PRESETS {
+            resolution_choice.add_choice(&format!("{}x{}", width, height));
+        }
+        resolution_choice.set_value(0);
+        Frame::default().with_size(100, 30).with_label("FPS:");
+        let mut fps_input = FloatInput::default().with_size(100, 30).with_id("fps_input");
+        fps_input.set_tooltip("Frame rate every clip is converted to, leave empty to keep it");
+        resolution_row.end();
+        let encoder_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Encoder:");
+        let mut encoder_choice = Choice::default().with_size(100, 30).with_id("encoder_choice");
+        let available_encoders = probe_available_encoders();
+        for (index, encoder) in Encoder::value_variants().iter().enumerate() {
+            encoder_choice.add_choice(encoder.label());
+            if !available_encoders.contains(encoder) {
+                if let Some(mut item) = encoder_choice.at(index as i32) {
+                    item.deactivate();
+                }
+            }
+        }
+        encoder_choice.set_value(0);
+        Frame::default().with_size(100, 30).with_label("Threads:");
+        let mut threads_spinner = Spinner::default().with_size(100, 30).with_id("threads_spinner");
+        let core_count = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        threads_spinner.set_range(0.0, core_count as f64);
+        threads_spinner.set_step(1.0);
+        threads_spinner.set_value(0.0);
+        threads_spinner.set_tooltip("Threads used for encoding, 0 lets ffmpeg decide");
+        encoder_row.end();
+        let preset_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Preset:");
+        let mut preset_choice = Choice::default().with_size(100, 30).with_id("preset_choice");
+        for preset in Preset::value_variants() {
+            preset_choice.add_choice(preset.name());
+        }
+        let default_preset = Preset::value_variants()
+            .iter()
+            .position(|preset| *preset == Preset::default())
+            .unwrap_or(0);
+        preset_choice.set_value(default_preset as i32);
+        preset_choice.set_tooltip("x264 speed against size, slower presets give smaller files");
+        preset_row.end();
+        let quality_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Quality (CRF):");
+        let mut crf_slider = HorValueSlider::default().with_size(100, 30).with_id("crf_slider");
+        crf_slider.set_range(0.0, 51.0);
+        crf_slider.set_step(1.0, 1);
+        crf_slider.set_value(23.0);
+        crf_slider.set_tooltip("Used when clips are re-encoded, lower is better quality");
+        Frame::default().with_size(100, 30).with_label("Crossfade (s):");
+        let mut crossfade_input = FloatInput::default()
+            .with_size(100, 30)
+            .with_id("crossfade_input");
+        crossfade_input.set_tooltip("Seconds clips fade into each other, empty for hard cuts");
+        quality_row.end();
+        let normalize_check = CheckButton::default()
+            .with_label("Normalize loudness (-23 LUFS)")
+            .with_id("normalize_check");
+        let keep_temp_check = CheckButton::default()
+            .with_label("Keep temp files (debug)")
+            .with_id("keep_temp_check");
+        let mut notify_check = CheckButton::default()
+            .with_label("Desktop notification when done")
+            .with_id("notify_check");
+        notify_check.set_checked(!config.disable_notifications);
+        notify_check.set_tooltip("Shows when a combine ends even if the window is hidden");
+        notify_check.set_callback(notify_check_callback);
+        let output_row = Flex::default_fill().row();
+        Frame::default().with_size(100, 30).with_label("Output:");
+        let mut output_input = Input::default().with_size(100, 30).with_id("output_input");
+        output_input.set_tooltip("File Combine writes to, leave empty to pick it every time");
+        output_input.set_trigger(CallbackTrigger::Changed);
+        output_input.set_callback(output_input_callback);
+        let mut browse_button = Button::default().with_size(100, 30).with_label("Browse...");
+        browse_button.set_callback(move |_| browse_output_callback());
+        output_row.end();
+        let job_row = Flex::default_fill().row();
+        let mut button = Button::default()
+            .with_size(100, 30)
+            .with_label("Combine")
+            .with_id("combine_button");
+        let combine_sender = sender.clone();
+        button.set_callback(move |_| combine_button_callback(combine_sender.clone(), false));
+        let mut audio_only_button = Button::default()
+            .with_size(100, 30)
+            .with_label("Audio only")
+            .with_id("audio_only_button");
+        audio_only_button.set_tooltip("Combine only the audio of the clips into an audio file");
+        audio_only_button.set_callback(move |_| combine_button_callback(sender.clone(), true));
+        let mut cancel_button = Button::default()
+            .with_size(100, 30)
+            .with_label("Cancel")
+            .with_id("cancel_button");
+        cancel_button.set_callback(move |_| cancel_running_jobs());
+        cancel_button.deactivate();
+        job_row.end();
+        let mut progress_bar = Progress::default().with_size(100, 30).with_id("progress_bar");
+        progress_bar.set_minimum(0.0);
+        progress_bar.set_maximum(100.0);
+        let estimate_label = Frame::default().with_size(100, 30).with_id("estimate_label");
         wind.resizable(&col);
         col.fixed(&menu_bar, 30);
+        col.fixed(&media_info, 110);
+        col.fixed(&order_row, 30);
+        col.fixed(&volume_row, 30);
+        col.fixed(&roles_row, 30);
+        col.fixed(&drop_background_check, 30);
+        col.fixed(&trim_row, 30);
+        col.fixed(&clip_volume_row, 30);
+        col.fixed(&codec_row, 30);
+        col.fixed(&resolution_row, 30);
+        col.fixed(&encoder_row, 30);
+        col.fixed(&preset_row, 30);
+        col.fixed(&quality_row, 30);
+        col.fixed(&normalize_check, 30);
+        col.fixed(&keep_temp_check, 30);
+        col.fixed(&notify_check, 30);
+        col.fixed(&output_row, 30);
+        col.fixed(&job_row, 30);
+        col.fixed(&progress_bar, 30);
+        col.fixed(&estimate_label, 30);
         col.end();
     }
     wind.end();
+    wind.handle(window_handle);
+    wind.set_callback(window_callback);
     wind.show();
 
-    app.run().unwrap();
-    wind.set_callback(window_callback);
+    while app.wait() {
+        if let Some(message) = receiver.recv() {
+            handle_job_message(message);
+        }
+    }
+    // The loop also ends without a close event, e.g. when the last window is hidden
+    cancel_running_jobs();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cli_definition_is_valid() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn completions_cover_the_options_and_subcommands() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script: Vec<u8> = Vec::new();
+            print_completions(shell, &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("video_editor"), "{:?}", shell);
+            assert!(script.contains("crossfade"), "{:?}", shell);
+            assert!(script.contains("extract-audio"), "{:?}", shell);
+        }
+    }
+
+    #[test]
+    fn only_vorbis_intermediates_take_a_quality() {
+        let vorbis = AudioEncoding { format: IntermediateAudio::Vorbis, quality: Some(6.0) };
+        assert_eq!(vorbis.codec_args(), vec!["-c:a", "libvorbis", "-q:a", "6"]);
+        let flac = AudioEncoding { format: IntermediateAudio::Flac, quality: Some(6.0) };
+        assert_eq!(flac.codec_args(), vec!["-c:a", "flac"]);
+        assert_eq!(AudioEncoding::default().codec_args(), vec!["-c:a", "libvorbis"]);
+
+        assert!(parse_intermediate_quality("10").is_ok());
+        assert!(parse_intermediate_quality("11").is_err());
+    }
+
+    #[test]
+    fn audio_bitrates_are_bits_per_second() {
+        assert_eq!(parse_audio_bitrate("192k"), Ok(192_000));
+        assert_eq!(parse_audio_bitrate("96K"), Ok(96_000));
+        assert_eq!(parse_audio_bitrate("128000"), Ok(128_000));
+        assert_eq!(parse_audio_bitrate("64.5k"), Ok(64_500));
+        assert!(parse_audio_bitrate("192kbps").is_err());
+        assert!(parse_audio_bitrate("-96k").is_err());
+        assert!(parse_audio_bitrate("k").is_err());
+        assert!(parse_audio_bitrate("4k").is_err());
+        assert!(parse_audio_bitrate("1M").is_err());
+    }
+
+    #[test]
+    fn split_times_take_seconds_or_clock_times() {
+        assert_eq!(parse_split_time("90"), Ok(90.0));
+        assert_eq!(parse_split_time("1:30"), Ok(90.0));
+        assert_eq!(parse_split_time("1:01:30.5"), Ok(3690.5));
+        assert_eq!(parse_split_time("90:00"), Ok(5400.0));
+        assert!(parse_split_time("1:60").is_err());
+        assert!(parse_split_time("0:00").is_err());
+        assert!(parse_split_time("1:2:3:4").is_err());
+        assert!(parse_split_time("1m30").is_err());
+    }
+
+    #[test]
+    fn elapsed_times_are_short() {
+        assert_eq!(format_elapsed(Duration::from_millis(42_900)), "42s");
+        assert_eq!(format_elapsed(Duration::from_secs(185)), "3m 05s");
+        assert_eq!(format_elapsed(Duration::from_secs(3723)), "1h 02m 03s");
+    }
+
+    #[test]
+    fn target_sizes_use_decimal_units() {
+        assert_eq!(parse_target_size("100MB"), Ok(100_000_000));
+        assert_eq!(parse_target_size("1.5g"), Ok(1_500_000_000));
+        assert_eq!(parse_target_size("700 KB"), Ok(700_000));
+        assert_eq!(parse_target_size("2048"), Ok(2048));
+        assert!(parse_target_size("0MB").is_err());
+        assert!(parse_target_size("100MiB").is_err());
+        assert!(parse_target_size("MB").is_err());
+    }
+
+    #[test]
+    fn manifest_jobs_override_the_command_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("jobs.toml");
+        std::fs::write(
+            &path,
+            r#"
+                [[job]]
+                inputs = ["day1/*.mkv"]
+                output = "day1.mp4"
+
+                [[job]]
+                inputs = ["/clips/a.mkv", "b.mkv"]
+                output = "ab.mp4"
+                volumes = [0.5, 1.2]
+                mix_mode = "merge"
+                video_bitrate = "8M"
+                trim = ["1=5-"]
+            "#
+        ).unwrap();
+        let manifest = manifest::load(&path).unwrap();
+        assert_eq!(manifest.jobs.len(), 2);
+
+        let base = Args::parse_from(["video_editor", "-c", "--crf", "20", "-v", "0.8"]).combine;
+        let first = manifest.jobs[0].combine_args(&base, dir.path()).unwrap();
+        let pattern = dir.path().join("day1/*.mkv").into_os_string();
+        assert_eq!(first.input, Some(vec![pattern]));
+        assert_eq!(first.output.as_deref(), Some(Path::new("day1.mp4")));
+        assert_eq!(first.volume, vec![0.8]);
+        assert_eq!(first.crf, Some(20));
+
+        let second = manifest.jobs[1].combine_args(&base, dir.path()).unwrap();
+        assert_eq!(second.input.as_ref().unwrap()[0], "/clips/a.mkv");
+        assert_eq!(second.volume, vec![0.5, 1.2]);
+        assert_eq!(second.mix_mode, MixMode::Merge);
+        assert_eq!((second.crf, second.video_bitrate.as_deref()), (None, Some("8M")));
+        assert_eq!(second.trim.len(), 1);
+
+        let both = manifest::Job {
+            output: "both.mp4".to_string(),
+            crf: Some(18),
+            video_bitrate: Some("8M".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(both.combine_args(&base, dir.path()), Err(MyError::InvalidInput(_))));
+
+        std::fs::write(&path, "[[job]]\ninputs = []\noutput = \"a.mp4\"\nspeed = 2\n").unwrap();
+        assert!(manifest::load(&path).is_err());
+        std::fs::write(&path, "").unwrap();
+        assert!(manifest::load(&path).is_err());
+    }
+
+    #[test]
+    fn outputs_need_an_existing_writable_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_output_writable(&dir.path().join("out.mp4")).is_ok());
+        assert!(check_output_writable(&dir.path().join("missing/out.mp4")).is_err());
+        // The probe file is gone again
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn relative_outputs_are_resolved_against_the_output_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("renders");
+
+        let output = resolve_output_path(Path::new("day1/out.mp4"), Some(&base)).unwrap();
+        assert_eq!(output, base.canonicalize().unwrap().join("day1/out.mp4"));
+        assert!(output.parent().unwrap().is_dir());
+
+        let absolute = dir.path().join("out.mp4");
+        assert_eq!(resolve_output_path(&absolute, Some(&base)).unwrap(), absolute);
+        let stdout = Path::new("-");
+        assert_eq!(resolve_output_path(stdout, Some(&base)).unwrap(), stdout);
+        let relative = Path::new("out.mp4");
+        assert_eq!(resolve_output_path(relative, None).unwrap(), relative);
+    }
+
+    #[test]
+    fn job_summary_lists_what_the_job_does() {
+        let config = CombineConfig::builder(vec!["a.mp4".into(), "b.mp4".into()], "out.mp4")
+            .concat_strategy(ConcatStrategy::ReEncode)
+            .video_quality(Some(VideoQuality::Crf(20)))
+            .target_resolution(Some((1280, 720)))
+            .audio_bitrate(Some(192_000))
+            .build();
+        let estimate = OutputEstimate { duration_seconds: 3725.0, size_bytes: 2_500_000 };
+
+        let summary = job_summary(&config, Some(&estimate));
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[0], "Combining 2 inputs into out.mp4");
+        assert_eq!(lines[1], "Total duration: 1:02:05; estimated size: 2.5 MB");
+        assert_eq!(lines[2], "Video: re-encode with Software (x264), crf 20, 1280x720");
+        assert_eq!(lines[3], "Audio: AAC, Mix (stereo), 192 kbit/s");
+
+        let repeated = CombineConfig::builder(vec!["a.mp4".into()], "loop.mp4").repeat(3).build();
+        assert!(job_summary(&repeated, None).starts_with("Repeating a.mp4 3 times into loop.mp4"));
+
+        let audio_only = CombineConfig::builder(vec!["a.mp4".into()], "out.m4a")
+            .audio_only(true)
+            .build();
+        assert!(job_summary(&audio_only, None).contains("Video: none (audio only)"));
+
+        let slow = CombineConfig::builder(vec!["a.mp4".into()], "out.mp4")
+            .concat_strategy(ConcatStrategy::ReEncode)
+            .preset(Preset::Slow)
+            .build();
+        let summary = job_summary(&slow, None);
+        assert!(summary.contains("Video: re-encode with Software (x264), preset slow"));
+        let copied = CombineConfig::builder(vec!["a.mp4".into()], "out.mp4")
+            .preset(Preset::Slow)
+            .build();
+        assert!(job_summary(&copied, None).contains("Video: stream copy\n"));
+    }
+
+    #[test]
+    fn clip_volumes_must_be_in_range() {
+        assert_eq!(parse_clip_volume(" 1.5 "), Ok(1.5));
+        assert_eq!(parse_clip_volume(""), Ok(1.0));
+        assert_eq!(parse_clip_volume("0"), Ok(0.0));
+        assert!(parse_clip_volume("5").is_err());
+        assert!(parse_clip_volume("-1").is_err());
+        assert!(parse_clip_volume("loud").is_err());
+    }
+
+    #[test]
+    fn presets_are_parsed_by_name() {
+        assert_eq!(Preset::from_str("VerySlow", true), Ok(Preset::Veryslow));
+        assert!(Preset::from_str("placebo", true).is_err());
+    }
+
+    #[test]
+    fn stream_copy_is_refused_with_options_that_re_encode() {
+        let args = |extra: &[&str]| {
+            let mut argv = vec!["video_editor", "-c", "--concat-strategy", "stream-copy"];
+            argv.extend_from_slice(extra);
+            Args::parse_from(argv).combine
+        };
+        assert!(check_concat_strategy(&args(&[])).is_ok());
+        let tonemap = check_concat_strategy(&args(&["--tonemap"]));
+        assert!(matches!(tonemap, Err(MyError::InvalidInput(_))));
+        assert!(check_concat_strategy(&args(&["--label-clips"])).is_err());
+    }
 }
@@ -0,0 +1,167 @@
+use std::{ ffi::OsString, fs, path::{ Path, PathBuf } };
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{
+    parse_crossfade, parse_fade, parse_fps, parse_resolution, parse_trim_arg, parse_volume,
+    AudioCodec, CombineArgs, MixMode, MyError, Preset,
+};
+
+// A batch of outputs for --manifest, every `[[job]]` table is one combined video
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    #[serde(rename = "job", default)]
+    pub jobs: Vec<Job>,
+}
+
+// Settings a job leaves out keep the value given on the command line
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Job {
+    pub inputs: Vec<String>,
+    pub output: String,
+    pub volumes: Option<Vec<f32>>,
+    pub auto_gain: Option<bool>,
+    pub trim: Option<Vec<String>>,
+    pub audio_codec: Option<String>,
+    pub mix_mode: Option<String>,
+    pub resolution: Option<String>,
+    pub fps: Option<f64>,
+    pub crf: Option<u8>,
+    pub video_bitrate: Option<String>,
+    pub preset: Option<String>,
+    pub crossfade: Option<f64>,
+    pub normalize: Option<bool>,
+    pub target_lufs: Option<f32>,
+    pub fade_in: Option<f64>,
+    pub fade_out: Option<f64>,
+    pub audio_only: Option<bool>,
+    pub chapters: Option<bool>,
+    pub label_clips: Option<bool>,
+    pub overwrite: Option<bool>,
+}
+
+pub fn load(path: &Path) -> Result<Manifest, MyError> {
+    let contents = fs::read_to_string(path)?;
+    let manifest: Manifest = toml
+        ::from_str(&contents)
+        .map_err(|e| MyError::InvalidInput(format!("Invalid manifest {}: {}", path.display(), e)))?;
+    if manifest.jobs.is_empty() {
+        return Err(
+            MyError::InvalidInput(format!("The manifest {} has no [[job]]", path.display()))
+        );
+    }
+    Ok(manifest)
+}
+
+// Values go through the same parsers as the command line, so they are checked the same way
+fn checked<T>(value: impl ToString, parse: fn(&str) -> Result<T, String>) -> Result<T, MyError> {
+    parse(&value.to_string()).map_err(MyError::InvalidInput)
+}
+
+fn value_enum<T: ValueEnum>(value: &str, name: &str) -> Result<T, MyError> {
+    T::from_str(value, true).map_err(|_| {
+        MyError::InvalidInput(format!("Invalid {} `{}` in the manifest", name, value))
+    })
+}
+
+impl Job {
+    // The job's settings on top of the command line ones, `dir` is the folder of the manifest
+    // so relative inputs don't depend on the working directory
+    pub fn combine_args(&self, base: &CombineArgs, dir: &Path) -> Result<CombineArgs, MyError> {
+        let mut args = base.clone();
+        args.input = Some(
+            self.inputs
+                .iter()
+                .map(|input| {
+                    if Path::new(input).is_absolute() {
+                        OsString::from(input)
+                    } else {
+                        dir.join(input).into_os_string()
+                    }
+                })
+                .collect()
+        );
+        args.output = Some(PathBuf::from(&self.output));
+        if let Some(volumes) = &self.volumes {
+            args.volume = volumes
+                .iter()
+                .map(|volume| checked(volume, parse_volume))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(auto_gain) = self.auto_gain {
+            args.auto_gain = auto_gain;
+        }
+        if let Some(trim) = &self.trim {
+            args.trim = trim
+                .iter()
+                .map(|trim| checked(trim, parse_trim_arg))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(audio_codec) = &self.audio_codec {
+            args.audio_codec = value_enum::<AudioCodec>(audio_codec, "audio_codec")?;
+        }
+        if let Some(mix_mode) = &self.mix_mode {
+            args.mix_mode = value_enum::<MixMode>(mix_mode, "mix_mode")?;
+        }
+        if let Some(resolution) = &self.resolution {
+            args.resolution = Some(checked(resolution, parse_resolution)?);
+        }
+        if let Some(fps) = self.fps {
+            args.fps = Some(checked(fps, parse_fps)?);
+        }
+        // crf and video_bitrate exclude each other, the job's choice replaces the other one
+        if self.crf.is_some() && self.video_bitrate.is_some() {
+            return Err(
+                MyError::InvalidInput(
+                    format!("The job for {} sets both crf and video_bitrate", self.output)
+                )
+            );
+        }
+        if let Some(crf) = self.crf {
+            if crf > 51 {
+                return Err(
+                    MyError::InvalidInput(format!("crf {} is out of range (0-51)", crf))
+                );
+            }
+            args.crf = Some(crf);
+            args.video_bitrate = None;
+        }
+        if let Some(video_bitrate) = &self.video_bitrate {
+            args.video_bitrate = Some(video_bitrate.clone());
+            args.crf = None;
+        }
+        if let Some(preset) = &self.preset {
+            args.preset = value_enum::<Preset>(preset, "preset")?;
+        }
+        if let Some(crossfade) = self.crossfade {
+            args.crossfade = checked(crossfade, parse_crossfade)?;
+        }
+        if let Some(normalize) = self.normalize {
+            args.normalize = normalize;
+        }
+        if let Some(target_lufs) = self.target_lufs {
+            args.target_lufs = target_lufs;
+        }
+        if let Some(fade_in) = self.fade_in {
+            args.fade_in = checked(fade_in, parse_fade)?;
+        }
+        if let Some(fade_out) = self.fade_out {
+            args.fade_out = checked(fade_out, parse_fade)?;
+        }
+        if let Some(audio_only) = self.audio_only {
+            args.audio_only = audio_only;
+        }
+        if let Some(chapters) = self.chapters {
+            args.chapters = chapters;
+        }
+        if let Some(label_clips) = self.label_clips {
+            args.label_clips = label_clips;
+        }
+        if let Some(overwrite) = self.overwrite {
+            args.overwrite = overwrite;
+        }
+        Ok(args)
+    }
+}
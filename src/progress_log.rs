@@ -0,0 +1,101 @@
+use std::fs::{ File, OpenOptions };
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{ SystemTime, UNIX_EPOCH };
+use serde::Serialize;
+
+use crate::MyError;
+
+// Set by --log-file, every pipeline step appends a line while it runs
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+
+// One JSON object per line, written as soon as it happens so a crash leaves a trail
+#[derive(Debug, Serialize)]
+struct Entry<'a> {
+    timestamp: String,
+    stage: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    input: Option<String>,
+    status: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+pub fn open(path: &Path) -> Result<(), MyError> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+pub fn record(stage: &str, input: Option<&Path>, status: &str, message: Option<String>) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    let Some(file) = log_file.as_mut() else {
+        return;
+    };
+    let entry = Entry {
+        timestamp: timestamp(SystemTime::now()),
+        stage,
+        input: input.map(|input| input.display().to_string()),
+        status,
+        message,
+    };
+    let Ok(mut line) = serde_json::to_string(&entry) else {
+        return;
+    };
+    line.push('\n');
+    // Losing a log line must not fail the job it describes
+    if let Err(e) = file.write_all(line.as_bytes()) {
+        verbose!("Failed to write to the log file: {}", e);
+    }
+}
+
+// Runs one pipeline step between a `started` and a `done` or `failed` line
+pub fn step<T>(
+    stage: &str,
+    input: Option<&Path>,
+    run: impl FnOnce() -> Result<T, MyError>
+) -> Result<T, MyError> {
+    record(stage, input, "started", None);
+    let result = run();
+    match &result {
+        Ok(_) => record(stage, input, "done", None),
+        Err(MyError::Cancelled) => record(stage, input, "cancelled", None),
+        Err(e) => record(stage, input, "failed", Some(e.to_string())),
+    }
+    result
+}
+
+// RFC 3339 in UTC, e.g. `2024-01-07T04:45:38Z`
+pub fn timestamp(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86_400) as i64);
+    let seconds_of_day = seconds % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds_of_day / 3600,
+        (seconds_of_day % 3600) / 60,
+        seconds_of_day % 60
+    )
+}
+
+// Days since 1970-01-01 to a calendar date, Howard Hinnant's algorithm
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = (if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 }) as u32;
+    let year = year_of_era + era * 400 + (if month <= 2 { 1 } else { 0 });
+    (year, month, day)
+}
@@ -0,0 +1,163 @@
+use std::collections::{ BTreeMap, HashMap, HashSet };
+use std::ffi::{ OsStr, OsString };
+use std::path::{ Path, PathBuf };
+use std::sync::mpsc::{ self, RecvTimeoutError };
+use std::time::{ Duration, Instant };
+use notify::{ RecursiveMode, Watcher };
+
+use crate::{ clip_extension, natural_sort, MyError };
+
+// How long a file has to keep its size before it counts as fully written
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub struct WatchSettings {
+    pub dir: PathBuf,
+    pub output_dir: PathBuf,
+    pub pattern: glob::Pattern,
+    // A set is combined once this many of its clips are written
+    pub clips_per_set: usize,
+}
+
+// A file that changed recently and may still be written to
+struct PendingFile {
+    size: u64,
+    changed_at: Instant,
+}
+
+// Clips belong to the set named by their file name up to the last `_`,
+// e.g. `stream_1.mkv` and `stream_2.mkv` form the set `stream`
+pub fn set_name(path: &Path) -> Option<OsString> {
+    Some(strip_clip_number(path.file_stem()?))
+}
+
+// Cut on the bytes, so names that aren't UTF-8 still name their output right
+#[cfg(unix)]
+fn strip_clip_number(stem: &OsStr) -> OsString {
+    use std::os::unix::ffi::OsStrExt;
+    let bytes = stem.as_bytes();
+    let end = bytes.iter().rposition(|byte| *byte == b'_').unwrap_or(bytes.len());
+    OsStr::from_bytes(&bytes[..end]).to_os_string()
+}
+
+// Windows names are UTF-16, only unpaired surrogates get lost here
+#[cfg(not(unix))]
+fn strip_clip_number(stem: &OsStr) -> OsString {
+    let stem = stem.to_string_lossy();
+    OsString::from(stem.rsplit_once('_').map_or(stem.as_ref(), |(name, _)| name))
+}
+
+fn file_size(path: &Path) -> Option<u64> {
+    std::fs::metadata(path).ok().map(|metadata| metadata.len())
+}
+
+// Blocks until interrupted, calling `combine` with the clips and the output path of every
+// complete set. Sets whose output already exists are skipped, so restarting the watcher
+// doesn't redo earlier work.
+pub fn watch_directory(
+    settings: &WatchSettings,
+    mut combine: impl FnMut(Vec<PathBuf>, PathBuf) -> Result<(), MyError>
+) -> Result<(), MyError> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify
+        ::recommended_watcher(sender)
+        .map_err(|e| MyError::Other(format!("Failed to watch {}: {}", settings.dir.display(), e)))?;
+    watcher
+        .watch(&settings.dir, RecursiveMode::NonRecursive)
+        .map_err(|e| MyError::Other(format!("Failed to watch {}: {}", settings.dir.display(), e)))?;
+
+    let is_clip = |path: &Path| {
+        path.parent() == Some(settings.dir.as_path()) &&
+            path.file_name().is_some_and(|name| settings.pattern.matches(&name.to_string_lossy()))
+    };
+
+    // Clips that were already there are treated like new ones
+    let mut pending: HashMap<PathBuf, PendingFile> = HashMap::new();
+    for entry in std::fs::read_dir(&settings.dir)? {
+        let path = entry?.path();
+        if is_clip(&path) {
+            let size = file_size(&path).unwrap_or(0);
+            pending.insert(path, PendingFile { size, changed_at: Instant::now() });
+        }
+    }
+    let mut sets: BTreeMap<OsString, Vec<PathBuf>> = BTreeMap::new();
+    let mut processed: HashSet<OsString> = HashSet::new();
+
+    status!("Watching {} for {}", settings.dir.display(), settings.pattern);
+    loop {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                for path in event.paths.into_iter().filter(|path| is_clip(path)) {
+                    if event.kind.is_remove() {
+                        pending.remove(&path);
+                        continue;
+                    }
+                    let size = file_size(&path).unwrap_or(0);
+                    pending.insert(path, PendingFile { size, changed_at: Instant::now() });
+                }
+            }
+            Ok(Err(e)) => status!("Watch error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(MyError::Other("The directory watcher stopped".to_string()));
+            }
+        }
+
+        // Debounce: a clip is done once its size stopped changing for a while
+        let mut settled: Vec<PathBuf> = Vec::new();
+        for (path, file) in pending.iter_mut() {
+            if file.changed_at.elapsed() < SETTLE_TIME {
+                continue;
+            }
+            match file_size(path) {
+                Some(size) if size == file.size && size > 0 => settled.push(path.clone()),
+                Some(size) => {
+                    file.size = size;
+                    file.changed_at = Instant::now();
+                }
+                // Deleted before it settled
+                None => settled.push(path.clone()),
+            }
+        }
+        for path in settled {
+            pending.remove(&path);
+            if !path.exists() {
+                continue;
+            }
+            if let Some(name) = set_name(&path) {
+                let clips = sets.entry(name).or_default();
+                if !clips.contains(&path) {
+                    clips.push(path);
+                }
+            }
+        }
+
+        let complete: Vec<OsString> = sets
+            .iter()
+            .filter(|(name, clips)| {
+                clips.len() >= settings.clips_per_set && !processed.contains(*name)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in complete {
+            processed.insert(name.clone());
+            let mut clips = sets.remove(&name).unwrap_or_default();
+            natural_sort(&mut clips);
+            let mut file_name = name.clone();
+            file_name.push(".");
+            file_name.push(clip_extension(&clips[0]));
+            let output = settings.output_dir.join(file_name);
+            let name = name.to_string_lossy();
+            if output.exists() {
+                verbose!("Skipping {}, {} already exists", name, output.display());
+                continue;
+            }
+
+            status!("Combining {} clips of {} into {}", clips.len(), name, output.display());
+            // A failed set is reported and not retried, the watcher keeps going
+            if let Err(e) = combine(clips, output) {
+                status!("Failed to combine {}: {}", name, e);
+            }
+        }
+    }
+}